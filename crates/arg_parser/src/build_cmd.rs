@@ -2,8 +2,8 @@ use crate::{
     arg_path::ArgPath, input_args::InputArgs, lua_args::LuaArgs, output_args::OutputArgs,
     resource_limit::ResourceLimit,
 };
-use clap::Parser;
-use emblem_core::context::Iteration;
+use clap::{Parser, ValueHint::AnyPath};
+use emblem_core::{context::Iteration, ProfileOutput, Reiteration};
 
 /// Arguments to the build subcommand
 #[derive(Clone, Debug, Default, Parser, PartialEq, Eq)]
@@ -24,6 +24,14 @@ pub struct BuildCmd {
     /// Max iterations of the typesetting loop
     #[arg(long, value_parser = ResourceLimit::<Iteration>::parser(), default_value_t, value_name = "max")]
     pub max_iters: ResourceLimit<Iteration>,
+
+    /// Keep iterating until the document stops changing, up to the maximum iterations
+    #[arg(long)]
+    pub until_converged: bool,
+
+    /// Profile extensions, printing a summary or writing collapsed stacks to the given file
+    #[arg(long, require_equals = true, value_name = "file", value_hint = AnyPath)]
+    pub profile_extensions: Option<Option<String>>,
 }
 
 impl BuildCmd {
@@ -31,16 +39,29 @@ impl BuildCmd {
     pub fn output_stem(&self) -> ArgPath {
         self.output.stem.infer_from(&self.input.file)
     }
+
+    pub fn reiteration(&self) -> Reiteration {
+        if self.until_converged {
+            Reiteration::UntilConverged
+        } else {
+            Reiteration::OnRequest
+        }
+    }
 }
 
 impl From<&BuildCmd> for emblem_core::Builder {
     fn from(cmd: &BuildCmd) -> Self {
         let output_stem = cmd.output_stem().into();
-        emblem_core::Builder::new(
+        let builder = emblem_core::Builder::new(
             cmd.input.file.clone().into(),
             output_stem,
             cmd.output.driver.clone(),
-        )
+        );
+        match &cmd.profile_extensions {
+            None => builder,
+            Some(None) => builder.with_profile(ProfileOutput::Summary),
+            Some(Some(path)) => builder.with_profile(ProfileOutput::CollapsedStacks(path.into())),
+        }
     }
 }
 
@@ -48,7 +69,7 @@ impl From<&BuildCmd> for emblem_core::Builder {
 mod test {
     use super::*;
     use crate::{sandbox_level::SandboxLevel, Args};
-    use emblem_core::context::{Memory, Resource, Step};
+    use emblem_core::context::{Memory, Resource, Step, Time};
 
     #[test]
     fn output_driver() {
@@ -176,7 +197,7 @@ mod test {
                 .unwrap()
                 .lua
                 .max_mem,
-            ResourceLimit::Limited(Resource::default_limit()),
+            ResourceLimit::Limited(Resource::default_limit().unwrap()),
         );
         assert_eq!(
             Args::try_parse_from(["em", "build", "--max-mem", "25"])
@@ -245,7 +266,7 @@ mod test {
                 .unwrap()
                 .lua
                 .max_steps,
-            ResourceLimit::Limited(Resource::default_limit()),
+            ResourceLimit::Limited(Resource::default_limit().unwrap()),
         );
         assert_eq!(
             Args::try_parse_from(["em", "build", "--max-steps", "25"])
@@ -321,6 +342,86 @@ mod test {
         );
     }
 
+    #[test]
+    fn max_time() {
+        assert_eq!(
+            Args::try_parse_from(["em"])
+                .unwrap()
+                .command
+                .build()
+                .unwrap()
+                .lua
+                .max_time,
+            ResourceLimit::Unlimited,
+        );
+        for (raw, expected) in [
+            ("25", Time(25)),
+            ("25s", Time(25)),
+            ("25m", Time(25 * 60)),
+            ("25h", Time(25 * 60 * 60)),
+        ] {
+            assert_eq!(
+                Args::try_parse_from(["em", "build", "--max-time", raw])
+                    .unwrap()
+                    .command
+                    .build()
+                    .unwrap()
+                    .lua
+                    .max_time,
+                ResourceLimit::Limited(expected)
+            );
+        }
+        assert_eq!(
+            Args::try_parse_from(["em", "build", "--max-time", "unlimited"])
+                .unwrap()
+                .command
+                .build()
+                .unwrap()
+                .lua
+                .max_time,
+            ResourceLimit::Unlimited,
+        );
+
+        {
+            let err =
+                Args::try_parse_from(["em", "build", "--max-time", &format!("{}h", u32::MAX)])
+                    .unwrap_err()
+                    .to_string();
+            assert!(err.contains("invalid value"), "unexpected error: {err:#?}",);
+        }
+
+        assert!(&Args::try_parse_from(["em", "build", "--max-time", "100K"])
+            .unwrap_err()
+            .to_string()
+            .contains("unrecognised unit: K"));
+    }
+
+    #[test]
+    fn profile_extensions() {
+        let profile_extensions = |args: &[&str]| {
+            Args::try_parse_from(args)
+                .unwrap()
+                .command
+                .build()
+                .unwrap()
+                .profile_extensions
+                .clone()
+        };
+        assert_eq!(profile_extensions(&["em"]), None);
+        assert_eq!(
+            profile_extensions(&["em", "build", "--profile-extensions"]),
+            Some(None)
+        );
+        assert_eq!(
+            profile_extensions(&["em", "build", "--profile-extensions", "main.em"]),
+            Some(None)
+        );
+        assert_eq!(
+            profile_extensions(&["em", "build", "--profile-extensions=prof.folded"]),
+            Some(Some("prof.folded".into()))
+        );
+    }
+
     #[test]
     fn sandbox_level() {
         assert_eq!(
@@ -401,6 +502,27 @@ mod test {
         assert!(Args::try_parse_from(["em", "-a=v"]).is_err());
     }
 
+    #[test]
+    fn until_converged() {
+        let cmd = Args::try_parse_from(["em", "build"])
+            .unwrap()
+            .command
+            .build()
+            .unwrap()
+            .clone();
+        assert!(!cmd.until_converged);
+        assert_eq!(cmd.reiteration(), Reiteration::OnRequest);
+
+        let cmd = Args::try_parse_from(["em", "build", "--until-converged"])
+            .unwrap()
+            .command
+            .build()
+            .unwrap()
+            .clone();
+        assert!(cmd.until_converged);
+        assert_eq!(cmd.reiteration(), Reiteration::UntilConverged);
+    }
+
     #[test]
     fn max_iters() {
         assert_eq!(
@@ -410,7 +532,7 @@ mod test {
                 .build()
                 .unwrap()
                 .max_iters,
-            ResourceLimit::Limited(Resource::default_limit()),
+            ResourceLimit::Limited(Resource::default_limit().unwrap()),
         );
         assert_eq!(
             Args::try_parse_from(["em", "build", "--max-iters", "25"])
@@ -431,4 +553,35 @@ mod test {
             ResourceLimit::Unlimited,
         );
     }
+
+    #[test]
+    fn include_dirs() {
+        assert_eq!(
+            Args::try_parse_from(["em", "build"])
+                .unwrap()
+                .command
+                .build()
+                .unwrap()
+                .input
+                .include_dirs,
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            Args::try_parse_from([
+                "em",
+                "build",
+                "-I",
+                "../shared",
+                "--include-dir",
+                "/opt/assets"
+            ])
+            .unwrap()
+            .command
+            .build()
+            .unwrap()
+            .input
+            .include_dirs,
+            vec!["../shared", "/opt/assets"],
+        );
+    }
 }
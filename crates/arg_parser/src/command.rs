@@ -1,6 +1,6 @@
 use crate::{
     add_cmd::AddCmd, build_cmd::BuildCmd, explain_cmd::ExplainCmd, format_cmd::FormatCmd,
-    init_cmd::InitCmd, lint_cmd::LintCmd, list_cmd::ListCmd, lua_args::LuaArgs,
+    init_cmd::InitCmd, lint_cmd::LintCmd, list_cmd::ListCmd, lua_args::LuaArgs, test_cmd::TestCmd,
 };
 use clap::Subcommand;
 
@@ -29,6 +29,9 @@ pub enum Command {
 
     /// Print info and exit
     List(ListCmd),
+
+    /// Run the tests in the specs of the document's local modules
+    Test(TestCmd),
 }
 
 impl Command {
@@ -41,6 +44,16 @@ impl Command {
             Self::Init(_) => None,
             Self::Lint(cmd) => Some(&cmd.lua),
             Self::List(cmd) => Some(&cmd.lua),
+            Self::Test(cmd) => Some(&cmd.lua),
+        }
+    }
+
+    pub fn include_dirs(&self) -> &[String] {
+        match self {
+            Self::Build(cmd) => &cmd.input.include_dirs,
+            Self::Lint(cmd) => &cmd.input.include_dirs,
+            Self::Test(cmd) => &cmd.input.include_dirs,
+            _ => &[],
         }
     }
 }
@@ -94,6 +107,13 @@ impl Command {
             _ => None,
         }
     }
+
+    pub(crate) fn test(&self) -> Option<&TestCmd> {
+        match self {
+            Self::Test(t) => Some(t),
+            _ => None,
+        }
+    }
 }
 
 impl Default for Command {
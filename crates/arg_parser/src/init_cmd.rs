@@ -1,4 +1,4 @@
-use clap::{Parser, ValueHint::DirPath};
+use clap::{Parser, ValueEnum, ValueHint::DirPath};
 
 /// Arguments to the init subcommand
 #[derive(Clone, Debug, Parser, PartialEq, Eq)]
@@ -7,10 +7,31 @@ pub struct InitCmd {
     /// Directory to contain the new document
     #[arg(value_name = "dir", value_hint = DirPath, default_value = ".")]
     pub dir: String,
+
+    /// Create the document from a template: one of article, report or slides, a directory or a
+    /// git repository
+    #[arg(long, value_name = "template")]
+    pub template: Option<String>,
+
+    /// Version control system to use for the new document
+    #[arg(long, value_enum, default_value_t, value_name = "vcs")]
+    pub vcs: Vcs,
+}
+
+/// Version control system to set up for a new document
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Vcs {
+    /// Do not use version control
+    None,
+
+    /// Create a git repository, unless already inside one
+    #[default]
+    Git,
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
     use crate::Args;
 
     #[test]
@@ -34,4 +55,58 @@ mod test {
             "cool-doc",
         );
     }
+
+    #[test]
+    fn template() {
+        assert_eq!(
+            Args::try_parse_from(["em", "init"])
+                .unwrap()
+                .command
+                .init()
+                .unwrap()
+                .template,
+            None,
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "init", "--template", "slides"])
+                .unwrap()
+                .command
+                .init()
+                .unwrap()
+                .template,
+            Some("slides".into()),
+        );
+    }
+
+    #[test]
+    fn vcs() {
+        assert_eq!(
+            Args::try_parse_from(["em", "init"])
+                .unwrap()
+                .command
+                .init()
+                .unwrap()
+                .vcs,
+            Vcs::Git,
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "init", "--vcs", "none"])
+                .unwrap()
+                .command
+                .init()
+                .unwrap()
+                .vcs,
+            Vcs::None,
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "init", "--vcs", "git"])
+                .unwrap()
+                .command
+                .init()
+                .unwrap()
+                .vcs,
+            Vcs::Git,
+        );
+        assert!(Args::try_parse_from(["em", "init", "--vcs", "svn"]).is_err());
+    }
 }
@@ -1,5 +1,8 @@
 use crate::arg_path::ArgPath;
-use clap::{Parser, ValueHint::FilePath};
+use clap::{
+    Parser,
+    ValueHint::{DirPath, FilePath},
+};
 
 /// Holds the source of the user's document
 #[derive(Clone, Debug, Default, Parser, PartialEq, Eq)]
@@ -8,4 +11,8 @@ pub struct InputArgs {
     /// Document to typeset
     #[arg(value_name = "in-file", value_hint = FilePath, default_value_t = ArgPath::default(), value_parser = ArgPath::parser())]
     pub file: ArgPath,
+
+    /// Add a directory to search for files included with .include
+    #[arg(short = 'I', long = "include-dir", value_name = "dir", value_hint = DirPath)]
+    pub include_dirs: Vec<String>,
 }
@@ -14,14 +14,16 @@ mod lua_args;
 mod output_args;
 mod resource_limit;
 mod sandbox_level;
+mod test_cmd;
 
 pub use crate::add_cmd::AddCmd;
 pub use crate::build_cmd::BuildCmd;
 pub use crate::explain_cmd::ExplainCmd;
 pub use crate::format_cmd::FormatCmd;
-pub use crate::init_cmd::InitCmd;
+pub use crate::init_cmd::{InitCmd, Vcs};
 pub use crate::lint_cmd::LintCmd;
-pub use crate::list_cmd::ListCmd;
+pub use crate::list_cmd::{ListCmd, RequestedInfo};
+pub use crate::test_cmd::TestCmd;
 pub use command::Command;
 pub use input_args::InputArgs;
 pub use log_args::LogArgs;
@@ -68,6 +70,10 @@ impl Args {
     pub fn lua_args(&self) -> Option<&LuaArgs> {
         self.command.lua_args()
     }
+
+    pub fn include_dirs(&self) -> &[String] {
+        self.command.include_dirs()
+    }
 }
 
 impl TryFrom<RawArgs> for Args {
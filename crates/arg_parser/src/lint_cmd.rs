@@ -63,6 +63,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn include_dirs() {
+        assert_eq!(
+            Args::try_parse_from(["em", "lint"]).unwrap().include_dirs(),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "lint", "-I", "../shared"])
+                .unwrap()
+                .include_dirs(),
+            ["../shared"],
+        );
+    }
+
     #[test]
     fn module_args() {
         assert_eq!(
@@ -1,6 +1,6 @@
 use crate::{ext_arg::ExtArg, resource_limit::ResourceLimit, sandbox_level::SandboxLevel};
 use clap::{ArgAction::Append, Parser};
-use emblem_core::context::{Memory, Step};
+use emblem_core::context::{Memory, Step, Time};
 
 /// Holds the user's preferences for the lua environment used when running the program
 #[derive(Clone, Debug, Default, Parser, PartialEq, Eq)]
@@ -15,10 +15,18 @@ pub struct LuaArgs {
     #[arg(long, value_parser = ResourceLimit::<Memory>::parser(), default_value_t, value_name = "amount")]
     pub max_mem: ResourceLimit<Memory>,
 
-    /// Limit lua execution steps
+    /// Limit lua execution steps, counting each loop iteration as a step
     #[arg(long, value_parser = ResourceLimit::<Step>::parser(), default_value_t, value_name = "steps")]
     pub max_steps: ResourceLimit<Step>,
 
+    /// Limit lua execution time
+    ///
+    /// Setting a limit turns off LuaJIT's compiler so that the limit can be checked as extensions
+    /// run, which makes extensions slower. The limit is only checked while lua code is running,
+    /// so a call into C which blocks, such as a read from a pipe, is not interrupted.
+    #[arg(long, value_parser = ResourceLimit::<Time>::parser(), default_value_t, value_name = "time")]
+    pub max_time: ResourceLimit<Time>,
+
     /// Restrict system access
     #[arg(long = "sandbox", value_enum, default_value_t, value_name = "level")]
     pub sandbox_level: SandboxLevel,
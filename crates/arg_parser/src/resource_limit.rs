@@ -70,6 +70,6 @@ impl<T: Resource> From<ResourceLimit<T>> for EmblemResourceLimit<T> {
 
 impl<T: Resource> Default for ResourceLimit<T> {
     fn default() -> Self {
-        Self::Limited(T::default_limit())
+        T::default_limit().map_or(Self::Unlimited, Self::Limited)
     }
 }
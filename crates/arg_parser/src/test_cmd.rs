@@ -0,0 +1,107 @@
+use crate::{input_args::InputArgs, lua_args::LuaArgs};
+use clap::Parser;
+use emblem_core::Tester as EmblemTester;
+
+/// Arguments to the test subcommand
+#[derive(Clone, Debug, Parser, PartialEq, Eq)]
+#[warn(missing_docs)]
+pub struct TestCmd {
+    #[command(flatten)]
+    #[allow(missing_docs)]
+    pub input: InputArgs,
+
+    /// Only run tests whose names contain this text
+    #[arg(long, value_name = "text")]
+    pub filter: Option<String>,
+
+    #[command(flatten)]
+    #[allow(missing_docs)]
+    pub lua: LuaArgs,
+}
+
+impl From<&TestCmd> for EmblemTester {
+    fn from(cmd: &TestCmd) -> Self {
+        Self::new(cmd.input.file.clone().into(), cmd.filter.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{arg_path::ArgPath, Args};
+
+    #[test]
+    fn input_file() {
+        assert_eq!(
+            Args::try_parse_from(["em", "test"])
+                .unwrap()
+                .command
+                .test()
+                .unwrap()
+                .input
+                .file,
+            ArgPath::Path("main.em".into())
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "test", "manual.em"])
+                .unwrap()
+                .command
+                .test()
+                .unwrap()
+                .input
+                .file,
+            ArgPath::Path("manual.em".into())
+        );
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(
+            Args::try_parse_from(["em", "test"])
+                .unwrap()
+                .command
+                .test()
+                .unwrap()
+                .filter,
+            None
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "test", "--filter", "figures"])
+                .unwrap()
+                .command
+                .test()
+                .unwrap()
+                .filter
+                .as_deref(),
+            Some("figures")
+        );
+    }
+
+    #[test]
+    fn include_dirs() {
+        assert_eq!(
+            Args::try_parse_from(["em", "test"]).unwrap().include_dirs(),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            Args::try_parse_from(["em", "test", "-I", "../shared"])
+                .unwrap()
+                .include_dirs(),
+            ["../shared"],
+        );
+    }
+
+    #[test]
+    fn module_args() {
+        assert_eq!(
+            Args::try_parse_from(["em", "test", "-afigures.numbered=false"])
+                .unwrap()
+                .command
+                .test()
+                .unwrap()
+                .lua
+                .args
+                .len(),
+            1
+        );
+    }
+}
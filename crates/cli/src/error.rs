@@ -94,8 +94,25 @@ enum ErrorImpl {
 }
 
 impl From<Error> for Log {
-    fn from(_error: Error) -> Self {
-        unimplemented!()
+    fn from(error: Error) -> Self {
+        match *error.0 {
+            ErrorImpl::EmblemCore(e) => e.into(),
+            ErrorImpl::WithContext { context, cause } => {
+                let log = Log::from(cause);
+                let mut ret = Log::error(format!("{context}: {}", log.msg()));
+                if let Some(infos) = log.infos() {
+                    ret = ret.with_info(infos.to_vec());
+                }
+                if let Some(help) = log.help() {
+                    ret = ret.with_help(help);
+                }
+                for src in log.srcs() {
+                    ret = ret.with_src(src.clone());
+                }
+                ret
+            }
+            error => Log::error(error.to_string()),
+        }
     }
 }
 
@@ -144,4 +161,20 @@ mod tests {
             "unused arguments: hello, world"
         );
     }
+
+    #[test]
+    fn into_log() {
+        let log = Log::from(Error::manifest_invalid("lmao").context("cannot load emblem.toml"));
+        assert_eq!(log.msg(), "cannot load emblem.toml: manifest invalid: lmao");
+
+        let log = Log::from(Error::from(emblem_core::Error::not_found_along_path(
+            "chapter.em",
+            vec!["foo".into(), "bar".into()],
+        )));
+        assert_eq!(log.msg(), "could not find chapter.em");
+        assert_eq!(
+            log.infos(),
+            Some(&["looked in foo".to_owned(), "looked in bar".to_owned()][..])
+        );
+    }
 }
@@ -1,11 +1,19 @@
-use crate::{Context, Error, Result};
-use arg_parser::InitCmd;
+mod template;
+
+use crate::{manifest::DocManifest, Context, Error, Result};
+use arg_parser::{InitCmd, Vcs};
 use camino::{Utf8Path, Utf8PathBuf};
 use derive_new::new;
-use emblem_core::log::Logger;
-use git2::{Repository, RepositoryInitOptions};
+use emblem_core::{log::Logger, Log};
+use git2::{Config, ErrorCode, Repository, RepositoryInitOptions};
 use std::io::ErrorKind;
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+};
+use template::{Instance, Template};
+use toml_edit::{Document, Item};
 
 static MAIN_CONTENTS: &str = r#"
 # Welcome! Welcome to Emblem.
@@ -21,10 +29,16 @@ static GITIGNORE_CONTENTS: &str = r#"
 #[derive(new)]
 pub struct Initialiser<T: AsRef<Utf8Path>> {
     dir: T,
+
+    #[new(default)]
+    template: Option<String>,
+
+    #[new(value = "Vcs::Git")]
+    vcs: Vcs,
 }
 
 impl<T: AsRef<Utf8Path>> Initialiser<T> {
-    pub fn run<L: Logger>(&self, _: &mut Context<L>) -> Result<()> {
+    pub fn run<L: Logger>(&self, ctx: &mut Context<L>) -> Result<()> {
         let p;
         let dir = {
             if !self.dir.as_ref().is_absolute() && !self.dir.as_ref().starts_with("./") {
@@ -39,28 +53,115 @@ impl<T: AsRef<Utf8Path>> Initialiser<T> {
         let main_file = dir.join("main.em");
         let manifest_file = dir.join("emblem.toml");
 
-        self.init_repo()?;
+        let mut repo_created = false;
+        if self.vcs == Vcs::Git {
+            if let Some(enclosing) = self.enclosing_repo()? {
+                ctx.print(Log::info(format!(
+                    "{dir} is already inside a git repository at {enclosing}, not creating another"
+                )))?;
+            } else {
+                self.init_repo()?;
+                repo_created = true;
+            }
+        }
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+
+        let instance = match &self.template {
+            Some(template) => {
+                let name = self.name();
+                let author = self.author();
+                Template::resolve(template)?
+                    .instantiate(dir, &[("name", name), ("author", &author)])?
+            }
+            None => Instance::default(),
+        };
+        if !instance.skipped.is_empty() {
+            ctx.print(
+                Log::warning("template contains symbolic links, which were not copied").with_info(
+                    instance
+                        .skipped
+                        .iter()
+                        .map(|path| format!("skipped {path}"))
+                        .collect(),
+                ),
+            )?;
+        }
 
-        self.try_create_file(&git_ignore, GITIGNORE_CONTENTS)?;
-        self.try_create_file(&main_file, MAIN_CONTENTS)?;
-        self.try_create_file(&manifest_file, &self.generate_manifest()?)?;
+        let mut untouched = instance.untouched;
+        if repo_created && !instance.created.contains(&git_ignore) {
+            self.try_create_file(&git_ignore, GITIGNORE_CONTENTS, &mut untouched)?;
+        }
+        if !instance.created.contains(&main_file) {
+            self.try_create_file(&main_file, MAIN_CONTENTS, &mut untouched)?;
+        }
+        let has_requires = instance.requires.is_some();
+        let manifest_created = instance.created.contains(&manifest_file)
+            || self.try_create_file(
+                &manifest_file,
+                &self.generate_manifest(instance.requires)?,
+                &mut untouched,
+            )?;
+        untouched.sort();
+        untouched.dedup();
+
+        if !untouched.is_empty() {
+            let mut log = Log::warning("some files already exist, leaving them untouched")
+                .with_info(
+                    untouched
+                        .iter()
+                        .map(|path| format!("did not overwrite {path}"))
+                        .collect(),
+                );
+            if has_requires && !manifest_created {
+                log = log.with_help(format!(
+                    "add the template's requirements to {manifest_file} by hand"
+                ));
+            }
+            ctx.print(log)?;
+        }
 
         Ok(())
     }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    pub fn with_vcs(mut self, vcs: Vcs) -> Self {
+        self.vcs = vcs;
+        self
+    }
 }
 
 impl From<&InitCmd> for Initialiser<Utf8PathBuf> {
     fn from(cmd: &InitCmd) -> Self {
-        Self::new(Utf8PathBuf::from(cmd.dir.clone()))
+        let initialiser = Self::new(Utf8PathBuf::from(cmd.dir.clone())).with_vcs(cmd.vcs);
+        match &cmd.template {
+            Some(template) => initialiser.with_template(template),
+            None => initialiser,
+        }
     }
 }
 
 impl<T: AsRef<Utf8Path>> Initialiser<T> {
-    /// Construct the contents of the manifest file
-    fn generate_manifest(&self) -> Result<String> {
-        let name = self.dir.as_ref().file_name().unwrap_or("emblem-document");
+    /// The name of the new document
+    fn name(&self) -> &str {
+        self.dir.as_ref().file_name().unwrap_or("emblem-document")
+    }
 
-        Ok(indoc::formatdoc!(
+    /// The name of the author of the new document, as best as can be determined
+    fn author(&self) -> String {
+        Config::open_default()
+            .and_then(|config| config.get_string("user.name"))
+            .ok()
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_else(|| "Anonymous".into())
+    }
+
+    /// Construct the contents of the manifest file, including any default requirements
+    fn generate_manifest(&self, requires: Option<Item>) -> Result<String> {
+        let mut manifest = indoc::formatdoc!(
             r#"
                 [document]
                 name = "{}"
@@ -68,21 +169,70 @@ impl<T: AsRef<Utf8Path>> Initialiser<T> {
 
                 # Use `em add <package>` to make <package> available to this document
             "#,
-            name.replace('"', r#"\""#),
-        ))
+            self.name().replace('"', r#"\""#),
+        );
+
+        if let Some(requires) = requires {
+            let mut extra = Document::new();
+            extra.insert("requires", requires);
+            manifest.push('\n');
+            manifest.push_str(&extra.to_string());
+
+            DocManifest::try_from(&manifest[..])
+                .map_err(|e| e.context("template has invalid requirements"))?;
+        }
+
+        Ok(manifest)
     }
 
-    /// Try to create a new file with given contents. Optionally skip if file is already present.
-    fn try_create_file(&self, path: &Utf8Path, contents: &str) -> Result<()> {
+    /// Try to create a new file with given contents. If the file is already present, it is left
+    /// untouched and recorded. Returns whether the file was created.
+    fn try_create_file(
+        &self,
+        path: &Utf8Path,
+        contents: &str,
+        untouched: &mut Vec<Utf8PathBuf>,
+    ) -> Result<bool> {
         match OpenOptions::new().write(true).create_new(true).open(path) {
             Ok(mut file) => {
-                Ok(writeln!(file, "{}", contents.trim()).map_err(|e| Error::io(path, e))?)
+                writeln!(file, "{}", contents.trim()).map_err(|e| Error::io(path, e))?;
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                untouched.push(path.to_owned());
+                Ok(false)
             }
-            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(()),
             Err(e) => Err(Error::io(path, e)),
         }
     }
 
+    /// Find the root of any repository which already contains the target directory.
+    fn enclosing_repo(&self) -> Result<Option<Utf8PathBuf>> {
+        let Some(existing) = self
+            .dir
+            .as_ref()
+            .ancestors()
+            .map(|dir| {
+                if dir.as_str().is_empty() {
+                    Utf8Path::new(".")
+                } else {
+                    dir
+                }
+            })
+            .find(|dir| dir.exists())
+        else {
+            return Ok(None);
+        };
+
+        let repo = match Repository::discover(existing) {
+            Ok(repo) => repo,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let root = repo.workdir().unwrap_or_else(|| repo.path());
+        Ok(Some(Utf8PathBuf::from(root.to_string_lossy().as_ref())))
+    }
+
     /// Create a new code repository at the given path.
     fn init_repo(&self) -> Result<Repository> {
         Ok(Repository::init_opts(
@@ -97,7 +247,7 @@ mod test {
     use super::*;
     use crate::Result;
     use crate::{manifest::DocManifest, Error};
-    use emblem_core::parser;
+    use emblem_core::{log::MessageType, parser};
     use std::{
         fs::{self, File},
         io::{BufRead, BufReader},
@@ -245,4 +395,300 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn builtin_templates() -> Result<()> {
+        for name in Template::builtin_names() {
+            let tmpdir = tempfile::tempdir().unwrap();
+            let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+            let mut ctx = Context::test_new();
+            Initialiser::new(&tmpdir_path)
+                .with_template(name)
+                .run(&mut ctx)?;
+
+            let main_file = tmpdir_path.join("main.em");
+            let content = fs::read_to_string(&main_file).map_err(|e| Error::io(&main_file, e))?;
+            assert!(
+                content.starts_with(&format!("# {}\n", tmpdir_path.file_name().unwrap())),
+                "{name}: name not substituted in {content:?}"
+            );
+            assert!(
+                !content.contains("{{"),
+                "{name}: unsubstituted variables in {content:?}"
+            );
+            assert!(parser::parse(
+                ctx.alloc_file_name(main_file.as_str()),
+                ctx.alloc_file_content(&content)
+            )
+            .is_ok());
+
+            let logs = ctx.logger().logs().to_vec();
+            assert!(
+                logs.iter()
+                    .all(|log| log.msg_type() != MessageType::Warning),
+                "{name}: unexpected warnings in {logs:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn make_template(dir: &Utf8Path) -> Result<()> {
+        let files = [
+            ("main.em", "# {{name}}\n\n.include{chapters/one.em}\n"),
+            ("chapters/one.em", "Written by {{author}}\n"),
+            (
+                "template.toml",
+                indoc::indoc!(
+                    r#"
+                        [requires.citations]
+                        tag = "v1.0"
+                        args = { style = "harvard" }
+                    "#
+                ),
+            ),
+        ];
+        for (path, content) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).map_err(|e| Error::io(&path, e))?;
+            fs::write(&path, content).map_err(|e| Error::io(&path, e))?;
+        }
+        Ok(())
+    }
+
+    fn check_templated(dir: &Utf8Path) -> Result<()> {
+        let main_file = dir.join("main.em");
+        let main_content = fs::read_to_string(&main_file).map_err(|e| Error::io(&main_file, e))?;
+        assert_eq!(
+            format!(
+                "# {}\n\n.include{{chapters/one.em}}\n",
+                dir.file_name().unwrap()
+            ),
+            main_content
+        );
+
+        let chapter_file = dir.join("chapters/one.em");
+        let chapter_content =
+            fs::read_to_string(&chapter_file).map_err(|e| Error::io(&chapter_file, e))?;
+        assert!(!chapter_content.contains("{{author}}"));
+
+        assert!(
+            !dir.join("template.toml").exists(),
+            "template manifest copied"
+        );
+
+        let manifest_file = dir.join("emblem.toml");
+        let manifest = DocManifest::try_from(
+            &fs::read_to_string(&manifest_file).map_err(|e| Error::io(&manifest_file, e))?[..],
+        )?;
+        let dependencies = manifest.dependencies.expect("no requirements registered");
+        assert_eq!(
+            &"harvard",
+            dependencies["citations"]
+                .args()
+                .unwrap()
+                .get("style")
+                .unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dir_template() -> Result<()> {
+        let template_dir = tempfile::tempdir().unwrap();
+        let template_path = Utf8PathBuf::try_from(template_dir.path().to_owned()).unwrap();
+        make_template(&template_path)?;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let mut ctx = Context::test_new();
+        Initialiser::new(&tmpdir_path)
+            .with_template(template_path.as_str())
+            .run(&mut ctx)?;
+
+        check_templated(&tmpdir_path)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_skipped() -> Result<()> {
+        let secret_dir = tempfile::tempdir().unwrap();
+        let secret_path = Utf8PathBuf::try_from(secret_dir.path().to_owned()).unwrap();
+        let secret_file = secret_path.join("id_rsa");
+        fs::write(&secret_file, "secret").map_err(|e| Error::io(&secret_file, e))?;
+
+        let template_dir = tempfile::tempdir().unwrap();
+        let template_path = Utf8PathBuf::try_from(template_dir.path().to_owned()).unwrap();
+        make_template(&template_path)?;
+        for (link, target) in [("key", &secret_file), ("loop", &template_path)] {
+            let link = template_path.join(link);
+            std::os::unix::fs::symlink(target, &link).map_err(|e| Error::io(&link, e))?;
+        }
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let mut ctx = Context::test_new();
+        Initialiser::new(&tmpdir_path)
+            .with_template(template_path.as_str())
+            .run(&mut ctx)?;
+
+        check_templated(&tmpdir_path)?;
+        assert!(!tmpdir_path.join("key").exists(), "symlink followed");
+        assert!(!tmpdir_path.join("loop").exists(), "symlink followed");
+
+        let logs = ctx.logger().logs().to_vec();
+        let warning = logs
+            .iter()
+            .find(|log| log.msg().contains("symbolic links"))
+            .unwrap_or_else(|| panic!("no warning in {logs:?}"));
+        assert_eq!(2, warning.infos().unwrap_or_default().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn git_template() -> Result<()> {
+        let template_dir = tempfile::tempdir().unwrap();
+        let template_path = Utf8PathBuf::try_from(template_dir.path().to_owned()).unwrap();
+        make_template(&template_path)?;
+        {
+            let repo = Repository::init(&template_path)?;
+            let mut index = repo.index()?;
+            index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let sig = git2::Signature::now("kcza", "kcza@example.com")?;
+            repo.commit(Some("HEAD"), &sig, &sig, "add template", &tree, &[])?;
+        }
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let mut ctx = Context::test_new();
+        Initialiser::new(&tmpdir_path)
+            .with_template(format!("file://{template_path}"))
+            .run(&mut ctx)?;
+
+        check_templated(&tmpdir_path)
+    }
+
+    #[test]
+    fn unknown_template() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let mut ctx = Context::test_new();
+        let err = Initialiser::new(&tmpdir_path)
+            .with_template("pamphlet")
+            .run(&mut ctx)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("no such template"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn no_vcs() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let doc_dir = tmpdir_path.join("doc");
+
+        let mut ctx = Context::test_new();
+        Initialiser::new(&doc_dir)
+            .with_vcs(Vcs::None)
+            .run(&mut ctx)?;
+
+        assert!(doc_dir.join("main.em").is_file(), "no main.em");
+        assert!(doc_dir.join("emblem.toml").is_file(), "no emblem.toml");
+        assert!(!doc_dir.join(".git").exists(), "unexpected .git");
+        assert!(
+            !doc_dir.join(".gitignore").exists(),
+            "unexpected .gitignore"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_repo() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        Repository::init(&tmpdir_path)?;
+        let doc_dir = tmpdir_path.join("docs").join("manual");
+
+        let mut ctx = Context::test_new();
+        do_init(&mut ctx, &doc_dir)?;
+
+        assert!(doc_dir.join("main.em").is_file(), "no main.em");
+        assert!(!doc_dir.join(".git").exists(), "nested repository created");
+        assert!(
+            !doc_dir.join(".gitignore").exists(),
+            "unexpected .gitignore"
+        );
+
+        let logs = ctx.logger().logs().to_vec();
+        assert!(
+            logs.iter()
+                .any(|log| log.msg().contains("already inside a git repository")),
+            "no explanation in {logs:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn existing_files_reported() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let main_file = tmpdir_path.join("main.em");
+        fs::write(&main_file, "hello, world!").map_err(|e| Error::io(&main_file, e))?;
+
+        let mut ctx = Context::test_new();
+        do_init(&mut ctx, &tmpdir_path)?;
+
+        let logs = ctx.logger().logs().to_vec();
+        let warning = logs
+            .iter()
+            .find(|log| log.msg().contains("already exist"))
+            .unwrap_or_else(|| panic!("no warning in {logs:?}"));
+        let infos = warning.infos().unwrap_or_default();
+        assert_eq!(1, infos.len(), "unexpected infos: {infos:?}");
+        assert!(
+            infos[0].contains("main.em"),
+            "unexpected info: {}",
+            infos[0]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn existing_files_reported_once() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let tmpdir_path = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let main_file = tmpdir_path.join("main.em");
+        fs::write(&main_file, "hello, world!").map_err(|e| Error::io(&main_file, e))?;
+
+        let mut ctx = Context::test_new();
+        Initialiser::new(&tmpdir_path)
+            .with_template("article")
+            .run(&mut ctx)?;
+
+        let logs = ctx.logger().logs().to_vec();
+        let warning = logs
+            .iter()
+            .find(|log| log.msg().contains("already exist"))
+            .unwrap_or_else(|| panic!("no warning in {logs:?}"));
+        let infos = warning.infos().unwrap_or_default();
+        assert_eq!(1, infos.len(), "unexpected infos: {infos:?}");
+
+        Ok(())
+    }
 }
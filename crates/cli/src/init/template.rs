@@ -0,0 +1,264 @@
+use crate::{Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use git2::Repository;
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+};
+use tempfile::TempDir;
+use toml_edit::{Document, Item};
+
+/// Name of the file which holds a template's own configuration. This is never copied into the
+/// new document.
+static TEMPLATE_MANIFEST: &str = "template.toml";
+
+/// The files of each built-in template. These depend on no packages, so unlike template
+/// directories they have no `[requires]` table.
+static BUILTIN_TEMPLATES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "article",
+        &[("main.em", include_str!("templates/article/main.em"))],
+    ),
+    (
+        "report",
+        &[("main.em", include_str!("templates/report/main.em"))],
+    ),
+    (
+        "slides",
+        &[("main.em", include_str!("templates/slides/main.em"))],
+    ),
+];
+
+/// A skeleton from which a new document is created.
+pub(crate) enum Template {
+    Builtin(&'static [(&'static str, &'static str)]),
+    Dir(Utf8PathBuf),
+    Git {
+        _checkout: TempDir,
+        dir: Utf8PathBuf,
+    },
+}
+
+impl Template {
+    /// Find the template referred to by `src`, which may be the name of a built-in template, a
+    /// path to a local directory or the location of a git repository. Built-in templates take
+    /// precedence over local directories of the same name, which may be given as `./name`.
+    pub(crate) fn resolve(src: &str) -> Result<Self> {
+        if Self::is_git_source(src) {
+            return Self::clone(src);
+        }
+
+        if let Some((_, files)) = BUILTIN_TEMPLATES.iter().find(|(name, _)| *name == src) {
+            return Ok(Self::Builtin(files));
+        }
+
+        let path = Utf8Path::new(src);
+        if path.is_dir() {
+            return Ok(Self::Dir(path.to_owned()));
+        }
+
+        Err(Error::arg_invalid(
+            src.into(),
+            format!(
+                "no such template, expected a directory, a git repository or one of: {}",
+                Self::builtin_names().collect::<Vec<_>>().join(", ")
+            ),
+        ))
+    }
+
+    pub(crate) fn builtin_names() -> impl Iterator<Item = &'static str> {
+        BUILTIN_TEMPLATES.iter().map(|(name, _)| *name)
+    }
+
+    fn is_git_source(src: &str) -> bool {
+        src.contains("://") || src.starts_with("git@") || src.ends_with(".git")
+    }
+
+    fn clone(src: &str) -> Result<Self> {
+        let checkout = tempfile::tempdir().map_err(|e| Error::io("(temporary directory)", e))?;
+        let dir = Utf8PathBuf::try_from(checkout.path().to_owned())
+            .map_err(|e| Error::arg_invalid(src.into(), e.to_string()))?;
+        Repository::clone(src, &dir)?;
+        Ok(Self::Git {
+            _checkout: checkout,
+            dir,
+        })
+    }
+
+    /// Copy this template into `dst`, substituting each `{{var}}` for its value. Files which
+    /// already exist are left untouched and symbolic links are never followed.
+    pub(crate) fn instantiate(&self, dst: &Utf8Path, vars: &[(&str, &str)]) -> Result<Instance> {
+        let mut instance = Instance::default();
+        match self {
+            Self::Builtin(files) => {
+                for (path, contents) in files.iter() {
+                    instance.write_new(&dst.join(path), substitute(contents, vars).as_bytes())?;
+                }
+            }
+            Self::Dir(dir) | Self::Git { dir, .. } => {
+                Self::copy_dir(dir, dst, vars, &mut instance)?;
+
+                let manifest = dir.join(TEMPLATE_MANIFEST);
+                if manifest.exists() {
+                    instance.requires = Self::requires(
+                        &fs::read_to_string(&manifest).map_err(|e| Error::io(&manifest, e))?,
+                    )?;
+                }
+            }
+        }
+        Ok(instance)
+    }
+
+    fn copy_dir(
+        src: &Utf8Path,
+        dst: &Utf8Path,
+        vars: &[(&str, &str)],
+        instance: &mut Instance,
+    ) -> Result<()> {
+        fs::create_dir_all(dst).map_err(|e| Error::io(dst, e))?;
+
+        for entry in src.read_dir_utf8().map_err(|e| Error::io(src, e))? {
+            let entry = entry.map_err(|e| Error::io(src, e))?;
+            let name = entry.file_name();
+            if name == TEMPLATE_MANIFEST || name == ".git" {
+                continue;
+            }
+
+            let path = entry.path();
+            let file_type = entry.file_type().map_err(|e| Error::io(path, e))?;
+            if file_type.is_symlink() {
+                instance.skipped.push(path.to_owned());
+                continue;
+            }
+            if file_type.is_dir() {
+                Self::copy_dir(path, &dst.join(name), vars, instance)?;
+                continue;
+            }
+
+            let raw = fs::read(path).map_err(|e| Error::io(path, e))?;
+            let contents = match String::from_utf8(raw) {
+                Ok(text) => substitute(&text, vars).into_bytes(),
+                Err(e) => e.into_bytes(),
+            };
+            instance.write_new(&dst.join(name), &contents)?;
+        }
+
+        Ok(())
+    }
+
+    fn requires(manifest: &str) -> Result<Option<Item>> {
+        let mut manifest: Document = manifest
+            .parse()
+            .map_err(|e| Error::manifest_invalid(format!("invalid template manifest: {e}")))?;
+        Ok(manifest.remove("requires"))
+    }
+}
+
+/// The files written when a template is copied into a new document.
+#[derive(Debug, Default)]
+pub(crate) struct Instance {
+    /// Files which were created.
+    pub(crate) created: BTreeSet<Utf8PathBuf>,
+
+    /// Files which already existed, and so were left untouched.
+    pub(crate) untouched: Vec<Utf8PathBuf>,
+
+    /// Symbolic links in the template, which were not followed.
+    pub(crate) skipped: Vec<Utf8PathBuf>,
+
+    /// The default `[requires]` table of the template, if any.
+    pub(crate) requires: Option<Item>,
+}
+
+impl Instance {
+    /// Create a file at `path` with the given contents, unless it already exists.
+    fn write_new(&mut self, path: &Utf8Path, contents: &[u8]) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                file.write_all(contents).map_err(|e| Error::io(path, e))?;
+                self.created.insert(path.to_owned());
+                Ok(())
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                self.untouched.push(path.to_owned());
+                Ok(())
+            }
+            Err(e) => Err(Error::io(path, e)),
+        }
+    }
+}
+
+/// Replace each occurrence of `{{var}}` in `text` with the value of `var`. Values are inserted
+/// as-is, so any `{{var}}` they contain is left alone.
+fn substitute(text: &str, vars: &[(&str, &str)]) -> String {
+    let mut ret = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        ret.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let var = after.find("}}").and_then(|end| {
+            vars.iter()
+                .find(|(var, _)| *var == &after[..end])
+                .map(|(_, value)| (value, end))
+        });
+        match var {
+            Some((value, end)) => {
+                ret.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                ret.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn substitution() {
+        assert_eq!(
+            "# Burnt toast\nBy kcza, kcza and {{nobody}}",
+            substitute(
+                "# {{name}}\nBy {{author}}, {{author}} and {{nobody}}",
+                &[("name", "Burnt toast"), ("author", "kcza")]
+            )
+        );
+        assert_eq!(
+            "# {{author}} by kcza {{",
+            substitute(
+                "# {{name}} by {{author}} {{",
+                &[("name", "{{author}}"), ("author", "kcza")]
+            )
+        );
+    }
+
+    #[test]
+    fn git_sources() {
+        assert!(Template::is_git_source("https://github.com/foo/bar"));
+        assert!(Template::is_git_source("git@github.com:foo/bar"));
+        assert!(Template::is_git_source("../bar.git"));
+        assert!(!Template::is_git_source("article"));
+        assert!(!Template::is_git_source("../templates/article"));
+    }
+
+    #[test]
+    fn builtins_exist() {
+        for name in Template::builtin_names() {
+            assert!(
+                matches!(Template::resolve(name), Ok(Template::Builtin(_))),
+                "could not resolve {name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,11 @@
+# {{name}}
+
+By {{author}}.
+
+## Introduction
+
+Start writing your article here.
+
+## Conclusion
+
+Summarise your article here.
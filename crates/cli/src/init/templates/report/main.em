@@ -0,0 +1,19 @@
+# {{name}}
+
+Prepared by {{author}}.
+
+## Summary
+
+Briefly state the purpose and outcome of this report.
+
+## Background
+
+Describe the context in which this report was written.
+
+## Findings
+
+Present what was found.
+
+## Recommendations
+
+Suggest what should be done next.
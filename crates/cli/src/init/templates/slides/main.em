@@ -0,0 +1,15 @@
+# {{name}}
+
+{{author}}
+
+## Outline
+
+Give an overview of the talk.
+
+## First slide
+
+Each second-level heading starts a new slide.
+
+## Questions
+
+Thank the audience and invite questions.
@@ -13,8 +13,12 @@ pub use crate::result::Result;
 
 use crate::init::Initialiser;
 use crate::pretty_logger::PrettyLogger;
-use arg_parser::{Args, Command};
-use emblem_core::{log::Logger, Action, Builder, Context, Explainer, Linter};
+use arg_parser::{Args, Command, RequestedInfo};
+use camino::{Utf8Path, Utf8PathBuf};
+use emblem_core::{
+    log::Logger, Action, ArgPath, Builder, Context, Explainer, Linter, Lister, Listing, Log,
+    SearchPath, Tester,
+};
 use manifest::DocManifest;
 use std::{collections::HashMap, fs, process::ExitCode};
 
@@ -51,13 +55,53 @@ fn execute<L: Logger>(ctx: &mut Context<L>, args: &Args) -> Result<()> {
         Command::Build(build_args) => {
             load_manifest(ctx, "emblem.toml", args)?; // TODO(kcza): search parents for the
                                                       // manifest; find lock file in same location
-            Ok(Builder::from(build_args).run(ctx).map(|_| ())?)
+            let typesetter_params = ctx.typesetter_params_mut();
+            typesetter_params.set_max_iters(build_args.max_iters.into());
+            typesetter_params.set_reiteration(build_args.reiteration());
+            let outcome = Builder::from(build_args).run(ctx)?;
+            for (dest, contents) in outcome.outputs().into_iter().flatten() {
+                if *dest == ArgPath::Stdio {
+                    print!("{contents}");
+                }
+            }
+            if let Some(profile) = outcome.profile() {
+                eprint!("{profile}");
+            }
+            Ok(())
         }
         Command::Explain(explain_args) => Ok(Explainer::from(explain_args).run(ctx)?),
         Command::Format(_) => todo!(),
         Command::Init(init_args) => Initialiser::from(init_args).run(ctx),
-        Command::Lint(lint_args) => Ok(Linter::from(lint_args).run(ctx)?),
-        Command::List(_) => todo!(), // integrate_manifest!()  here
+        Command::Lint(lint_args) => {
+            ctx.set_search_path(SearchPath::from(
+                args.include_dirs()
+                    .iter()
+                    .map(Utf8PathBuf::from)
+                    .collect::<Vec<_>>(),
+            ));
+            Ok(Linter::from(lint_args).run(ctx)?)
+        }
+        Command::List(list_args) => {
+            load_manifest(ctx, "emblem.toml", args)?;
+            let listing = match list_args.what {
+                RequestedInfo::OutputFormats => Listing::OutputFormats,
+                RequestedInfo::OutputExtensions => Listing::OutputExtensions,
+            };
+            for item in Lister::new(listing).run(ctx)? {
+                println!("{item}");
+            }
+            Ok(())
+        }
+        Command::Test(test_args) => {
+            load_manifest(ctx, "emblem.toml", args)?;
+            let outcomes = Tester::from(test_args).run(ctx)?;
+            let passed = outcomes.len();
+            ctx.print(Log::info(format!(
+                "{passed} {} passed",
+                if passed == 1 { "spec" } else { "specs" }
+            )))?;
+            Ok(())
+        }
     }
 }
 
@@ -79,12 +123,27 @@ fn load_manifest<L: Logger>(ctx: &mut Context<L>, src: &str, args: &Args) -> Res
         doc_info.set_keywords(keywords);
     }
 
+    let manifest_dir = Utf8Path::new(src).parent().unwrap_or(Utf8Path::new(""));
+    let include_dirs = args
+        .include_dirs()
+        .iter()
+        .map(Utf8PathBuf::from)
+        .chain(
+            manifest
+                .paths
+                .iter()
+                .flat_map(|paths| paths.include_dirs(manifest_dir)),
+        )
+        .collect::<Vec<_>>();
+    ctx.set_search_path(SearchPath::from(include_dirs));
+
     let lua_info = ctx.lua_params_mut();
     let mut specific_args: HashMap<_, Vec<_>> = HashMap::new();
     if let Some(lua_args) = args.lua_args() {
         lua_info.set_sandbox_level(lua_args.sandbox_level.into());
         lua_info.set_max_mem(lua_args.max_mem.into());
         lua_info.set_max_steps(lua_args.max_steps.into());
+        lua_info.set_max_time(lua_args.max_time.into());
         let mut general_args = Vec::with_capacity(lua_args.args.len());
         for arg in &lua_args.args {
             let name = arg.name();
@@ -116,16 +175,16 @@ fn load_manifest<L: Logger>(ctx: &mut Context<L>, src: &str, args: &Args) -> Res
         .unwrap_or_default()
         .into_iter()
         .map(|(name, module)| {
-            let mut module = module.into_module(name.clone());
+            let mut module = module.into_module(name.clone())?;
             if let Some(args) = specific_args.remove(module.rename_as().unwrap_or(&name)) {
                 let dep_args = module.args_mut();
                 for (k2, v2) in args {
                     dep_args.insert(k2.to_string(), v2.to_string());
                 }
             }
-            module
+            Ok(module)
         })
-        .collect();
+        .collect::<Result<_>>()?;
     if !specific_args.is_empty() {
         return Err(Error::unused_args(
             specific_args.keys().map(ToString::to_string).collect(),
@@ -1,10 +1,14 @@
 use crate::{Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 use emblem_core::{
-    context::{Module as EmblemModule, ModuleVersion as EmblemModuleVersion},
+    context::{
+        Memory, Module as EmblemModule, ModuleVersion as EmblemModuleVersion, Resource,
+        ResourceLimit, Step,
+    },
     Version as EmblemVersion,
 };
 use serde::Deserialize as Deserialise;
-use std::collections::HashMap;
+use std::{collections::HashMap, error::Error as StdError};
 
 #[derive(Debug, Deserialise)]
 #[serde(deny_unknown_fields)]
@@ -13,6 +17,7 @@ pub(crate) struct DocManifest {
     pub(crate) metadata: DocMetadata,
     #[serde(rename = "requires")]
     pub(crate) dependencies: Option<HashMap<String, Module>>,
+    pub(crate) paths: Option<DocPaths>,
 }
 
 impl TryFrom<&str> for DocManifest {
@@ -46,6 +51,26 @@ pub(crate) struct DocMetadata {
     pub(crate) keywords: Option<Vec<String>>,
 }
 
+#[derive(Debug, Deserialise)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DocPaths {
+    /// Directories to search for files included with `.include` or read by extensions, such as
+    /// images and bibliographies, after the document's own directory. Relative paths are taken
+    /// relative to the directory which contains the manifest.
+    pub(crate) include: Option<Vec<String>>,
+}
+
+impl DocPaths {
+    /// The directories to search, given that the manifest lies in `manifest_dir`.
+    pub(crate) fn include_dirs(&self, manifest_dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+        self.include
+            .iter()
+            .flatten()
+            .map(|dir| manifest_dir.join(dir))
+            .collect()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialise, Eq, PartialEq)]
 pub(crate) enum Version {
     #[serde(rename = "1.0")]
@@ -77,6 +102,8 @@ pub(crate) struct Module {
     hash: Option<String>,
     branch: Option<String>,
     args: Option<HashMap<String, String>>,
+    max_mem: Option<String>,
+    max_steps: Option<String>,
 }
 
 impl Module {
@@ -107,7 +134,22 @@ impl Module {
         }
     }
 
+    /// The most memory this module may use. Modules without a limit are still subject to the
+    /// limit on all extensions.
+    pub fn max_mem(&self, name: &str) -> Result<ResourceLimit<Memory>> {
+        parse_limit(name, "max-mem", self.max_mem.as_deref())
+    }
+
+    /// The most steps this module may execute. Modules without a limit are still subject to the
+    /// limit on all extensions.
+    pub fn max_steps(&self, name: &str) -> Result<ResourceLimit<Step>> {
+        parse_limit(name, "max-steps", self.max_steps.as_deref())
+    }
+
     pub fn validate(&self, name: &str) -> Result<()> {
+        self.max_mem(name)?;
+        self.max_steps(name)?;
+
         match (&self.tag, &self.branch, &self.hash) {
             (Some(_), None, None) | (None, Some(_), None) | (None, None, Some(_)) => Ok(()),
             (None, None, None) => Err(Error::manifest_invalid("expected `tag` or `hash` field")),
@@ -117,14 +159,31 @@ impl Module {
         }
     }
 
-    pub fn into_module(self, source: String) -> EmblemModule {
+    pub fn into_module(self, source: String) -> Result<EmblemModule> {
         let version = self.version().into();
-        EmblemModule::new(
+        let max_mem = self.max_mem(&source)?;
+        let max_steps = self.max_steps(&source)?;
+        let mut module = EmblemModule::new(
             source,
             self.rename_as,
             version,
             self.args.unwrap_or_default(),
-        )
+        );
+        module.set_max_mem(max_mem);
+        module.set_max_steps(max_steps);
+        Ok(module)
+    }
+}
+
+fn parse_limit<T: Resource>(name: &str, field: &str, raw: Option<&str>) -> Result<ResourceLimit<T>>
+where
+    <T as TryFrom<u64>>::Error: StdError + 'static,
+{
+    match raw {
+        None | Some("unlimited") => Ok(ResourceLimit::Unlimited),
+        Some(raw) => Ok(ResourceLimit::Limited(T::parse(raw).map_err(|e| {
+            Error::manifest_invalid(format!("invalid {field} for {name}: {e}"))
+        })?)),
     }
 }
 
@@ -166,6 +225,7 @@ mod test {
         assert_eq!(Version::V1_0, manifest.metadata.version);
         assert_eq!(None, manifest.metadata.authors);
         assert_eq!(None, manifest.dependencies);
+        assert!(manifest.paths.is_none());
     }
 
     #[test]
@@ -198,6 +258,11 @@ mod test {
 
                 [requires.baz-hashed]
                 hash = "0123456789abcdef"
+                max-mem = "10M"
+                max-steps = "unlimited"
+
+                [paths]
+                include = ["../shared", "assets"]
             "#,
         );
         let manifest = DocManifest::try_from(raw).unwrap();
@@ -212,6 +277,10 @@ mod test {
             manifest.metadata.keywords.unwrap().as_slice()
         );
         assert_eq!(Version::V1_0, manifest.metadata.version);
+        assert_eq!(
+            &["../shared", "assets"],
+            manifest.paths.unwrap().include.unwrap().as_slice()
+        );
 
         {
             let dependencies = manifest.dependencies.unwrap();
@@ -236,10 +305,45 @@ mod test {
                     ModuleVersion::Hash("0123456789abcdef"),
                     baz_hashed.version()
                 );
+                assert_eq!(
+                    ResourceLimit::Limited(Memory(10 * 1024 * 1024)),
+                    baz_hashed.max_mem("baz-hashed").unwrap()
+                );
+                assert_eq!(
+                    ResourceLimit::Unlimited,
+                    baz_hashed.max_steps("baz-hashed").unwrap()
+                );
             }
         }
     }
 
+    #[test]
+    fn include_dirs() {
+        let raw = indoc::indoc!(
+            r#"
+                [document]
+                name = "foo"
+                emblem = "1.0"
+
+                [paths]
+                include = ["../shared", "assets", "/usr/share/emblem"]
+            "#,
+        );
+        let paths = DocManifest::try_from(raw).unwrap().paths.unwrap();
+        assert_eq!(
+            paths.include_dirs(Utf8Path::new("docs/manual")),
+            [
+                "docs/manual/../shared",
+                "docs/manual/assets",
+                "/usr/share/emblem"
+            ]
+        );
+        assert_eq!(
+            paths.include_dirs(Utf8Path::new("")),
+            ["../shared", "assets", "/usr/share/emblem"]
+        );
+    }
+
     #[test]
     fn incorrect_version() {
         let missing = indoc::indoc!(
@@ -293,6 +397,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn invalid_dependency_limits() {
+        let raw = indoc::indoc!(
+            r#"
+                [document]
+                name = "foo"
+                emblem = "1.0"
+
+                [requires.bar]
+                tag = "v1"
+                max-steps = "lots"
+            "#,
+        );
+        let err = DocManifest::try_from(raw).unwrap_err();
+        let re = Regex::new("invalid max-steps for bar: ").unwrap();
+        let msg = &err.to_string();
+        assert!(
+            re.is_match(msg),
+            "Unknown message doesn't match regex '{re:?}': got {msg}"
+        );
+    }
+
     #[test]
     fn extra_top_level_table() {
         let raw = indoc::indoc!(
@@ -101,7 +101,7 @@ impl<T: AstDebug> AstDebug for ParPart<T> {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Dash {
     Hyphen,
     En,
@@ -125,21 +125,24 @@ impl<T: AsRef<str>> From<T> for Dash {
     }
 }
 
+impl Dash {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hyphen => "-",
+            Self::En => "--",
+            Self::Em => "---",
+        }
+    }
+}
+
 #[cfg(test)]
 impl AstDebug for Dash {
     fn test_fmt(&self, buf: &mut Vec<String>) {
-        buf.push(
-            match self {
-                Self::Hyphen => "-",
-                Self::En => "--",
-                Self::Em => "---",
-            }
-            .into(),
-        );
+        buf.push(self.as_str().into());
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Glue {
     Tight,
     Nbsp,
@@ -161,15 +164,18 @@ impl<T: AsRef<str>> From<T> for Glue {
     }
 }
 
+impl Glue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tight => "~",
+            Self::Nbsp => "~~",
+        }
+    }
+}
+
 #[cfg(test)]
 impl AstDebug for Glue {
     fn test_fmt(&self, buf: &mut Vec<String>) {
-        buf.push(
-            match self {
-                Self::Tight => "~",
-                Self::Nbsp => "~~",
-            }
-            .into(),
-        );
+        buf.push(self.as_str().into());
     }
 }
@@ -2,31 +2,505 @@ pub(crate) mod typesetter;
 
 use crate::args::ArgPath;
 use crate::context::Context;
+use crate::extensions::{DocRef, Event, EventKind, Profile};
 use crate::log::Logger;
 use crate::parser;
 use crate::path::SearchResult;
+use crate::util;
 use crate::Action;
+use crate::Error;
+use crate::ErrorContext;
 use crate::Result;
+use camino::{Utf8Path, Utf8PathBuf};
 use derive_new::new;
+use std::fs;
+
+use crate::Log;
+
+#[cfg(feature = "git2")]
+use crate::repo;
 
 #[derive(new)]
 pub struct Builder {
     input: ArgPath,
 
-    #[allow(unused)]
     output_stem: ArgPath,
 
-    #[allow(unused)]
     output_driver: Option<String>,
+
+    #[new(default)]
+    profile: Option<ProfileOutput>,
+}
+
+/// Where to report the time extensions spend typesetting the document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProfileOutput {
+    /// Print the time and steps spent in each function.
+    Summary,
+
+    /// Write samples to the given file in collapsed-stack format, for flamegraph tools.
+    CollapsedStacks(Utf8PathBuf),
+}
+
+/// The result of building a document.
+#[derive(Debug, Default)]
+pub struct BuildOutcome {
+    outputs: Option<Vec<(ArgPath, String)>>,
+    profile: Option<Profile>,
+}
+
+impl BuildOutcome {
+    /// Where each file produced by the output driver was written and its contents, if a driver
+    /// was run. Files destined for stdout are left for the caller to print.
+    pub fn outputs(&self) -> Option<&[(ArgPath, String)]> {
+        self.outputs.as_deref()
+    }
+
+    /// The time and steps spent by extensions, if a summary was requested.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
 }
 
 impl Action for Builder {
-    type Response = Option<Vec<(ArgPath, String)>>;
+    type Response = BuildOutcome;
 
     fn run<L: Logger>(&self, ctx: &mut Context<L>) -> Result<Self::Response> {
+        let ret = self.build(ctx);
+        let logged = ctx.print_extension_logs();
+        let outcome = ret?;
+        logged?;
+        Ok(outcome)
+    }
+}
+
+impl Builder {
+    /// Profile extensions during typesetting, reporting the results to `output`.
+    pub fn with_profile(mut self, output: ProfileOutput) -> Self {
+        self.profile = Some(output);
+        self
+    }
+
+    fn build<L: Logger>(&self, ctx: &mut Context<L>) -> Result<<Self as Action>::Response> {
+        #[cfg(feature = "git2")]
+        self.inspect_repo(ctx)?;
+
+        let output_dir = self.output_dir();
+        let ext_state = ctx.extension_state()?;
+        ext_state.set_fs_roots(self.input_dir().to_owned(), output_dir.clone());
+        ext_state.set_output(self.output_driver.clone(), self.output_stem());
+        if let Some(dir) = &output_dir {
+            if let Err(e) = ext_state.open_store(dir) {
+                ctx.print(
+                    Log::warning(format!("ignoring stored values: {e}"))
+                        .with_help("delete the store to silence this warning"),
+                )?;
+            }
+        }
+        ext_state.load_modules(ctx.lua_params().modules())?;
+
         let fname: SearchResult = self.input.as_ref().try_into()?;
-        let root = parser::parse_file(ctx, fname)?;
-        ctx.typesetter().typeset(root)?;
-        Ok(None)
+        let root = parser::parse_document(ctx, self.input_dir(), fname)?;
+        let ext_state = ctx.extension_state()?;
+        if self.profile.is_some() {
+            ext_state.start_profiling()?;
+        }
+        let doc = ctx.typesetter().typeset(root);
+        let profile = match &self.profile {
+            Some(output) => Self::report_profile(output, ext_state.stop_profiling()?)?,
+            None => None,
+        };
+        let doc = DocRef::new(doc?);
+
+        ext_state.set_doc(Some(doc.clone()));
+        ext_state
+            .handle(Event::BeforeOutput)
+            .with_context(|| format!("failed to handle {} event", EventKind::BeforeOutput))?;
+        ext_state.set_doc(None);
+
+        let outputs = match &self.output_driver {
+            Some(format) => match ext_state.run_driver(format, doc)? {
+                Some(files) => Some(self.write(files)?),
+                None => {
+                    return Err(Error::unknown_output_format(
+                        format,
+                        ext_state.output_formats()?,
+                    ))
+                }
+            },
+            None => None,
+        };
+        let written = outputs
+            .iter()
+            .flatten()
+            .filter_map(|(path, _)| match path {
+                ArgPath::Path(path) => Some(path.clone()),
+                ArgPath::Stdio => None,
+            })
+            .collect();
+
+        ext_state
+            .handle(Event::AfterOutput { written })
+            .with_context(|| format!("failed to handle {} event", EventKind::AfterOutput))?;
+        ext_state.save_store()?;
+
+        Ok(BuildOutcome { outputs, profile })
+    }
+
+    /// Write the files produced by an output driver, each named by the output stem and its
+    /// extension. If no stem was given, nothing is written and the caller is left to print their
+    /// contents.
+    fn write(&self, files: Vec<(String, String)>) -> Result<Vec<(ArgPath, String)>> {
+        files
+            .into_iter()
+            .map(|(ext, contents)| {
+                let dest = match &self.output_stem {
+                    ArgPath::Path(stem) => {
+                        let path = Utf8PathBuf::from(format!("{stem}.{ext}"));
+                        fs::write(&path, &contents).map_err(|e| Error::io(&path, e))?;
+                        ArgPath::Path(path)
+                    }
+                    ArgPath::Stdio => ArgPath::Stdio,
+                };
+                Ok((dest, contents))
+            })
+            .collect()
+    }
+
+    /// Report the profile of extensions to `output`, returning it if a summary is to be printed.
+    fn report_profile(output: &ProfileOutput, profile: Profile) -> Result<Option<Profile>> {
+        match output {
+            ProfileOutput::Summary => Ok(Some(profile)),
+            ProfileOutput::CollapsedStacks(path) => {
+                fs::write(path, profile.collapsed_stacks()).map_err(|e| Error::io(path, e))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// The directory containing the input document.
+    fn input_dir(&self) -> &Utf8Path {
+        match &self.input {
+            ArgPath::Path(p) => util::parent_dir(p),
+            ArgPath::Stdio => Utf8Path::new("."),
+        }
+    }
+
+    /// The directory to which output is written, if any.
+    fn output_dir(&self) -> Option<Utf8PathBuf> {
+        match &self.output_stem {
+            ArgPath::Path(p) => Some(util::parent_dir(p).to_owned()),
+            ArgPath::Stdio => None,
+        }
+    }
+
+    /// The stem of the paths of the files written, if any.
+    fn output_stem(&self) -> Option<Utf8PathBuf> {
+        match &self.output_stem {
+            ArgPath::Path(p) => Some(p.to_owned()),
+            ArgPath::Stdio => None,
+        }
+    }
+
+    /// Record the state of the repository containing the input, complaining if it is dirty.
+    #[cfg(feature = "git2")]
+    fn inspect_repo<L: Logger>(&self, ctx: &mut Context<L>) -> Result<()> {
+        let dir = self.input_dir();
+        let info = match repo::info(dir.as_std_path()) {
+            Ok(Some(info)) => info,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                return ctx.print(Log::warning(format!(
+                    "cannot inspect repository containing {dir}: {e}"
+                )))
+            }
+        };
+
+        if info.dirty() {
+            if ctx.warnings_are_errors() {
+                return Err(Error::dirty_repository(dir));
+            }
+
+            ctx.print(
+                Log::warning("building from a repository with uncommitted changes")
+                    .with_help("commit all changes so this output can be traced to its source"),
+            )?;
+        }
+
+        ctx.set_repo_info(info);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::{Module, ModuleVersion};
+    use camino::Utf8PathBuf;
+    use std::{collections::HashMap, fs};
+
+    #[test]
+    fn input_dir() {
+        let builder = |input| Builder::new(input, ArgPath::Stdio, None);
+
+        assert_eq!(builder(ArgPath::Stdio).input_dir(), ".");
+        assert_eq!(
+            builder(ArgPath::Path(Utf8PathBuf::from("main.em"))).input_dir(),
+            "."
+        );
+        assert_eq!(
+            builder(ArgPath::Path(Utf8PathBuf::from("docs/manual/main.em"))).input_dir(),
+            "docs/manual"
+        );
+    }
+
+    #[test]
+    fn output_dir() {
+        let builder = |output| Builder::new(ArgPath::Stdio, output, None);
+
+        assert_eq!(builder(ArgPath::Stdio).output_dir(), None);
+        assert_eq!(
+            builder(ArgPath::Path(Utf8PathBuf::from("main"))).output_dir(),
+            Some(".".into())
+        );
+        assert_eq!(
+            builder(ArgPath::Path(Utf8PathBuf::from("out/main"))).output_dir(),
+            Some("out".into())
+        );
+    }
+
+    #[test]
+    fn profile() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let main_file = dir.join("main.em");
+        let stacks_file = dir.join("extensions.folded");
+        fs::write(&main_file, "hello\n").unwrap();
+
+        let mut ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.on('iter-start', function()
+                    local total = 0
+                    for i = 1, 1000 do total = total + i end
+                end)
+            "#,
+        )?;
+        let outcome = Builder::new(ArgPath::Path(main_file.clone()), ArgPath::Stdio, None)
+            .with_profile(ProfileOutput::CollapsedStacks(stacks_file.clone()))
+            .run(&mut ctx)?;
+        assert!(outcome.profile().is_none());
+
+        let stacks = fs::read_to_string(&stacks_file).unwrap();
+        assert!(
+            stacks
+                .lines()
+                .all(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().is_ok()),
+            "unexpected collapsed stacks: {stacks}"
+        );
+
+        let outcome = Builder::new(ArgPath::Path(main_file), ArgPath::Stdio, None)
+            .with_profile(ProfileOutput::Summary)
+            .run(&mut ctx)?;
+        let summary = outcome.profile().expect("no profile returned").to_string();
+        assert!(
+            summary.starts_with("module"),
+            "unexpected summary: {summary}"
+        );
+        assert!(ctx.logger().logs().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn events() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let main_file = dir.join("main.em");
+        fs::write(&main_file, "hello\n\n.include{chapter}\n").unwrap();
+        fs::write(dir.join("chapter.em"), "world\n").unwrap();
+
+        let mut ctx = Context::test_new();
+        {
+            let ext_state = ctx.extension_state()?;
+            ext_state.run("seen = {}")?;
+            for (kind, describe) in [
+                (
+                    EventKind::Include,
+                    "'include ' .. event.file:match('[^/]*$') .. ' at line ' .. event.loc.line",
+                ),
+                (EventKind::AfterParse, "'after-parse ' .. event.doc.kind"),
+                (EventKind::IterStart, "'iter-start'"),
+                (EventKind::Done, "'done'"),
+                (
+                    EventKind::BeforeOutput,
+                    "'before-output ' .. #event.doc.children",
+                ),
+                (EventKind::AfterOutput, "'after-output ' .. #event.written"),
+            ] {
+                ext_state.add_listener(
+                    kind,
+                    ext_state
+                        .lua()
+                        .load(&format!(
+                            "function(event) table.insert(seen, {describe}) end"
+                        ))
+                        .eval()?,
+                )?;
+            }
+        }
+
+        Builder::new(ArgPath::Path(main_file), ArgPath::Stdio, None).run(&mut ctx)?;
+
+        let seen: Vec<String> = ctx.extension_state()?.lua().globals().get("seen")?;
+        assert_eq!(
+            seen,
+            [
+                "include chapter.em at line 3",
+                "after-parse content",
+                "iter-start",
+                "done",
+                "before-output 2",
+                "after-output 0",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_driver() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let main_file = dir.join("main.em");
+        fs::write(&main_file, "hello\n").unwrap();
+
+        let mut ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.driver('xml', function(doc)
+                    return { xml = '<doc>' .. doc.children[1].text .. '</doc>' }
+                end)
+                em.on('after-output', function(event) written = event.written end)
+            "#,
+        )?;
+        let outcome = Builder::new(
+            ArgPath::Path(main_file.clone()),
+            ArgPath::Path(dir.join("main")),
+            Some("xml".into()),
+        )
+        .run(&mut ctx)?;
+
+        let xml_file = dir.join("main.xml");
+        assert_eq!(
+            outcome.outputs(),
+            Some([(ArgPath::Path(xml_file.clone()), "<doc>hello</doc>".into())].as_slice())
+        );
+        assert_eq!(fs::read_to_string(&xml_file).unwrap(), "<doc>hello</doc>");
+        let written: Vec<String> = ctx.extension_state()?.lua().globals().get("written")?;
+        assert_eq!(written, [xml_file.as_str()]);
+
+        let outcome = Builder::new(
+            ArgPath::Path(main_file.clone()),
+            ArgPath::Stdio,
+            Some("xml".into()),
+        )
+        .run(&mut ctx)?;
+        assert_eq!(
+            outcome.outputs(),
+            Some([(ArgPath::Stdio, "<doc>hello</doc>".into())].as_slice())
+        );
+
+        let err = Builder::new(
+            ArgPath::Path(main_file),
+            ArgPath::Stdio,
+            Some("html".into()),
+        )
+        .run(&mut ctx)
+        .unwrap_err();
+        assert_eq!(err.to_string(), "no such output format: html");
+
+        Ok(())
+    }
+
+    #[test]
+    fn required_driver() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let main_file = dir.join("main.em");
+        fs::write(&main_file, "hello\n").unwrap();
+        fs::write(
+            dir.join("plain.lua"),
+            r#"
+                local format = em.build.output_format
+                em.driver('txt', function(doc)
+                    return { txt = doc.children[1].text .. ' as ' .. format }
+                end)
+            "#,
+        )
+        .unwrap();
+
+        let mut ctx = Context::test_new();
+        ctx.lua_params_mut().set_modules(vec![Module::new(
+            "plain".into(),
+            None,
+            ModuleVersion::Tag("v1.0".into()),
+            HashMap::new(),
+        )]);
+        ctx.extension_state()?
+            .lua()
+            .globals()
+            .get::<_, mlua::Table>("package")?
+            .set("path", format!("{dir}/?.lua"))?;
+        Builder::new(
+            ArgPath::Path(main_file),
+            ArgPath::Path(dir.join("main")),
+            Some("txt".into()),
+        )
+        .run(&mut ctx)?;
+
+        assert_eq!(
+            fs::read_to_string(dir.join("main.txt")).unwrap(),
+            "hello as txt"
+        );
+        assert_eq!(ctx.extension_state()?.output_formats()?, ["txt"]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "git2")]
+    #[test]
+    fn dirty_repo() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        git2::Repository::init(&dir).unwrap();
+        let main_file = dir.join("main.em");
+        fs::write(&main_file, "hello, world").map_err(|e| Error::io(&main_file, e))?;
+
+        let builder = Builder::new(ArgPath::Path(main_file), ArgPath::Stdio, None);
+
+        {
+            let mut ctx = Context::test_new();
+            builder.run(&mut ctx)?;
+
+            let logs = ctx.logger().logs().to_vec();
+            assert!(
+                logs.iter()
+                    .any(|log| log.msg().contains("uncommitted changes")),
+                "no warning in {logs:?}"
+            );
+            assert_eq!(Some(true), ctx.repo_info().map(|info| info.dirty()));
+        }
+
+        {
+            let mut ctx = Context::test_new().warnings_as_errors(true);
+            let err = builder.run(&mut ctx).unwrap_err();
+            assert!(
+                err.to_string().contains("uncommitted changes"),
+                "unexpected error: {err}"
+            );
+        }
+
+        Ok(())
     }
 }
@@ -7,12 +7,17 @@ use crate::{
     FileContentSlice,
 };
 
+use crate::context::file_content::FileSlice;
+
 #[cfg(test)]
-use crate::{ast::AstDebug, context::file_content::FileSlice};
+use crate::ast::AstDebug;
 
 pub type Doc = DocElem;
 
-#[derive(Debug, Eq, PartialEq)]
+/// The name of the command which the typesetter wraps around each paragraph.
+const PAR_COMMAND: &str = "p";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DocElem {
     Word {
         word: FileContentSlice,
@@ -27,6 +32,7 @@ pub enum DocElem {
         loc: Location,
     },
     Command {
+        qualifier: Option<FileContentSlice>,
         name: CommandName,
         plus: bool,
         attrs: Option<Attrs>,
@@ -38,6 +44,184 @@ pub enum DocElem {
 }
 
 impl DocElem {
+    /// The name of the kind of this element.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::Word { .. } => "word",
+            Self::Dash { .. } => "dash",
+            Self::Glue { .. } => "glue",
+            Self::Command { .. } => "command",
+            Self::Content(_) => "content",
+        }
+    }
+
+    /// The elements directly contained within this one.
+    pub fn children(&self) -> Option<&Vec<DocElem>> {
+        match self {
+            Self::Command { args, .. } => Some(args),
+            Self::Content(content) => Some(content),
+            _ => None,
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Option<&mut Vec<DocElem>> {
+        match self {
+            Self::Command { args, .. } => Some(args),
+            Self::Content(content) => Some(content),
+            _ => None,
+        }
+    }
+
+    pub fn loc(&self) -> Option<&Location> {
+        match self {
+            Self::Word { loc, .. }
+            | Self::Dash { loc, .. }
+            | Self::Glue { loc, .. }
+            | Self::Command { loc, .. } => Some(loc),
+            Self::Content(_) => None,
+        }
+    }
+
+    /// Whether this is a paragraph which the typesetter wrapped around some content, rather than
+    /// a command written in the source.
+    pub fn is_implicit_par(&self) -> bool {
+        matches!(
+            self,
+            Self::Command { name: CommandName::Literal(name), .. } if *name == PAR_COMMAND
+        )
+    }
+
+    /// A copy of this element in which each evaluated command is substituted for its result.
+    pub fn evaluated(&self) -> DocElem {
+        match self {
+            Self::Command {
+                result: Some(result),
+                ..
+            } => result.evaluated(),
+            Self::Command {
+                qualifier,
+                name,
+                plus,
+                attrs,
+                args,
+                result: None,
+                loc,
+            } => Self::Command {
+                qualifier: qualifier.clone(),
+                name: name.clone(),
+                plus: *plus,
+                attrs: attrs.clone(),
+                args: args.iter().map(Self::evaluated).collect(),
+                result: None,
+                loc: loc.clone(),
+            },
+            Self::Content(content) => Self::Content(content.iter().map(Self::evaluated).collect()),
+            elem => elem.clone(),
+        }
+    }
+
+    /// The text of this element once evaluated, with its words separated by spaces.
+    pub fn text(&self) -> String {
+        let mut ret = String::new();
+        self.push_text(&mut ret, &mut false);
+        ret
+    }
+
+    fn push_text(&self, buf: &mut String, after_word: &mut bool) {
+        match self {
+            Self::Word { word, .. } => {
+                if *after_word {
+                    buf.push(' ');
+                }
+                buf.push_str(word.to_str());
+                *after_word = true;
+            }
+            Self::Dash { dash, .. } => {
+                buf.push_str(dash.as_str());
+                *after_word = false;
+            }
+            Self::Glue { .. } => {
+                buf.push(' ');
+                *after_word = false;
+            }
+            Self::Command {
+                result: Some(result),
+                ..
+            } => result.push_text(buf, after_word),
+            Self::Command { args: elems, .. } | Self::Content(elems) => {
+                for elem in elems {
+                    elem.push_text(buf, after_word);
+                }
+            }
+        }
+    }
+
+    /// Whether this element has the same structure and text as `other` once both are evaluated,
+    /// regardless of where in the source either came from.
+    pub fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Command {
+                    result: Some(result),
+                    ..
+                },
+                _,
+            ) => result.same_as(other),
+            (
+                _,
+                Self::Command {
+                    result: Some(result),
+                    ..
+                },
+            ) => self.same_as(result),
+            (Self::Word { word: a, .. }, Self::Word { word: b, .. }) => a.to_str() == b.to_str(),
+            (Self::Dash { dash: a, .. }, Self::Dash { dash: b, .. }) => a == b,
+            (Self::Glue { glue: a, .. }, Self::Glue { glue: b, .. }) => a == b,
+            (
+                Self::Command {
+                    qualifier: a_qualifier,
+                    name: a_name,
+                    plus: a_plus,
+                    attrs: a_attrs,
+                    args: a_args,
+                    ..
+                },
+                Self::Command {
+                    qualifier: b_qualifier,
+                    name: b_name,
+                    plus: b_plus,
+                    attrs: b_attrs,
+                    args: b_args,
+                    ..
+                },
+            ) => {
+                a_qualifier.as_ref().map(FileSlice::to_str)
+                    == b_qualifier.as_ref().map(FileSlice::to_str)
+                    && a_name.as_str() == b_name.as_str()
+                    && a_plus == b_plus
+                    && Self::same_attrs(a_attrs.as_ref(), b_attrs.as_ref())
+                    && Self::all_same(a_args, b_args)
+            }
+            (Self::Content(a), Self::Content(b)) => Self::all_same(a, b),
+            _ => false,
+        }
+    }
+
+    fn all_same(a: &[DocElem], b: &[DocElem]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_as(b))
+    }
+
+    fn same_attrs(a: Option<&Attrs>, b: Option<&Attrs>) -> bool {
+        fn pairs(attrs: Option<&Attrs>) -> Vec<(Option<&str>, &str)> {
+            attrs
+                .map_or(&[][..], Attrs::args)
+                .iter()
+                .map(|attr| (attr.name().map(FileSlice::to_str), attr.value().to_str()))
+                .collect()
+        }
+        pairs(a) == pairs(b)
+    }
+
     fn into_content(self) -> Option<Vec<DocElem>> {
         match self {
             Self::Content(cs) => Some(cs),
@@ -50,6 +234,7 @@ impl DocElem {
             Self::Content(c) if c.len() == 1 => c.into_iter().next().unwrap().simplify(),
             Self::Content(c) => Self::Content(c.into_iter().map(Self::simplify).collect()),
             Self::Command {
+                qualifier,
                 name,
                 plus,
                 attrs,
@@ -57,6 +242,7 @@ impl DocElem {
                 result,
                 loc,
             } => Self::Command {
+                qualifier,
                 name,
                 plus,
                 attrs,
@@ -83,6 +269,7 @@ impl AstDebug for Doc {
             Self::Dash { dash, .. } => dash.test_fmt(buf),
             Self::Glue { glue, .. } => glue.test_fmt(buf),
             Self::Command {
+                qualifier,
                 name,
                 plus,
                 attrs,
@@ -90,6 +277,10 @@ impl AstDebug for Doc {
                 ..
             } => {
                 ".".test_fmt(buf);
+                if let Some(qualifier) = qualifier {
+                    qualifier.surround(buf, "(", ")");
+                    ".".test_fmt(buf);
+                }
                 name.test_fmt(buf);
                 if *plus {
                     "+".test_fmt(buf);
@@ -106,12 +297,21 @@ impl AstDebug for Doc {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum CommandName {
     Literal(&'static str),
     FileContentSlice(FileContentSlice),
 }
 
+impl CommandName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Literal(l) => l,
+            Self::FileContentSlice(s) => s.to_str(),
+        }
+    }
+}
+
 impl From<&'static str> for CommandName {
     fn from(literal: &'static str) -> Self {
         Self::Literal(literal)
@@ -127,11 +327,7 @@ impl From<FileContentSlice> for CommandName {
 #[cfg(test)]
 impl AstDebug for CommandName {
     fn test_fmt(&self, buf: &mut Vec<String>) {
-        match self {
-            Self::Literal(l) => l,
-            Self::FileContentSlice(s) => s.to_str(),
-        }
-        .test_fmt(buf);
+        self.as_str().test_fmt(buf);
     }
 }
 
@@ -207,7 +403,8 @@ impl IntoDoc for Vec<Par<ParPart<Content>>> {
                     };
                 if apply_paragraph {
                     return Some(DocElem::Command {
-                        name: "p".into(),
+                        qualifier: None,
+                        name: PAR_COMMAND.into(),
                         plus: false,
                         attrs: None,
                         result: None,
@@ -262,6 +459,7 @@ impl IntoDoc for Content {
     fn into_doc(self, state: DocStackState) -> Option<DocElem> {
         match self {
             Self::Command {
+                qualifier,
                 name,
                 pluses,
                 attrs,
@@ -271,6 +469,7 @@ impl IntoDoc for Content {
                 invocation_loc,
                 ..
             } => Some(DocElem::Command {
+                qualifier,
                 name: name.into(),
                 plus: pluses != 0,
                 attrs,
@@ -318,6 +517,7 @@ impl IntoDoc for Sugar {
                 | Self::Monospace { arg, .. }
                 | Self::Smallcaps { arg, .. }
                 | Self::AlternateFace { arg, .. } => DocElem::Command {
+                    qualifier: None,
                     name,
                     plus: false,
                     attrs: None,
@@ -326,6 +526,7 @@ impl IntoDoc for Sugar {
                     loc,
                 },
                 Self::Heading { pluses, arg, .. } => DocElem::Command {
+                    qualifier: None,
                     name,
                     plus: pluses != 0,
                     attrs: None,
@@ -334,6 +535,7 @@ impl IntoDoc for Sugar {
                     loc,
                 },
                 Self::Mark { mark, .. } => DocElem::Command {
+                    qualifier: None,
                     name,
                     plus: false,
                     attrs: Some(Attrs::new(
@@ -349,6 +551,7 @@ impl IntoDoc for Sugar {
                     loc,
                 },
                 Self::Reference { reference, .. } => DocElem::Command {
+                    qualifier: None,
                     name,
                     plus: false,
                     attrs: Some(Attrs::new(
@@ -370,7 +573,8 @@ impl IntoDoc for Sugar {
 
 #[cfg(test)]
 mod test {
-    use crate::parser::test::ParserTest;
+    use super::*;
+    use crate::{parser, parser::test::ParserTest, Context};
 
     #[test]
     fn into_doc() {
@@ -508,4 +712,18 @@ mod test {
             .input("and let go of /* all */ your fears")
             .produces_doc(".p{[Word(and)|Word(let)|Word(go)|Word(of)|Word(your)|Word(fears)]}");
     }
+
+    #[test]
+    fn text() {
+        let ctx = Context::test_new();
+        let parsed = parser::parse(
+            ctx.alloc_file_name("text.em"),
+            ctx.alloc_file_content("version~1.0 is _really_ new-ish---.undefined{honest}"),
+        )
+        .unwrap();
+        assert_eq!(
+            DocElem::from(parsed).text(),
+            "version 1.0 is really new-ish---honest"
+        );
+    }
 }
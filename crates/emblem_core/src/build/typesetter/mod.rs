@@ -1,10 +1,11 @@
 use crate::{
     ast::parsed::ParsedFile,
-    build::typesetter::doc::Doc,
+    build::typesetter::doc::{Doc, DocElem},
     context::Iteration,
-    extensions::{Event, EventKind, ExtensionState},
-    log::Logger,
-    Context, ErrorContext, ResourceLimit, Result,
+    extensions::{DocRef, Event, EventKind, ExtensionState},
+    log::{Logger, Note, Src},
+    parser::Location,
+    Context, ErrorContext, Log, Reiteration, ResourceLimit, Result,
 };
 
 pub(crate) mod doc;
@@ -15,6 +16,11 @@ pub struct Typesetter<'ctx, L: Logger> {
     ctx: &'ctx Context<L>,
     curr_iter: Iteration,
     max_iters: ResourceLimit<Iteration>,
+    reiteration: Reiteration,
+
+    /// The state of the document after each of the last two iterations, when checking for
+    /// convergence.
+    snapshots: (Option<Doc>, Option<Doc>),
 }
 
 impl<'ctx, L: Logger> Typesetter<'ctx, L> {
@@ -23,20 +29,41 @@ impl<'ctx, L: Logger> Typesetter<'ctx, L> {
             ctx,
             curr_iter: Iteration(0),
             max_iters: ctx.typesetter_params().max_iters(),
+            reiteration: ctx.typesetter_params().reiteration(),
+            snapshots: (None, None),
         }
     }
 
-    pub fn typeset(mut self, root: ParsedFile) -> Result<()> {
-        let mut root = Doc::from(root);
+    /// Typeset the given file, returning the final document.
+    pub fn typeset(self, root: ParsedFile) -> Result<Doc> {
+        let ctx = self.ctx;
+        let ret = self.run(root);
+        let logged = ctx.print_extension_logs();
+        let doc = ret?;
+        logged?;
+        Ok(doc)
+    }
+
+    fn run(mut self, root: ParsedFile) -> Result<Doc> {
+        let root = DocRef::new(Doc::from(root));
         let ext_state = self.ctx.extension_state()?;
+        ext_state.set_doc(Some(root.clone()));
+        ext_state
+            .handle(Event::AfterParse)
+            .with_context(|| format!("failed to handle {} event", EventKind::AfterParse))?;
         loop {
-            self.iter(ext_state, &mut root)?;
+            self.iter(ext_state, &root)?;
 
-            if !self.will_reiter(ext_state) {
+            let changed = self.changed(&root);
+            if !self.will_reiter(ext_state, changed) {
+                if changed && !self.max_iters.lt(self.curr_iter) {
+                    self.ctx.print(self.unconverged())?;
+                }
                 break;
             }
             ext_state.reset_reiter_request();
         }
+        ext_state.set_doc(None);
 
         ext_state
             .handle(Event::Done {
@@ -44,25 +71,57 @@ impl<'ctx, L: Logger> Typesetter<'ctx, L> {
             })
             .with_context(|| format!("failed to handle {} event", EventKind::Done))?;
 
-        Ok(())
+        Ok(root.take())
     }
 
-    fn will_reiter(&self, ext_state: &ExtensionState) -> bool {
-        ext_state.reiter_requested() && self.max_iters.lt(self.curr_iter)
+    fn will_reiter(&self, ext_state: &ExtensionState, changed: bool) -> bool {
+        (ext_state.reiter_requested() || changed) && self.max_iters.lt(self.curr_iter)
     }
 
-    fn iter(&mut self, ext_state: &ExtensionState, _root: &mut Doc) -> Result<()> {
-        self.curr_iter += Iteration(1);
+    /// Returns whether the evaluated document changed during the last iteration, if checking for
+    /// convergence.
+    fn changed(&mut self, root: &DocRef) -> bool {
+        if self.reiteration != Reiteration::UntilConverged {
+            return false;
+        }
 
-        let Iteration(iter) = &self.curr_iter;
-        println!("Doing iteration {iter} of {:?}", self.max_iters);
+        let curr = root.borrow().clone();
+        let changed = match &self.snapshots.1 {
+            Some(prev) => prev.evaluated() != curr.evaluated(),
+            None => true,
+        };
+        self.snapshots = (self.snapshots.1.take(), Some(curr));
+        changed
+    }
+
+    /// A warning that the document did not converge, pointing to the parts which kept changing.
+    fn unconverged(&self) -> Log {
+        let Iteration(iters) = self.curr_iter;
+        let mut log = Log::warning(format!(
+            "document did not converge after {iters} iterations"
+        ))
+        .with_help("increase the maximum number of iterations with --max-iters");
+
+        let mut locs = Vec::new();
+        if let (Some(prev), Some(curr)) = &self.snapshots {
+            changes(prev, curr, None, &mut locs);
+        }
+        for loc in locs {
+            log = log
+                .with_src(Src::new(&loc).with_annotation(Note::warn(&loc, "this kept changing")));
+        }
+        log
+    }
+
+    fn iter(&mut self, ext_state: &ExtensionState, root: &DocRef) -> Result<()> {
+        self.curr_iter += Iteration(1);
 
         ext_state
             .handle(Event::IterStart {
                 iter: self.curr_iter,
             })
             .with_context(|| format!("failed to handle {} event", EventKind::IterStart))?;
-        // TODO(kzca): Evaluate the root.
+        ext_state.evaluate(root)?;
         ext_state
             .handle(Event::IterEnd {
                 iter: self.curr_iter,
@@ -73,16 +132,69 @@ impl<'ctx, L: Logger> Typesetter<'ctx, L> {
     }
 }
 
+/// Find the locations of the outermost parts of `curr` which differ from `prev`. Commands whose
+/// results differ are reported, rather than the results themselves, as these are what a user
+/// can see in their source.
+fn changes(prev: &DocElem, curr: &DocElem, parent: Option<&Location>, locs: &mut Vec<Location>) {
+    if prev.evaluated() == curr.evaluated() {
+        return;
+    }
+
+    let loc = curr.loc().or(parent);
+    if let (
+        DocElem::Command {
+            result: prev_result,
+            ..
+        },
+        DocElem::Command {
+            result: curr_result,
+            ..
+        },
+    ) = (prev, curr)
+    {
+        if prev_result.as_ref().map(|r| r.evaluated())
+            != curr_result.as_ref().map(|r| r.evaluated())
+        {
+            locs.extend(loc.cloned());
+            return;
+        }
+    }
+
+    match (prev.children(), curr.children()) {
+        (Some(prev_children), Some(curr_children))
+            if prev.kind_name() == curr.kind_name()
+                && prev_children.len() == curr_children.len() =>
+        {
+            for (prev_child, curr_child) in prev_children.iter().zip(curr_children) {
+                changes(prev_child, curr_child, loc, locs);
+            }
+        }
+        _ => locs.extend(loc.cloned()),
+    }
+}
+
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
     use crate::{
+        ast::AstDebug,
         extensions::{EventKind, ExtensionData},
-        parser,
+        log::MessageType,
+        parser::{self, Point},
     };
     use mlua::{Integer, MetaMethod, Table, ToLua, UserData, Value};
     use std::{cell::RefCell, rc::Rc};
 
+    /// Typeset `src` as the contents of the file `file_name`, returning a representation of the
+    /// evaluated document.
+    pub fn typeset(ctx: &Context<impl Logger>, file_name: &str, src: &str) -> Result<String> {
+        let doc = Typesetter::new(ctx).typeset(parser::parse(
+            ctx.alloc_file_name(file_name),
+            ctx.alloc_file_content(src),
+        )?)?;
+        Ok(doc.evaluated().repr())
+    }
+
     #[test]
     fn iter_events() -> Result<()> {
         let iter_start_indices = Rc::new(RefCell::new(Vec::new()));
@@ -145,6 +257,62 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn doc_in_events() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.add_listener(
+            EventKind::IterStart,
+            ext_state
+                .lua()
+                .load(
+                    r#"
+                        function(event)
+                            local words = event.doc.args[1].children
+                            assert(words[1].text == 'hello')
+                            words[1]:replace('goodbye')
+                        end
+                    "#,
+                )
+                .eval()?,
+        )?;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            ext_state.lua().globals().set(
+                "record",
+                ext_state.lua().create_function(move |_, text: String| {
+                    seen.try_borrow_mut().unwrap().push(text);
+                    Ok(())
+                })?,
+            )?;
+        }
+        ext_state.add_listener(
+            EventKind::IterEnd,
+            ext_state
+                .lua()
+                .load("function(event) record(event.doc.args[1].children[1].text) end")
+                .eval()?,
+        )?;
+        ext_state.add_listener(
+            EventKind::Done,
+            ext_state
+                .lua()
+                .load("function(event) assert(event.doc == nil) end")
+                .eval()?,
+        )?;
+
+        Typesetter::new(&ctx).typeset(parser::parse(
+            ctx.alloc_file_name("doc_in_events.em"),
+            ctx.alloc_file_content("hello world"),
+        )?)?;
+
+        assert_eq!(seen.borrow().clone(), ["goodbye"]);
+
+        Ok(())
+    }
+
     #[test]
     fn reiter_request() -> Result<()> {
         let iter_start_indices = Rc::new(RefCell::new(Vec::new()));
@@ -447,12 +615,35 @@ mod test {
                 table.set_metatable(None);
             }
 
-            let err = Typesetter::new(&ctx)
-                .typeset(parser::parse(
-                    ctx.alloc_file_name("event-listeners.em"),
-                    ctx.alloc_file_content(""),
-                )?)
-                .unwrap_err();
+            let result = match event_kind {
+                EventKind::AfterParse
+                | EventKind::IterStart
+                | EventKind::IterEnd
+                | EventKind::Done => Typesetter::new(&ctx)
+                    .typeset(parser::parse(
+                        ctx.alloc_file_name("event-listeners.em"),
+                        ctx.alloc_file_content(""),
+                    )?)
+                    .map(|_| ()),
+                EventKind::Include => {
+                    let src = ".include{other.em}";
+                    let start = Point::at_start_of(
+                        ctx.alloc_file_name("event-listeners.em"),
+                        ctx.alloc_file_content(src),
+                    );
+                    let end = start.clone().shift(src);
+                    ext_state.handle(Event::Include {
+                        file: "other.em".into(),
+                        loc: Location::new(&start, &end),
+                    })
+                }
+                EventKind::BeforeOutput => ext_state.handle(Event::BeforeOutput),
+                EventKind::AfterOutput => ext_state.handle(Event::AfterOutput { written: vec![] }),
+                EventKind::ModuleLoaded => ext_state.handle(Event::ModuleLoaded {
+                    name: "figures".into(),
+                }),
+            };
+            let err = result.unwrap_err();
             assert!(
                 err.to_string()
                     .contains("runtime error: attempt to call a table value"),
@@ -464,4 +655,77 @@ mod test {
 
         Ok(())
     }
+
+    fn iters_until_converged(max_iters: ResourceLimit<Iteration>) -> Result<(Integer, Vec<Log>)> {
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.typesetter_params_mut().set_max_iters(max_iters);
+            ctx.typesetter_params_mut()
+                .set_reiteration(Reiteration::UntilConverged);
+            ctx
+        };
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(
+            r#"
+                iters = 0
+                em.on('iter-start', function() iters = iters + 1 end)
+                em.define('pages', function() return tostring(math.min(iters, 3)) end)
+                em.define('title', function() return 'fixed' end)
+            "#,
+        )?;
+
+        Typesetter::new(&ctx).typeset(parser::parse(
+            ctx.alloc_file_name("converge.em"),
+            ctx.alloc_file_content(".title{} has .pages{} pages"),
+        )?)?;
+
+        let iters = ext_state.lua().globals().get("iters")?;
+        let logs = ctx.logger().logs().to_vec();
+        Ok((iters, logs))
+    }
+
+    #[test]
+    fn until_converged() -> Result<()> {
+        let (iters, logs) = iters_until_converged(ResourceLimit::Limited(Iteration(10)))?;
+        assert_eq!(iters, 4);
+        assert!(logs.is_empty(), "unexpected logs: {logs:?}");
+
+        let (iters, logs) = iters_until_converged(ResourceLimit::Limited(Iteration(2)))?;
+        assert_eq!(iters, 2);
+        assert_eq!(logs.len(), 1);
+        let log = &logs[0];
+        assert_eq!(log.msg_type(), MessageType::Warning);
+        assert_eq!(log.msg(), "document did not converge after 2 iterations");
+        assert_eq!(
+            log.srcs()
+                .iter()
+                .map(|src| src.loc().to_string())
+                .collect::<Vec<_>>(),
+            ["converge.em:1:14-19"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn on_request_ignores_changes() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(
+            r#"
+                iters = 0
+                em.on('iter-start', function() iters = iters + 1 end)
+                em.define('pages', function() return tostring(iters) end)
+            "#,
+        )?;
+
+        Typesetter::new(&ctx).typeset(parser::parse(
+            ctx.alloc_file_name("on_request.em"),
+            ctx.alloc_file_content(".pages{}"),
+        )?)?;
+
+        assert_eq!(ext_state.lua().globals().get::<_, Integer>("iters")?, 1);
+
+        Ok(())
+    }
 }
@@ -6,13 +6,14 @@ mod resources;
 
 use crate::{
     log::{BatchLogger, Log, Logger, MessageType},
-    ExtensionState, FileContent, FileName, Result, Typesetter, Verbosity, Version,
+    Error, ExtensionState, FileContent, FileName, RepoInfo, Result, SearchPath, Typesetter,
+    Verbosity, Version,
 };
 use derive_new::new;
 pub use module::{Module, ModuleVersion};
 use once_cell::unsync::OnceCell;
 pub use resource_limit::ResourceLimit;
-pub use resources::{Iteration, Memory, Resource, Step};
+pub use resources::{Iteration, Memory, Resource, Step, Time};
 use std::cell::{Ref, RefCell};
 use std::fmt::Debug;
 
@@ -20,6 +21,8 @@ pub struct Context<L: Logger> {
     name: Option<String>,
     version: Option<Version>,
     warnings_as_errors: bool,
+    repo_info: Option<RepoInfo>,
+    search_path: SearchPath,
     doc_params: DocumentParameters,
     lua_params: LuaParameters,
     typesetter_params: TypesetterParameters,
@@ -33,6 +36,8 @@ impl<L: Logger> Context<L> {
             name: None,
             version: None,
             warnings_as_errors: false,
+            repo_info: None,
+            search_path: Default::default(),
             doc_params: Default::default(),
             lua_params: Default::default(),
             typesetter_params: Default::default(),
@@ -62,6 +67,26 @@ impl<L: Logger> Context<L> {
         self
     }
 
+    pub fn warnings_are_errors(&self) -> bool {
+        self.warnings_as_errors
+    }
+
+    pub fn repo_info(&self) -> Option<&RepoInfo> {
+        self.repo_info.as_ref()
+    }
+
+    pub fn set_repo_info(&mut self, repo_info: RepoInfo) {
+        self.repo_info = Some(repo_info);
+    }
+
+    pub fn search_path(&self) -> &SearchPath {
+        &self.search_path
+    }
+
+    pub fn set_search_path(&mut self, search_path: SearchPath) {
+        self.search_path = search_path;
+    }
+
     pub fn alloc_file_name(&self, name: impl AsRef<str>) -> FileName {
         FileName::new(name.as_ref())
     }
@@ -118,6 +143,27 @@ impl<L: Logger> Context<L> {
         self.logger.borrow_mut().print(log)
     }
 
+    /// Print the messages logged by extensions since this was last called. If any of these were
+    /// errors, the run is marked as failed by returning an error once all have been printed.
+    pub(crate) fn print_extension_logs(&self) -> Result<()> {
+        let Some(ext_state) = self.extension_state.get() else {
+            return Ok(());
+        };
+        let mut errors = 0;
+        for log in ext_state.take_logs() {
+            if !log.successful(self.warnings_as_errors) {
+                errors += 1;
+            }
+            if self.verbosity().permits_printing(log.msg_type()) {
+                self.print(log)?;
+            }
+        }
+        if errors > 0 {
+            return Err(Error::extension_errors(errors));
+        }
+        Ok(())
+    }
+
     pub fn report(self) -> Result<()> {
         RefCell::into_inner(self.logger).report()
     }
@@ -129,6 +175,8 @@ impl Context<BatchLogger> {
             name: Some("On the Origin of Burnt Toast".into()),
             version: Some(Version::latest()),
             warnings_as_errors: false,
+            repo_info: None,
+            search_path: Default::default(),
             doc_params: DocumentParameters::test_new(),
             lua_params: LuaParameters::test_new(),
             typesetter_params: TypesetterParameters::test_new(),
@@ -186,6 +234,7 @@ pub struct LuaParameters {
     sandbox_level: SandboxLevel,
     max_mem: ResourceLimit<Memory>,
     max_steps: ResourceLimit<Step>,
+    max_time: ResourceLimit<Time>,
     general_args: Option<Vec<(String, String)>>,
     modules: Vec<Module>,
 }
@@ -215,6 +264,14 @@ impl LuaParameters {
         self.max_steps
     }
 
+    pub fn set_max_time(&mut self, max_time: ResourceLimit<Time>) {
+        self.max_time = max_time;
+    }
+
+    pub fn max_time(&self) -> ResourceLimit<Time> {
+        self.max_time
+    }
+
     pub fn set_general_args(&mut self, general_args: Vec<(String, String)>) {
         self.general_args = Some(general_args);
     }
@@ -238,6 +295,7 @@ impl LuaParameters {
             sandbox_level: SandboxLevel::Strict,
             max_mem: ResourceLimit::Unlimited,
             max_steps: ResourceLimit::Unlimited,
+            max_time: ResourceLimit::Unlimited,
             general_args: None,
             modules: vec![],
         }
@@ -272,6 +330,7 @@ impl SandboxLevel {
 #[derive(Debug, Default)]
 pub struct TypesetterParameters {
     max_iters: ResourceLimit<Iteration>,
+    reiteration: Reiteration,
 }
 
 impl TypesetterParameters {
@@ -282,16 +341,36 @@ impl TypesetterParameters {
     pub fn set_max_iters(&mut self, max_iters: ResourceLimit<Iteration>) {
         self.max_iters = max_iters
     }
+
+    pub fn reiteration(&self) -> Reiteration {
+        self.reiteration
+    }
+
+    pub fn set_reiteration(&mut self, reiteration: Reiteration) {
+        self.reiteration = reiteration
+    }
 }
 
 impl TypesetterParameters {
     pub fn test_new() -> Self {
         Self {
             max_iters: ResourceLimit::Unlimited,
+            reiteration: Reiteration::OnRequest,
         }
     }
 }
 
+/// When the typesetting loop runs another iteration
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Reiteration {
+    /// Only when an extension requests it
+    #[default]
+    OnRequest,
+
+    /// When an extension requests it or the evaluated document changed in the last iteration
+    UntilConverged,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
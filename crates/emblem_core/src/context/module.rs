@@ -1,3 +1,4 @@
+use crate::context::{Memory, ResourceLimit, Step};
 use std::collections::HashMap;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -7,6 +8,8 @@ pub struct Module {
     rename_as: Option<String>,
     version: ModuleVersion,
     args: HashMap<String, String>,
+    max_mem: ResourceLimit<Memory>,
+    max_steps: ResourceLimit<Step>,
 }
 
 impl Module {
@@ -27,6 +30,8 @@ impl Module {
             rename_as,
             version,
             args,
+            max_mem: ResourceLimit::Unlimited,
+            max_steps: ResourceLimit::Unlimited,
         }
     }
 
@@ -42,6 +47,11 @@ impl Module {
         self.rename_as.as_deref()
     }
 
+    /// The name by which the document refers to this module.
+    pub fn local_name(&self) -> &str {
+        self.rename_as().unwrap_or(self.name())
+    }
+
     pub fn version(&self) -> &ModuleVersion {
         &self.version
     }
@@ -53,6 +63,24 @@ impl Module {
     pub fn args_mut(&mut self) -> &mut HashMap<String, String> {
         &mut self.args
     }
+
+    pub fn set_max_mem(&mut self, max_mem: ResourceLimit<Memory>) {
+        self.max_mem = max_mem;
+    }
+
+    /// The most memory this module may use, beyond which it is stopped.
+    pub fn max_mem(&self) -> ResourceLimit<Memory> {
+        self.max_mem
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: ResourceLimit<Step>) {
+        self.max_steps = max_steps;
+    }
+
+    /// The most steps this module may execute, beyond which it is stopped.
+    pub fn max_steps(&self) -> ResourceLimit<Step> {
+        self.max_steps
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -87,6 +115,8 @@ mod test {
         assert_eq!(rename, dep.rename_as().unwrap());
         assert_eq!(&version, dep.version());
         assert_eq!(&args, dep.args());
+        assert_eq!(ResourceLimit::Unlimited, dep.max_mem());
+        assert_eq!(ResourceLimit::Unlimited, dep.max_steps());
     }
 
     #[test]
@@ -116,6 +146,45 @@ mod test {
         );
     }
 
+    #[test]
+    fn local_name() {
+        let version = ModuleVersion::Tag("bar".into());
+        assert_eq!(
+            "some-repo",
+            Module::new(
+                "github.com/foo/some-repo".into(),
+                None,
+                version.clone(),
+                HashMap::new()
+            )
+            .local_name()
+        );
+        assert_eq!(
+            "renamed",
+            Module::new(
+                "github.com/foo/some-repo".into(),
+                Some("renamed".into()),
+                version,
+                HashMap::new()
+            )
+            .local_name()
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut module = Module::new(
+            ".".into(),
+            None,
+            ModuleVersion::Tag("bar".into()),
+            HashMap::new(),
+        );
+        module.set_max_mem(ResourceLimit::Limited(Memory(100)));
+        module.set_max_steps(ResourceLimit::Limited(Step(200)));
+        assert_eq!(ResourceLimit::Limited(Memory(100)), module.max_mem());
+        assert_eq!(ResourceLimit::Limited(Step(200)), module.max_steps());
+    }
+
     #[test]
     fn version() {
         let tag = ModuleVersion::Tag("bar".into());
@@ -18,20 +18,21 @@ impl<T: Resource> ResourceLimit<T> {
 
 impl<T: Resource> Default for ResourceLimit<T> {
     fn default() -> Self {
-        Self::Limited(T::default_limit())
+        T::default_limit().map_or(Self::Unlimited, Self::Limited)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::context::{Iteration, Memory, Step};
+    use crate::context::{Iteration, Memory, Step, Time};
 
     #[test]
     fn default() {
         test_default::<Step>();
         test_default::<Memory>();
         test_default::<Iteration>();
+        assert_eq!(ResourceLimit::<Time>::default(), ResourceLimit::Unlimited);
     }
 
     fn test_default<T: Resource>() {
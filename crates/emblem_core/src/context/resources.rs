@@ -22,7 +22,8 @@ pub trait Resource:
     + Sync
     + 'static
 {
-    fn default_limit() -> Self;
+    /// The limit placed on this resource unless another is given, if any.
+    fn default_limit() -> Option<Self>;
     fn max_value() -> u64;
 
     fn parse(raw: &str) -> Result<Self, Box<dyn Error>>
@@ -65,8 +66,8 @@ pub struct Step(pub u32);
 
 #[sealed]
 impl Resource for Step {
-    fn default_limit() -> Self {
-        Self(100_000)
+    fn default_limit() -> Option<Self> {
+        Some(Self(100_000))
     }
 
     fn max_value() -> u64 {
@@ -123,8 +124,8 @@ pub struct Memory(pub usize);
 
 #[sealed]
 impl Resource for Memory {
-    fn default_limit() -> Self {
-        Self(100_000)
+    fn default_limit() -> Option<Self> {
+        Some(Self(100_000))
     }
 
     fn max_value() -> u64 {
@@ -175,8 +176,8 @@ pub struct Iteration(pub u32);
 
 #[sealed]
 impl Resource for Iteration {
-    fn default_limit() -> Self {
-        Self(5)
+    fn default_limit() -> Option<Self> {
+        Some(Self(5))
     }
 
     fn max_value() -> u64 {
@@ -221,3 +222,89 @@ impl TryFrom<u64> for Iteration {
         Ok(Self(value.try_into()?))
     }
 }
+
+/// An amount of wall-clock time, in seconds.
+#[derive(Copy, Clone, Debug, Default, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Time(pub u32);
+
+#[sealed]
+impl Resource for Time {
+    fn default_limit() -> Option<Self> {
+        None
+    }
+
+    fn max_value() -> u64 {
+        u32::MAX as u64
+    }
+
+    fn parse(raw: &str) -> Result<Self, Box<dyn Error>> {
+        if raw.is_empty() {
+            return Err("need amount".into());
+        }
+
+        let max = Self::max_value();
+
+        let (raw_amt, unit): (String, String) = raw.chars().partition(|c| c.is_numeric());
+        let amt: u64 = raw_amt
+            .parse()
+            .map_err(|_| format!("resource limit too large, expected at most {max}",))?;
+
+        let multiplier: u64 = match &unit[..] {
+            "s" | "" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => return Err(format!("unrecognised unit: {}", unit).into()),
+        };
+
+        if let Some(limit) = amt.checked_mul(multiplier) {
+            return Ok(limit.try_into()?);
+        }
+
+        Err(format!("resource limit too large, expected at most {max}s").into())
+    }
+}
+
+impl Time {
+    pub fn as_std(&self) -> std::time::Duration {
+        let Self(s) = self;
+        std::time::Duration::from_secs(*s as u64)
+    }
+}
+
+impl Add<Self> for Time {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        let Self(l) = self;
+        let Self(r) = rhs;
+        Self(l + r)
+    }
+}
+
+impl AddAssign<Self> for Time {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(s) = self;
+        write!(f, "{s}s")
+    }
+}
+
+impl<'lua> ToLua<'lua> for Time {
+    fn to_lua(self, lua: &'lua mlua::Lua) -> mlua::Result<mlua::Value<'lua>> {
+        let Self(s) = self;
+        s.to_lua(lua)
+    }
+}
+
+impl TryFrom<u64> for Time {
+    type Error = TryFromIntError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Ok(Self(value.try_into()?))
+    }
+}
@@ -2,7 +2,12 @@ use std::{borrow::Cow, error::Error as StdError, fmt::Display, io};
 
 use camino::Utf8PathBuf;
 
-use crate::{log::LogId, parser::error::ParseError, FileName, Log};
+use crate::{
+    extensions::traceback,
+    log::{LogId, Note, Src},
+    parser::{error::ParseError, Location},
+    FileName, Log,
+};
 
 #[derive(Debug)]
 pub struct Error(Box<ErrorImpl>);
@@ -20,6 +25,56 @@ impl Error {
         })
     }
 
+    pub fn absolute_path(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(ErrorImpl::AbsolutePath { path })
+    }
+
+    pub fn command_failed(name: impl Into<String>, loc: Location, cause: Error) -> Self {
+        let name = name.into();
+        Self::new(ErrorImpl::CommandFailed { name, loc, cause })
+    }
+
+    pub fn corrupt_store(path: impl Into<Utf8PathBuf>, reason: impl Into<String>) -> Self {
+        let path = path.into();
+        let reason = reason.into();
+        Self::new(ErrorImpl::CorruptStore { path, reason })
+    }
+
+    pub fn dirty_repository(dir: impl Into<Utf8PathBuf>) -> Self {
+        let dir = dir.into();
+        Self::new(ErrorImpl::DirtyRepository { dir })
+    }
+
+    pub fn escaping_path(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(ErrorImpl::EscapingPath { path })
+    }
+
+    pub fn extension_errors(count: usize) -> Self {
+        Self::new(ErrorImpl::ExtensionErrors { count })
+    }
+
+    pub fn inaccessible_path(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(ErrorImpl::InaccessiblePath { path })
+    }
+
+    pub fn include_cycle(chain: Vec<Utf8PathBuf>) -> Self {
+        Self::new(ErrorImpl::IncludeCycle { chain })
+    }
+
+    pub fn invalid_driver_output(format: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::new(ErrorImpl::InvalidDriverOutput {
+            format: format.into(),
+            reason: reason.into(),
+        })
+    }
+
+    pub fn invalid_include(loc: Location, reason: &'static str) -> Self {
+        Self::new(ErrorImpl::InvalidInclude { loc, reason })
+    }
+
     pub fn io(path: impl Into<Utf8PathBuf>, cause: io::Error) -> Self {
         let path = path.into();
         Self::new(ErrorImpl::IO { path, cause })
@@ -29,10 +84,29 @@ impl Error {
         Self::new(ErrorImpl::NoSuchErrorCode(id))
     }
 
+    pub fn not_found_along_path(target: impl Into<Utf8PathBuf>, tried: Vec<Utf8PathBuf>) -> Self {
+        let target = target.into();
+        Self::new(ErrorImpl::NotFoundAlongPath { target, tried })
+    }
+
+    pub fn parent_path(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(ErrorImpl::ParentPath { path })
+    }
+
     pub fn parse(file_name: FileName, cause: ParseError) -> Self {
         Self::new(ErrorImpl::ParseError { file_name, cause })
     }
 
+    pub fn specs_failed(failed: usize, total: usize) -> Self {
+        Self::new(ErrorImpl::SpecsFailed { failed, total })
+    }
+
+    pub fn symlink_target(path: impl Into<Utf8PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(ErrorImpl::SymlinkTarget { path })
+    }
+
     pub fn too_many_errors(tot_errors: i32) -> Self {
         Self::new(ErrorImpl::TooManyErrors { tot_errors })
     }
@@ -40,6 +114,13 @@ impl Error {
     pub fn uncallable_listener(type_name: &'static str) -> Self {
         Self::new(ErrorImpl::UncallableListener { type_name })
     }
+
+    pub fn unknown_output_format(format: impl Into<String>, known: Vec<String>) -> Self {
+        Self::new(ErrorImpl::UnknownOutputFormat {
+            format: format.into(),
+            known,
+        })
+    }
 }
 
 impl<T: Into<ErrorImpl>> From<T> for Error {
@@ -66,6 +147,40 @@ impl Display for Error {
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, thiserror::Error)]
 enum ErrorImpl {
+    #[error("absolute paths are forbidden: got {path}")]
+    AbsolutePath { path: Utf8PathBuf },
+
+    #[error("failed to evaluate .{name} at {loc}: {cause}")]
+    CommandFailed {
+        name: String,
+        loc: Location,
+        cause: Error,
+    },
+
+    #[error("cannot read store {path}: {reason}")]
+    CorruptStore { path: Utf8PathBuf, reason: String },
+
+    #[error("refusing to build from repository with uncommitted changes at {dir}")]
+    DirtyRepository { dir: Utf8PathBuf },
+
+    #[error("{path} escapes the project root")]
+    EscapingPath { path: Utf8PathBuf },
+
+    #[error("extensions reported {count} {}", crate::util::plural(*.count, "error", "errors"))]
+    ExtensionErrors { count: usize },
+
+    #[error("{path} lies outside of the directories extensions may access")]
+    InaccessiblePath { path: Utf8PathBuf },
+
+    #[error("include cycle detected: {}", .chain.iter().map(|path| path.as_str()).collect::<Vec<_>>().join(" -> "))]
+    IncludeCycle { chain: Vec<Utf8PathBuf> },
+
+    #[error("output driver for {format} returned invalid output: {reason}")]
+    InvalidDriverOutput { format: String, reason: String },
+
+    #[error("{loc}: invalid include: {reason}")]
+    InvalidInclude { loc: Location, reason: &'static str },
+
     #[error("IO error accessing {path}: {cause}")]
     IO { path: Utf8PathBuf, cause: io::Error },
 
@@ -75,18 +190,36 @@ enum ErrorImpl {
     #[error("no such error code: {0}")]
     NoSuchErrorCode(LogId),
 
+    #[error("could not find {target}, looked in {}", .tried.iter().map(|dir| dir.as_str()).collect::<Vec<_>>().join(", "))]
+    NotFoundAlongPath {
+        target: Utf8PathBuf,
+        tried: Vec<Utf8PathBuf>,
+    },
+
+    #[error("parent directory references are forbidden: got {path}")]
+    ParentPath { path: Utf8PathBuf },
+
     #[error("cannot parse '{file_name}': {cause}")]
     ParseError {
         file_name: FileName,
         cause: ParseError,
     },
 
+    #[error("{failed} of {total} specs failed")]
+    SpecsFailed { failed: usize, total: usize },
+
+    #[error("refusing to write through symbolic link {path}")]
+    SymlinkTarget { path: Utf8PathBuf },
+
     #[error("run aborted after {tot_errors}")]
     TooManyErrors { tot_errors: i32 },
 
     #[error("{type_name} is not callable")]
     UncallableListener { type_name: &'static str },
 
+    #[error("no such output format: {format}")]
+    UnknownOutputFormat { format: String, known: Vec<String> },
+
     #[error("{context}: {cause}")]
     WithContext {
         context: Cow<'static, str>,
@@ -95,8 +228,55 @@ enum ErrorImpl {
 }
 
 impl From<Error> for Log {
-    fn from(_error: Error) -> Self {
-        unimplemented!()
+    fn from(error: Error) -> Self {
+        match *error.0 {
+            ErrorImpl::NotFoundAlongPath { target, tried } => {
+                Log::error(format!("could not find {target}"))
+                    .with_info(tried.iter().map(|dir| format!("looked in {dir}")).collect())
+            }
+            ErrorImpl::CommandFailed { name, loc, cause } => {
+                let mut log = Log::from(cause);
+                log.msg = format!("failed to evaluate .{name}: {}", log.msg);
+                log.with_src(Src::new(&loc).with_annotation(Note::error(&loc, "in this command")))
+            }
+            ErrorImpl::Lua(cause) => {
+                let (msg, frames) = traceback::describe(&cause);
+                let log = Log::error(msg);
+                if frames.is_empty() {
+                    return log;
+                }
+                log.with_info(
+                    frames
+                        .into_iter()
+                        .map(|frame| format!("at {frame}"))
+                        .collect(),
+                )
+            }
+            ErrorImpl::InvalidInclude { loc, reason } => Log::error("invalid include")
+                .with_src(Src::new(&loc).with_annotation(Note::error(&loc, reason))),
+            ErrorImpl::UnknownOutputFormat { format, known } => {
+                let log = Log::error(format!("no such output format: {format}"));
+                if known.is_empty() {
+                    return log.with_help("no extension has registered an output driver");
+                }
+                log.with_help(format!("expected one of: {}", known.join(", ")))
+            }
+            ErrorImpl::WithContext { context, cause } => {
+                let mut log = Log::from(cause);
+                log.msg = format!("{context}: {}", log.msg);
+                log
+            }
+            error => Log::error(error.to_string()),
+        }
+    }
+}
+
+impl From<Error> for mlua::Error {
+    fn from(error: Error) -> Self {
+        match *error.0 {
+            ErrorImpl::Lua(cause) => cause,
+            error => mlua::Error::RuntimeError(error.to_string()),
+        }
     }
 }
 
@@ -106,6 +286,135 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn absolute_path() {
+        assert_eq!(
+            Error::absolute_path("/etc/passwd").to_string(),
+            "absolute paths are forbidden: got /etc/passwd"
+        );
+    }
+
+    #[test]
+    fn command_failed() {
+        let ctx = Context::test_new();
+        let start = Point::at_start_of(
+            ctx.alloc_file_name("main.em"),
+            ctx.alloc_file_content(".figure{x}"),
+        );
+        let end = start.clone().shift(".figure{x}");
+        let loc = Location::new(&start, &end);
+
+        let err = || {
+            Error::command_failed(
+                "figure",
+                loc.clone(),
+                mlua::Error::RuntimeError(
+                    "figures.lua:3: no such image\nstack traceback:\n\t[C]: in function 'error'\n\tfigures.lua:3: in main chunk".into(),
+                )
+                .into(),
+            )
+        };
+        assert!(err()
+            .to_string()
+            .starts_with("failed to evaluate .figure at main.em:1:1-10: lua error: "));
+
+        let log = Log::from(err());
+        assert_eq!(
+            log.msg(),
+            "failed to evaluate .figure: figures.lua:3: no such image"
+        );
+        assert_eq!(
+            log.infos(),
+            Some(&["at figures.lua:3: in main chunk".to_owned()][..])
+        );
+        assert_eq!(log.srcs().len(), 1);
+        assert_eq!(log.srcs()[0].loc(), &loc);
+    }
+
+    #[test]
+    fn corrupt_store() {
+        assert_eq!(
+            Error::corrupt_store("out/.em-store.toml", "expected a table").to_string(),
+            "cannot read store out/.em-store.toml: expected a table"
+        );
+    }
+
+    #[test]
+    fn dirty_repository() {
+        assert_eq!(
+            Error::dirty_repository("docs/manual").to_string(),
+            "refusing to build from repository with uncommitted changes at docs/manual"
+        );
+    }
+
+    #[test]
+    fn escaping_path() {
+        assert_eq!(
+            Error::escaping_path("../secret.em").to_string(),
+            "../secret.em escapes the project root"
+        );
+    }
+
+    #[test]
+    fn extension_errors() {
+        assert_eq!(
+            Error::extension_errors(1).to_string(),
+            "extensions reported 1 error"
+        );
+        assert_eq!(
+            Error::extension_errors(3).to_string(),
+            "extensions reported 3 errors"
+        );
+    }
+
+    #[test]
+    fn inaccessible_path() {
+        assert_eq!(
+            Error::inaccessible_path("../secret").to_string(),
+            "../secret lies outside of the directories extensions may access"
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        assert_eq!(
+            Error::include_cycle(vec!["main.em".into(), "a.em".into(), "main.em".into()])
+                .to_string(),
+            "include cycle detected: main.em -> a.em -> main.em"
+        );
+    }
+
+    #[test]
+    fn invalid_driver_output() {
+        assert_eq!(
+            Error::invalid_driver_output("xml", "expected a table, got string").to_string(),
+            "output driver for xml returned invalid output: expected a table, got string"
+        );
+    }
+
+    #[test]
+    fn invalid_include() {
+        let ctx = Context::test_new();
+        let start = Point::at_start_of(
+            ctx.alloc_file_name("main.em"),
+            ctx.alloc_file_content(".include{}"),
+        );
+        let end = start.clone().shift(".include{}");
+        let loc = Location::new(&start, &end);
+
+        let err = || Error::invalid_include(loc.clone(), "expected a file name");
+        assert_eq!(
+            err().to_string(),
+            "main.em:1:1-10: invalid include: expected a file name"
+        );
+
+        let log = Log::from(err());
+        assert_eq!(log.msg(), "invalid include");
+        assert_eq!(log.srcs().len(), 1);
+        assert_eq!(log.srcs()[0].loc(), &loc);
+        assert_eq!(log.srcs()[0].annotations()[0].msg(), "expected a file name");
+    }
+
     #[test]
     fn io() {
         assert_eq!(
@@ -118,6 +427,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn lua() {
+        let traceback =
+            "figures.lua:3: no such image\nstack traceback:\n\tfigures.lua:3: in main chunk";
+        let err = Error::from(mlua::Error::RuntimeError(traceback.into()));
+        assert!(
+            matches!(mlua::Error::from(err), mlua::Error::RuntimeError(msg) if msg == traceback)
+        );
+
+        let err = Error::uncallable_listener("string");
+        assert!(
+            matches!(mlua::Error::from(err), mlua::Error::RuntimeError(msg) if msg == "string is not callable")
+        );
+    }
+
     #[test]
     fn no_such_error_code() {
         assert_eq!(
@@ -126,6 +450,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn not_found_along_path() {
+        let err = || Error::not_found_along_path("chapter.em", vec!["foo".into(), "bar".into()]);
+        assert_eq!(
+            err().to_string(),
+            "could not find chapter.em, looked in foo, bar"
+        );
+
+        let log = Log::from(err().context("cannot include chapter.em"));
+        assert_eq!(
+            log.msg(),
+            "cannot include chapter.em: could not find chapter.em"
+        );
+        assert_eq!(
+            log.infos(),
+            Some(&["looked in foo".to_owned(), "looked in bar".to_owned()][..])
+        );
+    }
+
+    #[test]
+    fn parent_path() {
+        assert_eq!(
+            Error::parent_path("../secret").to_string(),
+            "parent directory references are forbidden: got ../secret"
+        );
+    }
+
     #[test]
     fn parse() {
         let ctx = Context::test_new();
@@ -139,9 +490,38 @@ mod test {
         )
     }
 
+    #[test]
+    fn specs_failed() {
+        assert_eq!(Error::specs_failed(2, 5).to_string(), "2 of 5 specs failed");
+    }
+
+    #[test]
+    fn symlink_target() {
+        assert_eq!(
+            Error::symlink_target("out/table.csv").to_string(),
+            "refusing to write through symbolic link out/table.csv"
+        );
+    }
+
     #[test]
     fn uncallable_listener() {
         let err = Error::uncallable_listener("string");
         assert_eq!(err.to_string(), "string is not callable")
     }
+
+    #[test]
+    fn unknown_output_format() {
+        let err = |known| Error::unknown_output_format("xml", known);
+        assert_eq!(err(vec![]).to_string(), "no such output format: xml");
+
+        let log = Log::from(err(vec!["bb".into(), "html".into()]));
+        assert_eq!(log.msg(), "no such output format: xml");
+        assert_eq!(log.help(), &Some("expected one of: bb, html".into()));
+
+        let log = Log::from(err(vec![]));
+        assert_eq!(
+            log.help(),
+            &Some("no extension has registered an output driver".into())
+        );
+    }
 }
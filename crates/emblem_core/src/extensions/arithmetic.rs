@@ -0,0 +1,182 @@
+use super::{commands, doc::DocNode, logging};
+use crate::{
+    build::typesetter::doc::DocElem,
+    log::{Note, Src},
+    Log,
+};
+use mlua::{Lua, Result as MLuaResult, Table};
+
+type Args<'lua> = (Vec<DocNode>, Table<'lua>, DocNode);
+type BinaryOp = fn(f64, f64) -> f64;
+type UnaryOp = fn(f64) -> f64;
+
+/// The built-in commands which combine two numbers.
+const BINARY_OPS: [(&str, BinaryOp); 7] = [
+    ("add", |a, b| a + b),
+    ("sub", |a, b| a - b),
+    ("mul", |a, b| a * b),
+    ("div", |a, b| a / b),
+    (
+        "idiv",
+        |a, b| if b == 0.0 { a / b } else { (a / b).floor() },
+    ),
+    ("mod", |a, b| if b == 0.0 { a / b } else { a.rem_euclid(b) }),
+    ("pow", f64::powf),
+];
+
+/// The built-in commands which transform a single number.
+const UNARY_OPS: [(&str, UnaryOp); 2] = [
+    ("abs", f64::abs),
+    ("sign", |n| {
+        if n == 0.0 || n.is_nan() {
+            n
+        } else {
+            n.signum()
+        }
+    }),
+];
+
+/// Define the built-in commands which compute with numbers.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    for (name, op) in BINARY_OPS {
+        commands::define_builtin(
+            lua,
+            name,
+            lua.create_function(move |lua, (args, _, call): Args| {
+                if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+                    return Ok(None);
+                }
+                let (Some(a), Some(b)) =
+                    (number(lua, &call, &args[0])?, number(lua, &call, &args[1])?)
+                else {
+                    return Ok(None);
+                };
+                Ok(Some(op(a, b).to_string()))
+            })?,
+        )?;
+    }
+    for (name, op) in UNARY_OPS {
+        commands::define_builtin(
+            lua,
+            name,
+            lua.create_function(move |lua, (args, _, call): Args| {
+                if !commands::check_num_args(lua, &call, &args, 1..=1)? {
+                    return Ok(None);
+                }
+                Ok(number(lua, &call, &args[0])?.map(|n| op(n).to_string()))
+            })?,
+        )?;
+    }
+    Ok(())
+}
+
+/// The number written in `arg`, which was passed to the command `call`. If `arg` does not hold a
+/// number, an error is reported instead.
+pub(super) fn number(lua: &Lua, call: &DocNode, arg: &DocNode) -> MLuaResult<Option<f64>> {
+    let text = arg.with(DocElem::text)?;
+    if let Ok(n) = text.trim().parse() {
+        return Ok(Some(n));
+    }
+
+    let Some((name, call_loc)) = commands::call_site(call)? else {
+        return Ok(None);
+    };
+    let arg_loc = arg
+        .with(|elem| logging::first_loc(elem).cloned())?
+        .unwrap_or_else(|| call_loc.clone());
+    logging::report(
+        lua,
+        Log::error(format!("non-numeric argument passed to .{name}")).with_src(
+            Src::new(&call_loc).with_annotation(Note::error(
+                &arg_loc,
+                format!("expected a number, got ‘{text}’"),
+            )),
+        ),
+    );
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{build::typesetter::test::typeset, log::MessageType, Context, Result};
+
+    #[test]
+    fn operations() -> Result<()> {
+        for (src, expected) in [
+            (".add{4}{25}", "29"),
+            (".sub{4}{25}", "-21"),
+            (".mul{4}{25}", "100"),
+            (".mul{0.5}{3}", "1.5"),
+            (".div{1}{8}", "0.125"),
+            (".div{1}{0}", "inf"),
+            (".div{-1}{0}", "-inf"),
+            (".div{0}{0}", "NaN"),
+            (".idiv{7}{2}", "3"),
+            (".idiv{-7}{2}", "-4"),
+            (".idiv{1}{0}", "inf"),
+            (".mod{7}{3}", "1"),
+            (".mod{-7}{3}", "2"),
+            (".pow{2}{3}", "8"),
+            (".pow{2}{-3}", "0.125"),
+            (".pow{-3}{2}", "9"),
+            (".abs{-12}", "12"),
+            (".abs{12}", "12"),
+            (".sign{-12}", "-1"),
+            (".sign{0}", "0"),
+            (".sign{0.1}", "1"),
+            (".add{ 0001 }{.mul{2}{3}}", "7"),
+        ] {
+            let ctx = Context::test_new();
+            assert_eq!(
+                typeset(&ctx, "arithmetic.em", src)?,
+                format!("Word({expected})"),
+                "unexpected result of {src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn non_numeric() -> Result<()> {
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(&ctx, "arithmetic.em", ".add{4}{four}")
+                .unwrap_err()
+                .to_string(),
+            "extensions reported 1 error"
+        );
+
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg_type(), MessageType::Error);
+        assert_eq!(logs[0].msg(), "non-numeric argument passed to .add");
+        let src = &logs[0].srcs()[0];
+        assert_eq!(src.loc().to_string(), "arithmetic.em:1:1-4");
+        assert_eq!(
+            src.annotations()[0].loc().to_string(),
+            "arithmetic.em:1:9-12"
+        );
+        assert_eq!(src.annotations()[0].msg(), "expected a number, got ‘four’");
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_num_args() -> Result<()> {
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(&ctx, "arithmetic.em", ".abs{1}{2}")
+                .unwrap_err()
+                .to_string(),
+            "extensions reported 1 error"
+        );
+
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg(), "too many arguments passed to .abs");
+        assert_eq!(
+            logs[0].srcs()[0].annotations()[0].msg(),
+            "expected 1 argument"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+use super::ExtensionData;
+use crate::context::{Memory, Module, ResourceLimit, Step};
+use mlua::Lua;
+use std::collections::HashMap;
+
+/// The resources used by a module.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Usage {
+    steps: Step,
+    mem: Memory,
+}
+
+impl Usage {
+    /// The number of steps executed on behalf of the module.
+    pub fn steps(&self) -> Step {
+        self.steps
+    }
+
+    /// The approximate amount of memory held on behalf of the module.
+    pub fn mem(&self) -> Memory {
+        self.mem
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Budget {
+    max_mem: ResourceLimit<Memory>,
+    max_steps: ResourceLimit<Step>,
+}
+
+/// Tracks the resources used by each module. Steps are charged to the module whose code is
+/// running, as are any changes in memory usage, hence memory figures are approximate. Steps are
+/// counted as they are taken but only charged, and checked against budgets, from time to time.
+/// Submodules share the usage and budget of their top-level module.
+#[derive(Debug, Default)]
+pub(crate) struct Accounts {
+    budgets: HashMap<String, Budget>,
+    usage: HashMap<String, Usage>,
+    owners: Vec<String>,
+    last_mem: usize,
+    uncharged_steps: Step,
+}
+
+impl Accounts {
+    pub(crate) fn new(modules: &[Module]) -> Self {
+        let budgets = modules
+            .iter()
+            .map(|module| {
+                (
+                    module.local_name().to_owned(),
+                    Budget {
+                        max_mem: module.max_mem(),
+                        max_steps: module.max_steps(),
+                    },
+                )
+            })
+            .collect();
+        Self {
+            budgets,
+            ..Self::default()
+        }
+    }
+
+    /// The module whose code is currently running.
+    pub(crate) fn owner(&self) -> Option<&str> {
+        self.owners.last().map(String::as_str)
+    }
+
+    /// Describe `problem` as having occurred while the current module was running, if any.
+    pub(crate) fn blame(&self, problem: &str) -> String {
+        match self.owner() {
+            Some(owner) => format!("{problem} while running module {owner}"),
+            None => problem.into(),
+        }
+    }
+
+    /// Start charging resources to `owner`, given the current memory usage.
+    fn enter(&mut self, owner: String, mem: usize) {
+        self.charge_mem(mem);
+        self.charge_steps();
+        self.owners.push(owner);
+    }
+
+    /// Stop charging resources to the most recently entered module.
+    fn exit(&mut self, mem: usize) {
+        self.charge_mem(mem);
+        self.charge_steps();
+        self.owners.pop();
+    }
+
+    /// Count `steps` more steps taken by the current module, to be charged to it later.
+    pub(crate) fn count_steps(&mut self, steps: Step) {
+        self.uncharged_steps += steps;
+    }
+
+    /// Charge the current module for the steps it has taken and any change in memory usage.
+    /// Returns a description of the problem if the module has exceeded its budget.
+    pub(crate) fn charge(&mut self, mem: usize) -> Result<(), String> {
+        self.charge_mem(mem);
+        self.charge_steps();
+        let Some(owner) = self.owners.last() else {
+            return Ok(());
+        };
+        let owner = top_level(owner);
+        let usage = self.usage.entry(owner.to_owned()).or_default();
+
+        let Some(budget) = self.budgets.get(owner) else {
+            return Ok(());
+        };
+        if let ResourceLimit::Limited(max_mem) = budget.max_mem {
+            if usage.mem >= max_mem {
+                return Err(format!(
+                    "module {owner} used too much memory, limit is {max_mem}"
+                ));
+            }
+        }
+        if let ResourceLimit::Limited(max_steps) = budget.max_steps {
+            if usage.steps > max_steps {
+                return Err(format!(
+                    "module {owner} used too many steps, limit is {max_steps}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn charge_steps(&mut self) {
+        let steps = std::mem::take(&mut self.uncharged_steps);
+        if let Some(owner) = self.owners.last() {
+            self.usage
+                .entry(top_level(owner).to_owned())
+                .or_default()
+                .steps += steps;
+        }
+    }
+
+    fn charge_mem(&mut self, mem: usize) {
+        let delta = mem as isize - self.last_mem as isize;
+        self.last_mem = mem;
+        if let Some(owner) = self.owners.last() {
+            let usage = self.usage.entry(top_level(owner).to_owned()).or_default();
+            let Memory(used) = usage.mem;
+            usage.mem = Memory(used.saturating_add_signed(delta));
+        }
+    }
+
+    /// The resources used by each module which has run so far.
+    pub(crate) fn usage(&self) -> impl Iterator<Item = (&str, Usage)> {
+        self.usage
+            .iter()
+            .map(|(owner, usage)| (owner.as_str(), *usage))
+    }
+}
+
+/// The module whose code is currently running, if any.
+pub(crate) fn owner(lua: &Lua) -> Option<String> {
+    lua.app_data_ref::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .accounts
+        .owner()
+        .map(ToOwned::to_owned)
+}
+
+/// The top-level module whose code is currently running, if any. Submodules such as
+/// `diagrams.render` belong to the module `diagrams`.
+pub(crate) fn module(lua: &Lua) -> Option<String> {
+    owner(lua).map(|owner| top_level(&owner).to_owned())
+}
+
+/// The top-level module to which `owner` belongs.
+fn top_level(owner: &str) -> &str {
+    owner.split_once('.').map_or(owner, |(module, _)| module)
+}
+
+/// Run `f` on behalf of `owner`, charging the resources it uses to that module.
+pub(crate) fn charged_to<R>(lua: &Lua, owner: Option<String>, f: impl FnOnce() -> R) -> R {
+    let Some(owner) = owner else {
+        return f();
+    };
+
+    lua.app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .accounts
+        .enter(owner, lua.used_memory());
+    let ret = f();
+    lua.app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .accounts
+        .exit(lua.used_memory());
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        context::{Iteration, ModuleVersion},
+        extensions::Event,
+        Context, ExtensionState, Result,
+    };
+
+    fn module(
+        name: &str,
+        max_mem: ResourceLimit<Memory>,
+        max_steps: ResourceLimit<Step>,
+    ) -> Module {
+        let mut module = Module::new(
+            name.into(),
+            None,
+            ModuleVersion::Tag("v1".into()),
+            HashMap::new(),
+        );
+        module.set_max_mem(max_mem);
+        module.set_max_steps(max_steps);
+        module
+    }
+
+    #[test]
+    fn charging() {
+        let mut accounts = Accounts::new(&[
+            module(
+                "figures",
+                ResourceLimit::Unlimited,
+                ResourceLimit::Limited(Step(10)),
+            ),
+            module(
+                "tables",
+                ResourceLimit::Limited(Memory(100)),
+                ResourceLimit::Unlimited,
+            ),
+        ]);
+
+        accounts.count_steps(Step(1000));
+        assert_eq!(accounts.charge(1000), Ok(()));
+        assert_eq!(accounts.usage().count(), 0);
+
+        accounts.enter("figures".into(), 1000);
+        assert_eq!(accounts.owner(), Some("figures"));
+        accounts.count_steps(Step(5));
+        assert_eq!(accounts.charge(1050), Ok(()));
+        accounts.count_steps(Step(1));
+        accounts.enter("tables".into(), 1060);
+        assert_eq!(accounts.owner(), Some("tables"));
+        accounts.count_steps(Step(3));
+        assert_eq!(accounts.charge(1100), Ok(()));
+        accounts.count_steps(Step(3));
+        assert_eq!(
+            accounts.charge(1200),
+            Err("module tables used too much memory, limit is 100".into())
+        );
+        accounts.exit(1200);
+        assert_eq!(accounts.owner(), Some("figures"));
+        accounts.count_steps(Step(4));
+        assert_eq!(accounts.charge(1000), Ok(()));
+        accounts.count_steps(Step(1));
+        assert_eq!(
+            accounts.charge(1000),
+            Err("module figures used too many steps, limit is 10".into())
+        );
+        accounts.exit(1000);
+        assert_eq!(accounts.owner(), None);
+
+        let mut usage: Vec<_> = accounts.usage().collect();
+        usage.sort_by_key(|(owner, _)| *owner);
+        assert_eq!(
+            usage,
+            [
+                (
+                    "figures",
+                    Usage {
+                        steps: Step(11),
+                        mem: Memory(0),
+                    }
+                ),
+                (
+                    "tables",
+                    Usage {
+                        steps: Step(6),
+                        mem: Memory(140),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn submodules() {
+        let mut accounts = Accounts::new(&[module(
+            "figures",
+            ResourceLimit::Unlimited,
+            ResourceLimit::Limited(Step(10)),
+        )]);
+
+        accounts.enter("figures".into(), 0);
+        accounts.count_steps(Step(6));
+        assert_eq!(accounts.charge(0), Ok(()));
+        accounts.enter("figures.render".into(), 0);
+        assert_eq!(accounts.owner(), Some("figures.render"));
+        accounts.count_steps(Step(5));
+        assert_eq!(
+            accounts.charge(0),
+            Err("module figures used too many steps, limit is 10".into())
+        );
+        accounts.exit(0);
+        accounts.exit(0);
+
+        let usage: Vec<_> = accounts.usage().collect();
+        assert_eq!(
+            usage,
+            [(
+                "figures",
+                Usage {
+                    steps: Step(11),
+                    mem: Memory(0),
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn blame() {
+        let mut accounts = Accounts::default();
+        assert_eq!(accounts.blame("too many steps"), "too many steps");
+
+        accounts.enter("figures".into(), 0);
+        assert_eq!(
+            accounts.blame("too many steps"),
+            "too many steps while running module figures"
+        );
+    }
+
+    #[test]
+    fn attribution() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(
+            r#"
+                jit.off()
+                package.preload['figures'] = function()
+                    em.on('iter-start', function()
+                        local total = 0
+                        for i = 1, 100 do total = total + i end
+                    end)
+                    em.define('figure', function()
+                        local total = 0
+                        for i = 1, 100 do total = total + i end
+                        return 'fig'
+                    end)
+                end
+                package.preload['quiet'] = function() end
+                require('figures')
+                require('quiet')
+            "#,
+        )?;
+
+        let steps = |ext_state: &ExtensionState| -> Vec<(String, Step)> {
+            let mut steps: Vec<_> = ext_state
+                .usage()
+                .into_iter()
+                .map(|(owner, usage)| (owner, usage.steps()))
+                .collect();
+            steps.sort();
+            steps
+        };
+        let loaded = steps(ext_state);
+        assert_eq!(
+            loaded
+                .iter()
+                .map(|(owner, _)| &owner[..])
+                .collect::<Vec<_>>(),
+            ["figures", "quiet"]
+        );
+
+        ext_state.handle(Event::IterStart { iter: Iteration(1) })?;
+        let after_listener = steps(ext_state);
+        assert!(after_listener[0].1 > loaded[0].1);
+        assert_eq!(after_listener[1].1, loaded[1].1);
+
+        ext_state.run("em.on('iter-start', function() end)")?;
+        assert_eq!(steps(ext_state), after_listener);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use super::{
+    budgets, call, callable,
+    doc::{attrs_table, DocNode, DocRef},
+    logging, COMMANDS_RKEY, MODULE_COMMANDS_RKEY,
+};
+use crate::{
+    ast::parsed::Attrs,
+    build::typesetter::doc::DocElem,
+    context::file_content::FileSlice,
+    log::{Note, Src},
+    parser::Location,
+    util, Error, Log, Result,
+};
+use mlua::{
+    Error as MLuaError, FromLua, Function, Lua, Result as MLuaResult, Table, ToLuaMulti, Value,
+};
+use std::ops::RangeInclusive;
+
+/// Register `definition` as the implementation of the command `name`, as requested by a call to
+/// `em.define(name, definition)`. Commands defined by a module may also be invoked as
+/// `.module.name`.
+pub(crate) fn define<'lua>(
+    lua: &'lua Lua,
+    (name, definition): (String, Value<'lua>),
+) -> MLuaResult<()> {
+    if name.is_empty() || name.contains('.') {
+        return Err(MLuaError::RuntimeError(format!(
+            "invalid command name {name:?}, commands are qualified by the module which defines them"
+        )));
+    }
+    if !callable(&definition) {
+        return Err(MLuaError::RuntimeError(format!(
+            "cannot define .{name}: {} is not callable",
+            definition.type_name()
+        )));
+    }
+
+    let commands = match budgets::module(lua) {
+        Some(module) => {
+            let modules: Table = lua.named_registry_value(MODULE_COMMANDS_RKEY)?;
+            match modules.get::<_, Option<Table>>(module.as_str())? {
+                Some(commands) => commands,
+                None => {
+                    let commands = lua.create_table()?;
+                    modules.set(module, commands.clone())?;
+                    commands
+                }
+            }
+        }
+        None => lua.named_registry_value(COMMANDS_RKEY)?,
+    };
+
+    let entry = lua.create_table_with_capacity(0, 2)?;
+    entry.set("definition", definition)?;
+    entry.set("owner", budgets::owner(lua))?;
+    commands.set(name, entry)
+}
+
+/// Register `definition` as the built-in implementation of the command `name`. Built-in commands
+/// are charged to no module and may be replaced by extensions.
+pub(super) fn define_builtin(lua: &Lua, name: &str, definition: Function) -> MLuaResult<()> {
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    let entry = lua.create_table_with_capacity(0, 2)?;
+    entry.set("definition", definition)?;
+    entry.set("builtin", true)?;
+    commands.set(name, entry)
+}
+
+/// Register `definition` as the built-in implementation of the command `name`, which is passed
+/// its args unevaluated. It may then evaluate only those it needs, as many times as it needs.
+pub(super) fn define_lazy_builtin(lua: &Lua, name: &str, definition: Function) -> MLuaResult<()> {
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    let entry = lua.create_table_with_capacity(0, 3)?;
+    entry.set("definition", definition)?;
+    entry.set("lazy", true)?;
+    entry.set("builtin", true)?;
+    commands.set(name, entry)
+}
+
+/// Register `definition` as the implementation of the command `name`, defined by the document
+/// itself. Like a lazy built-in, it is passed its args unevaluated.
+pub(super) fn define_macro(lua: &Lua, name: &str, definition: Function) -> MLuaResult<()> {
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    let entry = lua.create_table_with_capacity(0, 2)?;
+    entry.set("definition", definition)?;
+    entry.set("lazy", true)?;
+    commands.set(name, entry)
+}
+
+/// Whether the unqualified command `name` is currently implemented by a built-in.
+pub(super) fn is_builtin(lua: &Lua, name: &str) -> MLuaResult<bool> {
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    match commands.get::<_, Option<Table>>(name)? {
+        Some(entry) => entry
+            .get::<_, Option<bool>>("builtin")
+            .map(|b| b.unwrap_or(false)),
+        None => Ok(false),
+    }
+}
+
+/// Remove the unqualified command `name`, whether built-in or defined by an extension, returning
+/// whether it was defined.
+pub(super) fn undefine(lua: &Lua, name: &str) -> MLuaResult<bool> {
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    let defined = commands.contains_key(name)?;
+    commands.set(name, Value::Nil)?;
+    Ok(defined)
+}
+
+/// Evaluate `arg`, an arg of a lazy command, returning a snapshot of its evaluated content.
+pub(super) fn evaluate_arg(lua: &Lua, arg: &DocNode) -> MLuaResult<DocNode> {
+    evaluate(lua, arg).map_err(|e| MLuaError::RuntimeError(e.to_string()))?;
+    Ok(DocNode::root(DocRef::new(arg.with(DocElem::evaluated)?)))
+}
+
+/// The name and location of the command `call`.
+pub(super) fn call_site(call: &DocNode) -> MLuaResult<Option<(String, Location)>> {
+    call.with(|elem| match elem {
+        DocElem::Command { name, loc, .. } => Some((name.as_str().to_owned(), loc.clone())),
+        _ => None,
+    })
+}
+
+/// Check that the number of args passed to a built-in command lies within `expected`,
+/// reporting an error at the call otherwise.
+pub(super) fn check_num_args(
+    lua: &Lua,
+    call: &DocNode,
+    args: &[DocNode],
+    expected: RangeInclusive<usize>,
+) -> MLuaResult<bool> {
+    if expected.contains(&args.len()) {
+        return Ok(true);
+    }
+    let Some((name, loc)) = call_site(call)? else {
+        return Ok(false);
+    };
+
+    let (min, max) = (*expected.start(), *expected.end());
+    let (problem, expectation) = if args.len() < min {
+        let bound = if min == max { "" } else { "at least " };
+        (
+            "few",
+            format!(
+                "expected {bound}{min} {}",
+                util::plural(min, "argument", "arguments")
+            ),
+        )
+    } else {
+        let bound = if min == max { "" } else { "at most " };
+        (
+            "many",
+            format!(
+                "expected {bound}{max} {}",
+                util::plural(max, "argument", "arguments")
+            ),
+        )
+    };
+    logging::report(
+        lua,
+        Log::error(format!("too {problem} arguments passed to .{name}"))
+            .with_src(Src::new(&loc).with_annotation(Note::info(&loc, expectation))),
+    );
+    Ok(false)
+}
+
+/// Find the entry for a command, which holds its `definition` and the module which defined it as
+/// its `owner`. Unqualified names prefer commands defined outside of any module, then fall back to
+/// the single module which defines that name.
+fn lookup<'lua>(
+    lua: &'lua Lua,
+    qualifier: Option<&str>,
+    name: &str,
+) -> MLuaResult<Option<Table<'lua>>> {
+    let modules: Table = lua.named_registry_value(MODULE_COMMANDS_RKEY)?;
+    if let Some(qualifier) = qualifier {
+        return match modules.get::<_, Option<Table>>(qualifier)? {
+            Some(commands) => commands.get(name),
+            None => Ok(None),
+        };
+    }
+
+    let commands: Table = lua.named_registry_value(COMMANDS_RKEY)?;
+    if let Some(entry) = commands.get(name)? {
+        return Ok(Some(entry));
+    }
+
+    let mut candidates = Vec::new();
+    for entry in modules.pairs::<String, Table>() {
+        let (module, commands) = entry?;
+        if let Some(entry) = commands.get::<_, Option<Table>>(name)? {
+            candidates.push((module, entry));
+        }
+    }
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(candidates.pop().map(|(_, entry)| entry)),
+        _ => {
+            let mut modules: Vec<_> = candidates.into_iter().map(|(module, _)| module).collect();
+            modules.sort();
+            Err(MLuaError::RuntimeError(format!(
+                ".{name} is ambiguous as it is defined by modules {}, qualify it as .{}.{name}",
+                modules.join(", "),
+                modules[0],
+            )))
+        }
+    }
+}
+
+/// Evaluate each defined command within `node`, innermost first, storing the content each
+/// returns as its result. The args of lazy commands are left for their definitions to evaluate.
+pub(crate) fn evaluate(lua: &Lua, node: &DocNode) -> Result<()> {
+    let call = node.with(|elem| match elem {
+        DocElem::Command {
+            qualifier,
+            name,
+            attrs,
+            loc,
+            ..
+        } => Some((
+            qualifier
+                .as_ref()
+                .map(|qualifier| qualifier.to_str().to_owned()),
+            name.as_str().to_owned(),
+            attrs.clone(),
+            loc.clone(),
+        )),
+        _ => None,
+    })?;
+    let Some((qualifier, name, attrs, loc)) = call else {
+        for child in node.children()? {
+            evaluate(lua, &child)?;
+        }
+        return Ok(());
+    };
+
+    let failed = |cause: Error| {
+        let qualified_name = match &qualifier {
+            Some(qualifier) => format!("{qualifier}.{name}"),
+            None => name.clone(),
+        };
+        Error::command_failed(qualified_name, loc.clone(), cause)
+    };
+    let entry = lookup(lua, qualifier.as_deref(), &name).map_err(|e| failed(e.into()))?;
+    let lazy = match &entry {
+        Some(entry) => entry
+            .get::<_, Option<bool>>("lazy")
+            .map_err(|e| failed(e.into()))?
+            .unwrap_or(false),
+        None => false,
+    };
+    if !lazy {
+        for child in node.children()? {
+            evaluate(lua, &child)?;
+        }
+    }
+
+    let Some(entry) = entry else {
+        return Ok(());
+    };
+    let result = invoke(lua, node, entry, lazy, attrs.as_ref()).map_err(|e| failed(e.into()))?;
+    node.with_mut(|elem| {
+        if let DocElem::Command { result: slot, .. } = elem {
+            *slot = Some(Box::new(result));
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Call the definition of a command, passing the args, the attrs and the node of the call
+/// itself. Args are evaluated unless the command is lazy, in which case the nodes of the args
+/// themselves are passed.
+fn invoke(
+    lua: &Lua,
+    node: &DocNode,
+    entry: Table,
+    lazy: bool,
+    attrs: Option<&Attrs>,
+) -> MLuaResult<DocElem> {
+    let definition = entry.get("definition")?;
+
+    let args: Vec<_> = if lazy {
+        node.children()?
+    } else {
+        node.with(|elem| {
+            elem.children()
+                .into_iter()
+                .flatten()
+                .map(|arg| DocNode::root(DocRef::new(arg.evaluated())))
+                .collect()
+        })?
+    };
+    let attrs = attrs_table(lua, attrs.map_or(&[], Attrs::args))?;
+
+    let args = (args, attrs, node.clone()).to_lua_multi(lua)?;
+    let result = budgets::charged_to(lua, entry.get("owner")?, || call(definition, args))?;
+    DocElem::from_lua(result, lua)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::AstDebug, extensions::DocRef, parser, Context, Doc, Log};
+
+    fn evaluated(ctx: &Context<impl crate::log::Logger>, src: &str) -> Result<String> {
+        let parsed = parser::parse(
+            ctx.alloc_file_name("define.em"),
+            ctx.alloc_file_content(src),
+        )?;
+        let doc = DocRef::new(Doc::from(parsed));
+        ctx.extension_state()?.evaluate(&doc)?;
+        let repr = doc.borrow().evaluated().repr();
+        Ok(repr)
+    }
+
+    #[test]
+    fn define() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.define('name', function() return 'world' end)
+                em.define('upper', function(args)
+                    local words = {}
+                    for _, word in ipairs(args[1].children) do
+                        words[#words + 1] = word.text:upper()
+                    end
+                    return words
+                end)
+                em.define('greet', function(_, attrs, call)
+                    assert(call.name == 'greet')
+                    return 'hi-' .. attrs[1] .. attrs.punct
+                end)
+            "#,
+        )?;
+
+        assert_eq!(
+            evaluated(&ctx, ".upper{hello .name}")?,
+            "[Word(HELLO)|Word(WORLD)]"
+        );
+        assert_eq!(
+            evaluated(&ctx, ".greet[Alice, punct=!]")?,
+            "Word(hi-Alice!)"
+        );
+        assert_eq!(evaluated(&ctx, ".unknown{.name}")?, ".unknown{Word(world)}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn callable_definitions() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.define('tab', setmetatable({}, {
+                    __call = function(self, args) return 'table' end,
+                }))
+            "#,
+        )?;
+
+        assert_eq!(evaluated(&ctx, ".tab")?, "Word(table)");
+
+        Ok(())
+    }
+
+    #[test]
+    fn qualified() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                package.preload['a'] = function()
+                    em.define('x', function() return 'a' end)
+                    require('a.extra')
+                end
+                package.preload['a.extra'] = function()
+                    em.define('y', function() return 'y' end)
+                end
+                package.preload['b'] = function()
+                    em.define('x', function() return 'b' end)
+                    em.define('z', function() return 'module' end)
+                end
+                em.define('z', function() return 'global' end)
+                require('a')
+                require('b')
+            "#,
+        )?;
+
+        assert_eq!(evaluated(&ctx, ".a.x{}")?, "Word(a)");
+        assert_eq!(evaluated(&ctx, ".b.x{}")?, "Word(b)");
+        assert_eq!(evaluated(&ctx, ".y{}")?, "Word(y)");
+        assert_eq!(evaluated(&ctx, ".a.y{}")?, "Word(y)");
+        assert_eq!(evaluated(&ctx, ".b.y{}")?, ".(b).y{[]}");
+        assert_eq!(evaluated(&ctx, ".z{}")?, "Word(global)");
+        assert_eq!(evaluated(&ctx, ".b.z{}")?, "Word(module)");
+
+        let err = evaluated(&ctx, ".x{}").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(".x is ambiguous as it is defined by modules a, b"),
+            "unexpected error: {err}"
+        );
+        assert!(
+            err.to_string()
+                .contains("failed to evaluate .x at define.em:1:1"),
+            "unexpected error: {err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn failure_location() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?
+            .lua()
+            .load(
+                r#"
+                    em.define('broken', function()
+                        error('cannot typeset this')
+                    end)
+                "#,
+            )
+            .set_name("broken.lua")?
+            .exec()?;
+
+        let err = evaluated(&ctx, "fine\n\nnot .broken{here}")
+            .unwrap_err()
+            .context("failed to handle iter-end event");
+        let log = Log::from(err);
+        assert_eq!(
+            log.msg(),
+            "failed to handle iter-end event: failed to evaluate .broken: broken.lua:3: cannot typeset this"
+        );
+        assert_eq!(
+            log.infos(),
+            Some(&["at broken.lua:3: in function <broken.lua:2>".to_owned()][..])
+        );
+        assert_eq!(log.srcs().len(), 1);
+        assert_eq!(log.srcs()[0].loc().to_string(), "define.em:3:5-11");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use super::{arithmetic, commands, doc::DocNode, logic};
+use crate::build::typesetter::doc::DocElem;
+use mlua::{Lua, Result as MLuaResult, Table};
+
+type Args<'lua> = (Vec<DocNode>, Table<'lua>, DocNode);
+type Comparison = fn(f64, f64) -> bool;
+
+/// The built-in commands which check that each consecutive pair of numbers is ordered.
+const INEQUALITIES: [(&str, Comparison); 4] = [
+    ("lt", |a, b| a < b),
+    ("le", |a, b| a <= b),
+    ("gt", |a, b| a > b),
+    ("ge", |a, b| a >= b),
+];
+
+/// Define the built-in commands which compare values.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    commands::define_builtin(
+        lua,
+        "eq",
+        lua.create_function(|lua, (args, _, call): Args| {
+            if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+                return Ok(None);
+            }
+            let a = args[0].with(DocElem::clone)?;
+            Ok(Some(logic::boolean(args[1].with(|b| a.same_as(b))?)))
+        })?,
+    )?;
+    commands::define_builtin(
+        lua,
+        "streq",
+        lua.create_function(|lua, (args, _, call): Args| {
+            if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+                return Ok(None);
+            }
+            let a = args[0].with(DocElem::text)?;
+            let b = args[1].with(DocElem::text)?;
+            Ok(Some(logic::boolean(a == b)))
+        })?,
+    )?;
+    commands::define_lazy_builtin(
+        lua,
+        "numeq",
+        lua.create_function(|lua, (args, _, call): Args| {
+            if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+                return Ok(None);
+            }
+            ordered(lua, &args, &call, |a, b| a == b)
+        })?,
+    )?;
+    for (name, cmp) in INEQUALITIES {
+        commands::define_lazy_builtin(
+            lua,
+            name,
+            lua.create_function(move |lua, (args, _, call): Args| {
+                if !commands::check_num_args(lua, &call, &args, 2..=usize::MAX)? {
+                    return Ok(None);
+                }
+                ordered(lua, &args, &call, cmp)
+            })?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether each consecutive pair of the numbers in `args`, the args of a lazy command, satisfies
+/// `cmp`, or `None` if an arg is not a number. Args are evaluated from left to right, stopping at
+/// the first pair which does not satisfy `cmp`.
+fn ordered(
+    lua: &Lua,
+    args: &[DocNode],
+    call: &DocNode,
+    cmp: Comparison,
+) -> MLuaResult<Option<&'static str>> {
+    let mut prev = None;
+    for arg in args {
+        let Some(curr) = arithmetic::number(lua, call, &commands::evaluate_arg(lua, arg)?)? else {
+            return Ok(None);
+        };
+        if prev.is_some_and(|prev| !cmp(prev, curr)) {
+            return Ok(Some(logic::boolean(false)));
+        }
+        prev = Some(curr);
+    }
+    Ok(Some(logic::boolean(true)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{build::typesetter::test::typeset, Context, Result};
+
+    #[test]
+    fn comparisons() -> Result<()> {
+        for (src, expected) in [
+            (".eq{}{}", "1"),
+            (".eq{hello, world!}{hello, world!}", "1"),
+            (
+                ".eq{something _emphasised_}{something .it{emphasised}}",
+                "1",
+            ),
+            (".eq{.it{two words}}{.it{two}{words}}", "0"),
+            (
+                ".streq{**something emphasised**}{.it{something emphasised}}",
+                "1",
+            ),
+            (".streq{.it{two words}}{.it{two}{words}}", "1"),
+            (".streq{hello}{world}", "0"),
+            (".numeq{1234}{000000000000000001234}", "1"),
+            (".numeq{1234}{4321}", "0"),
+            (".gt{123}{321}", "0"),
+            (".ge{123}{321}", "0"),
+            (".lt{123}{321}", "1"),
+            (".le{123}{321}", "1"),
+            (".gt{456}{456}", "0"),
+            (".ge{456}{456}", "1"),
+            (".lt{456}{456}", "0"),
+            (".le{456}{456}", "1"),
+            (".lt{1}{2}{3}", "1"),
+            (".lt{1}{3}{2}", "0"),
+            (".eq{.add{1}{1}}{2}", "1"),
+            (".lt{.add{1}{1}}{3}", "1"),
+        ] {
+            let ctx = Context::test_new();
+            assert_eq!(
+                typeset(&ctx, "comparison.em", src)?,
+                format!("Word({expected})"),
+                "unexpected result of {src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn halts_early() -> Result<()> {
+        for (src, expected, calls) in [
+            (".lt{0}{.count}{5}", "1", 1),
+            (".lt{2}{1}{.count}", "0", 0),
+            (".ge{1}{2}{.count}{.count}", "0", 0),
+            (".ge{5}{.count}{.count}{.count}", "0", 2),
+            (".numeq{1}{.count}", "1", 1),
+        ] {
+            let ctx = Context::test_new();
+            ctx.extension_state()?.run(
+                r#"
+                    calls = 0
+                    em.define('count', function()
+                        calls = calls + 1
+                        return tostring(calls)
+                    end)
+                "#,
+            )?;
+            assert_eq!(
+                typeset(&ctx, "comparison.em", src)?,
+                format!("Word({expected})"),
+                "unexpected result of {src}"
+            );
+            let actual: u32 = ctx.extension_state()?.lua().globals().get("calls")?;
+            assert_eq!(actual, calls, "unexpected number of calls in {src}");
+        }
+        Ok(())
+    }
+}
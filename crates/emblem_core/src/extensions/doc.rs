@@ -0,0 +1,483 @@
+use super::logging;
+use crate::{
+    ast::parsed::Attr,
+    build::typesetter::doc::DocElem,
+    context::file_content::{FileContent, FileSlice},
+    parser::Location,
+    Doc,
+};
+use mlua::{
+    Error as MLuaError, FromLua, Lua, MetaMethod, Result as MLuaResult, Table, UserData,
+    UserDataFields, UserDataMethods, Value,
+};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+};
+
+/// A shared document, which records the parts which have been replaced so that handles on their
+/// old contents can be recognised as stale.
+#[derive(Clone, Debug)]
+pub(crate) struct DocRef(Rc<SharedDoc>);
+
+#[derive(Debug)]
+struct SharedDoc {
+    doc: RefCell<Doc>,
+
+    /// The number of replacements made so far.
+    generation: Cell<u64>,
+
+    /// The generation in which the node at each route was last replaced.
+    replaced: RefCell<BTreeMap<Vec<usize>, u64>>,
+}
+
+impl DocRef {
+    pub(crate) fn new(doc: Doc) -> Self {
+        Self(Rc::new(SharedDoc {
+            doc: RefCell::new(doc),
+            generation: Cell::new(0),
+            replaced: RefCell::new(BTreeMap::new()),
+        }))
+    }
+
+    pub(crate) fn borrow(&self) -> Ref<'_, Doc> {
+        self.0.doc.borrow()
+    }
+
+    /// Take the document, leaving an empty one in its place.
+    pub(crate) fn take(&self) -> Doc {
+        self.0.doc.take()
+    }
+
+    fn generation(&self) -> u64 {
+        self.0.generation.get()
+    }
+
+    /// Record that the node at `path` has been replaced.
+    fn replaced(&self, path: &[usize]) {
+        let generation = self.generation() + 1;
+        self.0.generation.set(generation);
+        self.0
+            .replaced
+            .borrow_mut()
+            .insert(path.to_vec(), generation);
+    }
+
+    /// Whether the node at `path` lies within one which has been replaced since `generation`.
+    fn replaced_within(&self, path: &[usize], generation: u64) -> bool {
+        let replaced = self.0.replaced.borrow();
+        (0..path.len()).any(|len| {
+            replaced
+                .get(&path[..len])
+                .is_some_and(|replaced| *replaced > generation)
+        })
+    }
+}
+
+/// A handle on an element of a document, addressed by its route from the root. A handle becomes
+/// stale once any node which contains its element is replaced.
+#[derive(Clone, Debug)]
+pub(crate) struct DocNode {
+    doc: DocRef,
+    path: Vec<usize>,
+
+    /// The generation of the document in which this handle was made.
+    generation: u64,
+}
+
+impl DocNode {
+    pub(crate) fn root(doc: DocRef) -> Self {
+        Self::at(doc, vec![])
+    }
+
+    fn at(doc: DocRef, path: Vec<usize>) -> Self {
+        let generation = doc.generation();
+        Self {
+            doc,
+            path,
+            generation,
+        }
+    }
+
+    pub(super) fn with<R>(&self, f: impl FnOnce(&DocElem) -> R) -> MLuaResult<R> {
+        let doc = self
+            .doc
+            .0
+            .doc
+            .try_borrow()
+            .map_err(|_| MLuaError::RuntimeError("document is being modified".into()))?;
+        if self.doc.replaced_within(&self.path, self.generation) {
+            return Err(Self::stale());
+        }
+        let elem = self
+            .path
+            .iter()
+            .try_fold(&*doc, |elem, idx| elem.children()?.get(*idx))
+            .ok_or_else(Self::stale)?;
+        Ok(f(elem))
+    }
+
+    pub(super) fn with_mut<R>(&self, f: impl FnOnce(&mut DocElem) -> R) -> MLuaResult<R> {
+        let mut doc = self
+            .doc
+            .0
+            .doc
+            .try_borrow_mut()
+            .map_err(|_| MLuaError::RuntimeError("document is being read".into()))?;
+        if self.doc.replaced_within(&self.path, self.generation) {
+            return Err(Self::stale());
+        }
+        let elem = self
+            .path
+            .iter()
+            .try_fold(&mut *doc, |elem, idx| elem.children_mut()?.get_mut(*idx))
+            .ok_or_else(Self::stale)?;
+        Ok(f(elem))
+    }
+
+    /// Replace this node with `replacement`. Handles on the nodes it contained become stale.
+    pub(super) fn replace(&self, replacement: DocElem) -> MLuaResult<()> {
+        self.with_mut(|elem| *elem = replacement)?;
+        self.doc.replaced(&self.path);
+        Ok(())
+    }
+
+    fn stale() -> MLuaError {
+        MLuaError::RuntimeError("document node no longer exists".into())
+    }
+
+    fn child(&self, idx: usize) -> Self {
+        let mut path = self.path.clone();
+        path.push(idx);
+        Self::at(self.doc.clone(), path)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        let (_, path) = self.path.split_last()?;
+        Some(Self::at(self.doc.clone(), path.to_vec()))
+    }
+
+    pub(super) fn children(&self) -> MLuaResult<Vec<Self>> {
+        let len = self.with(|elem| elem.children().map_or(0, Vec::len))?;
+        Ok((0..len).map(|idx| self.child(idx)).collect())
+    }
+
+    pub(super) fn is_command(&self) -> MLuaResult<bool> {
+        self.with(|elem| matches!(elem, DocElem::Command { .. }))
+    }
+
+    /// The route from the root of the document to this node.
+    pub(super) fn path(&self) -> &[usize] {
+        &self.path
+    }
+
+    /// The nodes which contain this one, innermost first.
+    pub(super) fn ancestors(&self) -> impl Iterator<Item = Self> {
+        std::iter::successors(self.parent(), Self::parent)
+    }
+}
+
+impl UserData for DocNode {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("kind", |_, this| this.with(DocElem::kind_name));
+        fields.add_field_method_get("name", |_, this| {
+            this.with(|elem| match elem {
+                DocElem::Command { name, .. } => Some(name.as_str().to_owned()),
+                _ => None,
+            })
+        });
+        fields.add_field_method_get("qualifier", |_, this| {
+            this.with(|elem| match elem {
+                DocElem::Command { qualifier, .. } => qualifier
+                    .as_ref()
+                    .map(|qualifier| qualifier.to_str().to_owned()),
+                _ => None,
+            })
+        });
+        fields.add_field_method_get("plus", |_, this| {
+            this.with(|elem| match elem {
+                DocElem::Command { plus, .. } => Some(*plus),
+                _ => None,
+            })
+        });
+        fields.add_field_method_get("text", |_, this| {
+            this.with(|elem| match elem {
+                DocElem::Word { word, .. } => Some(word.to_str().to_owned()),
+                DocElem::Dash { dash, .. } => Some(dash.as_str().to_owned()),
+                DocElem::Glue { glue, .. } => Some(glue.as_str().to_owned()),
+                DocElem::Command { .. } | DocElem::Content(_) => None,
+            })
+        });
+        fields.add_field_method_get("attrs", |lua, this| {
+            let attrs = this.with(|elem| match elem {
+                DocElem::Command {
+                    attrs: Some(attrs), ..
+                } => Some(attrs.args().to_vec()),
+                _ => None,
+            })?;
+            attrs.map(|attrs| attrs_table(lua, &attrs)).transpose()
+        });
+        fields.add_field_method_get("args", |_, this| {
+            if this.is_command()? {
+                Ok(Some(this.children()?))
+            } else {
+                Ok(None)
+            }
+        });
+        fields.add_field_method_get("loc", |_, this| this.with(|elem| elem.loc().cloned()));
+        fields.add_field_method_get("parent", |_, this| Ok(this.parent()));
+        fields.add_field_method_get("children", |_, this| this.children());
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("replace", |lua, this, replacement: Value| {
+            let loc = this.with(|elem| logging::first_loc(elem).cloned().unwrap_or_default())?;
+            this.replace(replacement_at(lua, replacement, &loc)?)
+        });
+        methods.add_meta_method(MetaMethod::Eq, |_, this, other: DocNode| {
+            Ok(Rc::ptr_eq(&this.doc.0, &other.doc.0) && this.path == other.path)
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            this.with(|elem| match elem {
+                DocElem::Command { name, .. } => format!("<command .{}>", name.as_str()),
+                DocElem::Word { word, .. } => format!("<word {}>", word.to_str()),
+                DocElem::Dash { dash, .. } => format!("<dash {}>", dash.as_str()),
+                DocElem::Glue { glue, .. } => format!("<glue {}>", glue.as_str()),
+                DocElem::Content(content) => format!("<content ({} elements)>", content.len()),
+            })
+        });
+    }
+}
+
+pub(super) fn attrs_table<'lua>(lua: &'lua Lua, attrs: &[Attr]) -> MLuaResult<Table<'lua>> {
+    let table = lua.create_table()?;
+    for attr in attrs {
+        match attr {
+            Attr::Named { name, value, .. } => table.set(name.to_str(), value.to_str())?,
+            Attr::Unnamed { value, .. } => table.push(value.to_str())?,
+        }
+    }
+    Ok(table)
+}
+
+/// Convert `value` into an element to replace one at `loc`. As in the parser, strings are split
+/// into words at whitespace. New words take `loc` as their location.
+fn replacement_at<'lua>(lua: &'lua Lua, value: Value<'lua>, loc: &Location) -> MLuaResult<DocElem> {
+    match value {
+        Value::String(s) => {
+            let mut words: Vec<_> = s
+                .to_str()?
+                .split_whitespace()
+                .map(|word| DocElem::Word {
+                    word: FileContent::new(word).into(),
+                    loc: loc.clone(),
+                })
+                .collect();
+            match words.len() {
+                1 => Ok(words.remove(0)),
+                _ => Ok(DocElem::Content(words)),
+            }
+        }
+        Value::Table(t) => Ok(DocElem::Content(
+            t.sequence_values()
+                .map(|value| replacement_at(lua, value?, loc))
+                .collect::<MLuaResult<_>>()?,
+        )),
+        _ => DocElem::from_lua(value, lua),
+    }
+}
+
+impl<'lua> FromLua<'lua> for DocElem {
+    fn from_lua(value: Value<'lua>, _: &'lua Lua) -> MLuaResult<Self> {
+        match value {
+            Value::Nil => Ok(DocElem::Content(vec![])),
+            Value::String(s) => Ok(DocElem::Word {
+                word: FileContent::new(s.to_str()?).into(),
+                loc: Location::default(),
+            }),
+            Value::Table(t) => Ok(DocElem::Content(
+                t.sequence_values::<DocElem>().collect::<MLuaResult<_>>()?,
+            )),
+            Value::UserData(ref u) => match u.borrow::<DocNode>() {
+                Ok(node) => node.with(Clone::clone),
+                Err(_) => Err(MLuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "document node",
+                    message: None,
+                }),
+            },
+            _ => Err(MLuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "document node",
+                message: Some("expected a node, string, table or nil".into()),
+            }),
+        }
+    }
+}
+
+impl UserData for Location {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("file", |_, this| Ok(this.file_name().to_string()));
+        fields.add_field_method_get("line", |_, this| Ok(this.lines().0));
+        fields.add_field_method_get("col", |_, this| Ok(this.cols().0));
+        fields.add_field_method_get("end_line", |_, this| Ok(this.lines().1));
+        fields.add_field_method_get("end_col", |_, this| Ok(this.cols().1));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| Ok(this.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::AstDebug, parser, Context, Result};
+
+    fn doc_ref(src: &str) -> Result<DocRef> {
+        let ctx = Context::test_new();
+        let parsed = parser::parse(ctx.alloc_file_name("doc.em"), ctx.alloc_file_content(src))?;
+        Ok(DocRef::new(Doc::from(parsed)))
+    }
+
+    #[test]
+    fn traversal() -> Result<()> {
+        let doc = doc_ref(".sec[intro, level=2]{Hello}\n\nhello -- world\n")?;
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.lua().globals().set("doc", DocNode::root(doc))?;
+        ext_state.run(
+            r#"
+                assert(doc.kind == 'content', 'root kind was ' .. doc.kind)
+                assert(doc.parent == nil)
+                assert(#doc.children == 2)
+                assert(doc.args == nil)
+
+                local sec = doc.children[1]
+                assert(sec.kind == 'command')
+                assert(sec.name == 'sec')
+                assert(not sec.plus)
+                assert(sec.attrs[1] == 'intro')
+                assert(sec.attrs.level == '2')
+                assert(#sec.args == 1)
+                assert(sec.args[1].text == 'Hello')
+                assert(sec.args[1].parent == sec)
+                assert(sec.parent == doc)
+                assert(tostring(sec) == '<command .sec>')
+
+                assert(sec.loc.file == 'doc.em')
+                assert(sec.loc.line == 1)
+                assert(sec.loc.col == 1)
+                assert(tostring(sec.loc) == 'doc.em:1:1-4', tostring(sec.loc))
+
+                local p = doc.children[2]
+                assert(p.name == 'p')
+                local words = p.args[1].children
+                assert(#words == 3)
+                assert(words[1].kind == 'word' and words[1].text == 'hello')
+                assert(words[2].kind == 'dash' and words[2].text == '--')
+                assert(words[3].text == 'world')
+                assert(words[1].children[1] == nil)
+                assert(words[1].attrs == nil)
+            "#,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn replacement() -> Result<()> {
+        let doc = doc_ref(".sec{Hello}\n\nhello world\n")?;
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state
+            .lua()
+            .globals()
+            .set("doc", DocNode::root(doc.clone()))?;
+        ext_state.run(
+            r#"
+                local sec, p = doc.children[1], doc.children[2]
+                local words = p.args[1].children
+                local line = words[1].loc.line
+                words[1]:replace('goodbye')
+                words[2]:replace({ ' cruel\tcold ', sec.args[1], 'world' })
+                sec.args[1]:replace(nil)
+
+                assert(words[1].text == 'goodbye')
+                assert(words[2].kind == 'content')
+                assert(words[2].children[1].kind == 'content')
+                assert(words[2].children[2].text == 'Hello')
+                assert(words[1].loc.line == line and line ~= 0)
+                assert(words[2].children[1].children[2].loc.line == line)
+                assert(words[2].children[2].loc.line == 1)
+            "#,
+        )?;
+
+        assert_eq!(
+            doc.borrow().repr(),
+            "[.sec{[]}|.p{[Word(goodbye)|[[Word(cruel)|Word(cold)]|Word(Hello)|Word(world)]]}]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_nodes() -> Result<()> {
+        let doc = doc_ref(".sec{Hello}\n\nhello world\n")?;
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.lua().globals().set("doc", DocNode::root(doc))?;
+        let err = ext_state
+            .run(
+                r#"
+                    local hello = doc.children[1].args[1]
+                    doc.children[1]:replace('nothing')
+                    return hello.text
+                "#,
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("document node no longer exists"),
+            "unexpected error: {err}"
+        );
+
+        let err = ext_state.run("doc:replace(em)").unwrap_err();
+        assert!(
+            err.to_string().contains("document node"),
+            "unexpected error: {err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_after_same_shape_replacement() -> Result<()> {
+        let doc = doc_ref(".sec{Hello}\n\nhello world\n")?;
+        let root = DocNode::root(doc.clone());
+
+        let sec = root.children()?[0].clone();
+        let hello = sec.children()?[0].clone();
+        let replacement = sec.with(Clone::clone)?;
+        sec.replace(replacement)?;
+
+        assert!(
+            hello.with(|_| ()).is_err(),
+            "handle on replaced subtree not stale"
+        );
+        assert!(sec.with(|_| ()).is_ok(), "handle on replaced node stale");
+        assert_eq!(
+            sec.children()?[0].with(|elem| elem.text())?,
+            "Hello",
+            "fresh handle on replaced subtree stale"
+        );
+
+        let p = root.children()?[1].clone();
+        assert!(p.with(|_| ()).is_ok(), "handle on sibling stale");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+use super::{budgets, call, callable, doc::DocNode, DRIVERS_RKEY};
+use crate::{Error, Result};
+use mlua::{Error as MLuaError, Lua, Result as MLuaResult, Table, ToLuaMulti, Value};
+
+/// Register `driver` as the output driver for the format `name`, as requested by a call to
+/// `em.driver(name, driver)`. When the document is built with `-T name`, the driver is called
+/// with the final document and returns a table which maps the extension of each file to write
+/// to its contents.
+pub(crate) fn register<'lua>(
+    lua: &'lua Lua,
+    (name, driver): (String, Value<'lua>),
+) -> MLuaResult<()> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(MLuaError::RuntimeError(format!(
+            "invalid output format name {name:?}"
+        )));
+    }
+    if !callable(&driver) {
+        return Err(MLuaError::RuntimeError(format!(
+            "cannot register driver for {name}: {} is not callable",
+            driver.type_name()
+        )));
+    }
+
+    let entry = lua.create_table_with_capacity(0, 2)?;
+    entry.set("definition", driver)?;
+    entry.set("owner", budgets::owner(lua))?;
+    lua.named_registry_value::<_, Table>(DRIVERS_RKEY)?
+        .set(name, entry)
+}
+
+/// The names of the output formats for which drivers have been registered, in order.
+pub(crate) fn formats(lua: &Lua) -> Result<Vec<String>> {
+    let mut formats = lua
+        .named_registry_value::<_, Table>(DRIVERS_RKEY)?
+        .pairs::<String, Table>()
+        .map(|pair| pair.map(|(name, _)| name))
+        .collect::<MLuaResult<Vec<_>>>()?;
+    formats.sort();
+    Ok(formats)
+}
+
+/// The names of the modules which have registered output drivers, in order. Drivers registered by
+/// submodules such as `diagrams.svg` are counted as belonging to their top-level module, and
+/// those registered outside of any module are not counted.
+pub(crate) fn owners(lua: &Lua) -> Result<Vec<String>> {
+    let mut owners = lua
+        .named_registry_value::<_, Table>(DRIVERS_RKEY)?
+        .pairs::<String, Table>()
+        .map(|pair| pair.and_then(|(_, entry)| entry.get::<_, Option<String>>("owner")))
+        .filter_map(|owner| owner.transpose())
+        .map(|owner| {
+            owner.map(|owner| match owner.split_once('.') {
+                Some((module, _)) => module.to_owned(),
+                None => owner,
+            })
+        })
+        .collect::<MLuaResult<Vec<_>>>()?;
+    owners.sort();
+    owners.dedup();
+    Ok(owners)
+}
+
+/// Run the driver for `format` over `doc`, returning the extension and contents of each file to
+/// write, in order of extension. If no driver has been registered for `format`, `None` is
+/// returned.
+pub(crate) fn run(lua: &Lua, format: &str, doc: DocNode) -> Result<Option<Vec<(String, String)>>> {
+    let Some(entry) = lua
+        .named_registry_value::<_, Table>(DRIVERS_RKEY)?
+        .get::<_, Option<Table>>(format)?
+    else {
+        return Ok(None);
+    };
+
+    let args = doc.to_lua_multi(lua)?;
+    let output = budgets::charged_to(lua, entry.get("owner")?, || {
+        call(entry.get("definition")?, args)
+    })?;
+    let files = match output {
+        Value::Nil => return Ok(Some(vec![])),
+        Value::Table(files) => files,
+        other => {
+            return Err(Error::invalid_driver_output(
+                format,
+                format!("expected a table, got {}", other.type_name()),
+            ))
+        }
+    };
+
+    let mut written = Vec::new();
+    for pair in files.pairs::<Value, Value>() {
+        let (ext, contents) = pair?;
+        let (Value::String(ext), Value::String(contents)) = (&ext, &contents) else {
+            return Err(Error::invalid_driver_output(
+                format,
+                format!(
+                    "expected a table of strings to strings, got a {} key with a {} value",
+                    ext.type_name(),
+                    contents.type_name()
+                ),
+            ));
+        };
+        let ext = ext.to_str()?;
+        if ext.is_empty() || ext.contains(['/', '\\']) {
+            return Err(Error::invalid_driver_output(
+                format,
+                format!("invalid file extension {ext:?}"),
+            ));
+        }
+        written.push((ext.to_owned(), contents.to_str()?.to_owned()));
+    }
+    written.sort();
+    Ok(Some(written))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        build::typesetter::Typesetter,
+        context::{Module, ModuleVersion},
+        extensions::{DocNode, DocRef},
+        parser, Context, Result,
+    };
+    use camino::Utf8PathBuf;
+    use mlua::Table;
+    use std::{collections::HashMap, fs};
+
+    fn root(ctx: &Context<impl crate::log::Logger>, src: &str) -> Result<DocNode> {
+        let doc = Typesetter::new(ctx).typeset(parser::parse(
+            ctx.alloc_file_name("drivers.em"),
+            ctx.alloc_file_content(src),
+        )?)?;
+        Ok(DocNode::root(DocRef::new(doc)))
+    }
+
+    #[test]
+    fn formats() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        assert_eq!(ext_state.output_formats()?, Vec::<String>::new());
+
+        ext_state.run(
+            r#"
+                em.driver('xml', function() end)
+                em.driver('bb', setmetatable({}, { __call = function() end }))
+            "#,
+        )?;
+        assert_eq!(ext_state.output_formats()?, ["bb", "xml"]);
+        Ok(())
+    }
+
+    #[test]
+    fn owners() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        fs::write(
+            dir.join("diagrams.lua"),
+            "em.driver('svg', function() end); require('diagrams.png')",
+        )
+        .unwrap();
+        fs::create_dir(dir.join("diagrams")).unwrap();
+        fs::write(
+            dir.join("diagrams/png.lua"),
+            "em.driver('png', function() end)",
+        )
+        .unwrap();
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state
+            .lua()
+            .globals()
+            .get::<_, Table>("package")?
+            .set("path", format!("{dir}/?.lua"))?;
+        ext_state.run("em.driver('xml', function() end)")?;
+        ext_state.load_modules(&[Module::new(
+            "diagrams".into(),
+            None,
+            ModuleVersion::Tag("v1.0".into()),
+            HashMap::new(),
+        )])?;
+
+        assert_eq!(ext_state.output_formats()?, ["png", "svg", "xml"]);
+        assert_eq!(ext_state.output_extensions()?, ["diagrams"]);
+        Ok(())
+    }
+
+    #[test]
+    fn output() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(
+            r#"
+                local function words(node)
+                    if node.text then
+                        return '<w>' .. node.text .. '</w>'
+                    end
+                    local parts = {}
+                    for _, child in ipairs(node.children) do
+                        parts[#parts + 1] = words(child)
+                    end
+                    return table.concat(parts)
+                end
+
+                em.driver('xml', function(doc)
+                    return {
+                        xml = '<doc>' .. words(doc) .. '</doc>',
+                        ['xml.md5'] = 'not really',
+                    }
+                end)
+                em.driver('none', function() end)
+            "#,
+        )?;
+
+        let doc = root(&ctx, "hello\n\nworld\n")?;
+        assert_eq!(
+            super::run(ext_state.lua(), "xml", doc.clone())?,
+            Some(vec![
+                ("xml".into(), "<doc><w>hello</w><w>world</w></doc>".into()),
+                ("xml.md5".into(), "not really".into()),
+            ])
+        );
+        assert_eq!(
+            super::run(ext_state.lua(), "none", doc.clone())?,
+            Some(vec![])
+        );
+        assert_eq!(super::run(ext_state.lua(), "html", doc)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_output() -> Result<()> {
+        for (ret, expected) in [
+            ("'<doc/>'", "expected a table, got string"),
+            (
+                "{ '<doc/>' }",
+                "expected a table of strings to strings, got a integer key with a string value",
+            ),
+            (
+                "{ ['../xml'] = '<doc/>' }",
+                "invalid file extension \"../xml\"",
+            ),
+            ("{ [''] = '<doc/>' }", "invalid file extension \"\""),
+        ] {
+            let ctx = Context::test_new();
+            let ext_state = ctx.extension_state()?;
+            ext_state.run(&format!("em.driver('xml', function() return {ret} end)"))?;
+
+            let err = super::run(ext_state.lua(), "xml", root(&ctx, "hello")?).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("output driver for xml returned invalid output: {expected}"),
+                "unexpected error when returning {ret}"
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,122 @@
+use super::ExtensionData;
+use super::{
+    commands, drivers, fs, listeners,
+    logging::{self, Level},
+    store, vars,
+};
+use crate::{context::Context, log::Logger, RepoInfo};
+use camino::Utf8PathBuf;
 use derive_new::new;
 use mlua::{MetaMethod, UserData};
 
 #[derive(new)]
-pub(crate) struct Em {}
+pub(crate) struct Em {
+    repo: Option<RepoInfo>,
+    doc: DocInfo,
+}
 
 impl UserData for Em {
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
         fields.add_field_method_get("version", |lua, _| lua.create_userdata(Version::new()));
+        fields.add_field_method_get("repo", |_, this| Ok(this.repo.clone()));
+        fields.add_field_method_get("doc", |_, this| Ok(this.doc.clone()));
+        fields.add_field_function_get("build", |lua, _| {
+            Ok(lua
+                .app_data_ref::<ExtensionData>()
+                .expect("internal error: lua app data not set")
+                .build
+                .clone())
+        });
+        fields.add_field_function_get("define", |lua, _| lua.create_function(commands::define));
+        fields.add_field_function_get("on", |lua, _| lua.create_function(listeners::on));
+        fields.add_field_function_get("driver", |lua, _| lua.create_function(drivers::register));
+        fields.add_field_function_get("fs", |lua, _| fs::table(lua));
+        fields.add_field_function_get("vars", |lua, _| vars::table(lua));
+        fields.add_field_function_get("store", |lua, _| store::table(lua));
+        fields.add_field_function_get("error", |lua, _| {
+            lua.create_function(|lua, args| logging::log(lua, Level::Error, args))
+        });
+        fields.add_field_function_get("warn", |lua, _| {
+            lua.create_function(|lua, args| logging::log(lua, Level::Warning, args))
+        });
+        fields.add_field_function_get("info", |lua, _| {
+            lua.create_function(|lua, args| logging::log(lua, Level::Info, args))
+        });
+        fields.add_field_function_get("debug", |lua, _| {
+            lua.create_function(|lua, args| logging::log(lua, Level::Debug, args))
+        });
+    }
+}
+
+/// What extensions may know of the document being built, as declared in its manifest.
+#[derive(Clone, Debug)]
+pub(crate) struct DocInfo {
+    name: Option<String>,
+    authors: Option<Vec<String>>,
+    keywords: Option<Vec<String>>,
+    emblem_version: Option<&'static str>,
+}
+
+impl DocInfo {
+    pub(crate) fn new<L: Logger>(ctx: &Context<L>) -> Self {
+        let params = ctx.doc_params();
+        Self {
+            name: ctx.name().map(ToOwned::to_owned),
+            authors: params.authors().map(ToOwned::to_owned),
+            keywords: params.keywords().map(ToOwned::to_owned),
+            emblem_version: ctx.version().map(|version| version.as_str()),
+        }
+    }
+}
+
+impl UserData for DocInfo {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_, this| Ok(this.name.clone()));
+        fields.add_field_method_get("authors", |_, this| Ok(this.authors.clone()));
+        fields.add_field_method_get("keywords", |_, this| Ok(this.keywords.clone()));
+        fields.add_field_method_get("emblem_version", |_, this| Ok(this.emblem_version));
+    }
+}
+
+/// What extensions may know of the output being built.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BuildInfo {
+    output_format: Option<String>,
+    output_stem: Option<Utf8PathBuf>,
+}
+
+impl BuildInfo {
+    pub(crate) fn new(output_format: Option<String>, output_stem: Option<Utf8PathBuf>) -> Self {
+        Self {
+            output_format,
+            output_stem,
+        }
+    }
+}
+
+impl UserData for BuildInfo {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("output_format", |_, this| Ok(this.output_format.clone()));
+        fields.add_field_method_get("output_stem", |_, this| {
+            Ok(this.output_stem.as_ref().map(|stem| stem.to_string()))
+        });
+    }
+}
+
+impl UserData for RepoInfo {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("commit", |_, this| Ok(this.commit().map(ToOwned::to_owned)));
+        fields.add_field_method_get("dirty", |_, this| Ok(this.dirty()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!(
+                "<repo {}{}>",
+                this.commit().unwrap_or("(no commits)"),
+                if this.dirty() { " (dirty)" } else { "" }
+            ))
+        });
     }
 }
 
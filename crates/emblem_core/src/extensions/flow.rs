@@ -0,0 +1,261 @@
+use super::{arithmetic, commands, doc::DocNode, logging, logic, vars, ExtensionData};
+use crate::{
+    build::typesetter::doc::DocElem,
+    context::Step,
+    log::{Note, Src},
+    util, Log,
+};
+use mlua::{Lua, Result as MLuaResult, Table};
+
+type Args<'lua> = (Vec<DocNode>, Table<'lua>, DocNode);
+
+/// Define the built-in commands which control which parts of the document are evaluated, and
+/// how many times. Only the args these need are evaluated.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    commands::define_lazy_builtin(lua, "if", lua.create_function(if_command)?)?;
+    commands::define_lazy_builtin(lua, "case", lua.create_function(case_command)?)?;
+    commands::define_lazy_builtin(lua, "for", lua.create_function(for_command)?)?;
+    commands::define_lazy_builtin(lua, "foreach", lua.create_function(foreach_command)?)?;
+    commands::define_lazy_builtin(lua, "while", lua.create_function(while_command)?)
+}
+
+/// Choose a branch by a condition, as in `.if{condition}{then}{else}`.
+fn if_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<Option<DocNode>> {
+    if !commands::check_num_args(lua, &call, &args, 2..=3)? {
+        return Ok(None);
+    }
+    let branch = if logic::condition(lua, &args[0])? {
+        args.get(1)
+    } else {
+        args.get(2)
+    };
+    branch
+        .map(|branch| commands::evaluate_arg(lua, branch))
+        .transpose()
+}
+
+/// Choose a branch by its index, counting from 1, as in `.case{index}{first}{second}`. Indices
+/// out of range choose the last branch.
+fn case_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<Option<DocNode>> {
+    if !commands::check_num_args(lua, &call, &args, 2..=usize::MAX)? {
+        return Ok(None);
+    }
+    let index = commands::evaluate_arg(lua, &args[0])?;
+    let Some(index) = arithmetic::number(lua, &call, &index)? else {
+        return Ok(None);
+    };
+
+    let branches = &args[1..];
+    let chosen = if 1.0 <= index && index <= branches.len() as f64 {
+        index as usize - 1
+    } else {
+        branches.len() - 1
+    };
+    commands::evaluate_arg(lua, &branches[chosen]).map(Some)
+}
+
+/// Repeat a body, as in `.for{initialiser}{condition}{mutator}{body}`. The initialiser is
+/// evaluated first, then while the condition holds, the body is evaluated followed by the
+/// mutator.
+fn for_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<Option<Vec<DocNode>>> {
+    if !commands::check_num_args(lua, &call, &args, 4..=4)? {
+        return Ok(None);
+    }
+    let [init, cond, mutator, body] = &args[..] else {
+        unreachable!();
+    };
+
+    vars::clear_scope(lua, &call);
+    commands::evaluate_arg(lua, init)?;
+    let mut results = Vec::new();
+    while logic::condition(lua, cond)? {
+        if !step(lua, &call, results.len())? {
+            break;
+        }
+        results.push(commands::evaluate_arg(lua, body)?);
+        commands::evaluate_arg(lua, mutator)?;
+    }
+    Ok(Some(results))
+}
+
+/// Repeat a body for each of a space-separated list of values, as in
+/// `.foreach{name}{values}{body}`. The variable `name` holds each value in turn.
+fn foreach_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<Option<Vec<DocNode>>> {
+    if !commands::check_num_args(lua, &call, &args, 3..=3)? {
+        return Ok(None);
+    }
+    let name = commands::evaluate_arg(lua, &args[0])?.with(DocElem::text)?;
+    let values = commands::evaluate_arg(lua, &args[1])?.with(DocElem::text)?;
+
+    vars::clear_scope(lua, &call);
+    let mut results = Vec::new();
+    for value in values.split_whitespace() {
+        if !step(lua, &call, results.len())? {
+            break;
+        }
+        vars::set_within(lua, &call, name.trim(), value.to_owned())?;
+        results.push(commands::evaluate_arg(lua, &args[2])?);
+    }
+    Ok(Some(results))
+}
+
+/// Repeat a body while a condition holds, as in `.while{condition}{body}`.
+fn while_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<Option<Vec<DocNode>>> {
+    if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+        return Ok(None);
+    }
+
+    vars::clear_scope(lua, &call);
+    let mut results = Vec::new();
+    while logic::condition(lua, &args[0])? {
+        if !step(lua, &call, results.len())? {
+            break;
+        }
+        results.push(commands::evaluate_arg(lua, &args[1])?);
+    }
+    Ok(Some(results))
+}
+
+/// Count another iteration of the loop `call` against the limit on the steps extensions may
+/// take, which has completed `iters` iterations so far. If the limit is exceeded, an error is
+/// reported and `false` returned.
+fn step(lua: &Lua, call: &DocNode, iters: usize) -> MLuaResult<bool> {
+    let exceeded = lua
+        .app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .take_steps(Step(1));
+    if !exceeded {
+        return Ok(true);
+    }
+
+    if let Some((name, loc)) = commands::call_site(call)? {
+        logging::report(
+            lua,
+            Log::error(format!("too many steps taken by .{name}"))
+                .with_src(Src::new(&loc).with_annotation(Note::error(
+                    &loc,
+                    format!(
+                        "stopped after {iters} {}",
+                        util::plural(iters, "iteration", "iterations")
+                    ),
+                )))
+                .with_help("increase the step limit with --max-steps"),
+        );
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        build::typesetter::test::typeset,
+        context::{ResourceLimit, Step},
+        log::MessageType,
+        Context, Result,
+    };
+
+    #[test]
+    fn branches() -> Result<()> {
+        for (src, expected) in [
+            (".if{1}{yes}", "Word(yes)"),
+            (".if{0}{yes}", "[]"),
+            (".if{true}{yes}{no}", "Word(yes)"),
+            (".if{false}{yes}{no}", "Word(no)"),
+            (".if{.lt{1}{2}}{yes}{no}", "Word(yes)"),
+            (".case{2}{Hello}{World}{How are you?}", "Word(World)"),
+            (".case{.add{1}{2}}{Hello}{World}{Bye}", "Word(Bye)"),
+            (".case{-1}{Hello}{World}{Bye}", "Word(Bye)"),
+            (".case{12}{Hello}{World}{Bye}", "Word(Bye)"),
+        ] {
+            let ctx = Context::test_new();
+            assert_eq!(
+                typeset(&ctx, "flow.em", src)?,
+                expected,
+                "unexpected result of {src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn untaken_branches() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.define('boom', function() error('evaluated needlessly') end)
+            "#,
+        )?;
+        assert_eq!(
+            typeset(
+                &ctx,
+                "flow.em",
+                ".if{1}{yes}{.boom}\n\n.case{1}{first}{.boom}\n"
+            )?,
+            "[Word(yes)|Word(first)]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn loops() -> Result<()> {
+        for (src, expected) in [
+            (
+                ".for{.set-var{i}{2}}{.le{.get-var{i}}{6}}{.set-var{i}{.add{.get-var{i}}{2}}}{.get-var{i}}",
+                "[Word(2)|Word(4)|Word(6)]",
+            ),
+            (
+                ".foreach{x}{false true}{.foreach{y}{false true}{.get-var{x}~.get-var{y}}}",
+                "[[[Word(false)|~|Word(false)]|[Word(false)|~|Word(true)]]|[[Word(true)|~|Word(false)]|[Word(true)|~|Word(true)]]]",
+            ),
+            (
+                ".while{.lt{.get-var{n}}{3}}{.set-var{n}{.add{.get-var{n}}{1}}.get-var{n}}",
+                "[[[]|Word(1)]|[[]|Word(2)]|[[]|Word(3)]]",
+            ),
+            (".while{0}{never}", "[]"),
+        ] {
+            let ctx = Context::test_new();
+            let repr = typeset(&ctx, "flow.em", &format!(".set-var{{n}}{{0}}\n\n{src}\n"))?;
+            assert_eq!(
+                repr,
+                format!("[[]|{expected}]"),
+                "unexpected result of {src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn loops_restart() -> Result<()> {
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.typesetter_params_mut()
+                .set_reiteration(crate::Reiteration::UntilConverged);
+            ctx
+        };
+        let repr = typeset(
+            &ctx,
+            "flow.em",
+            ".set-var{n}{0}\n\n.while{.lt{.get-var{n}}{2}}{.set-var{n}{.add{.get-var{n}}{1}}x}\n",
+        )?;
+        assert_eq!(repr, "[[]|[[[]|Word(x)]|[[]|Word(x)]]]");
+        Ok(())
+    }
+
+    #[test]
+    fn steps_limited() -> Result<()> {
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.lua_params_mut()
+                .set_max_steps(ResourceLimit::Limited(Step(1000)));
+            ctx
+        };
+        typeset(&ctx, "flow.em", ".while{1}{forever}\n")?;
+
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg_type(), MessageType::Error);
+        assert_eq!(logs[0].msg(), "too many steps taken by .while");
+        assert_eq!(logs[0].srcs()[0].loc().to_string(), "flow.em:1:1-6");
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+use super::ExtensionData;
+use crate::{Error, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use mlua::{Lua, Result as MLuaResult, String as LuaString, Table};
+use std::fs;
+
+/// The directories which extensions may access through `em.fs`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Roots {
+    project: Option<Utf8PathBuf>,
+    output: Option<Utf8PathBuf>,
+    writable: bool,
+}
+
+impl Roots {
+    pub(crate) fn new(writable: bool) -> Self {
+        Self {
+            writable,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn set(&mut self, project: Utf8PathBuf, output: Option<Utf8PathBuf>) {
+        self.project = Some(project);
+        self.output = output;
+    }
+
+    /// Find the file referred to by `target`, relative to the output directory if it lies there
+    /// and to the project root otherwise. Absolute paths and references to parent directories are
+    /// forbidden and the file must lie within either the project root or the output directory.
+    /// Files to be written need not exist yet, but their directory must, and they may not be
+    /// symbolic links.
+    fn resolve(&self, target: &Utf8Path, for_writing: bool) -> Result<Utf8PathBuf> {
+        let path = self.base(target, for_writing)?.join(target);
+        let canonical = if for_writing {
+            let (Some(dir), Some(Utf8Component::Normal(name))) =
+                (path.parent(), target.components().next_back())
+            else {
+                return Err(Error::inaccessible_path(target));
+            };
+            dir.canonicalize_utf8()
+                .map_err(|e| Error::io(dir, e))?
+                .join(name)
+        } else {
+            path.canonicalize_utf8().map_err(|e| Error::io(&path, e))?
+        };
+
+        let accessible = [self.project.as_deref(), self.output.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|dir| dir.canonicalize_utf8().ok())
+            .any(|dir| canonical.starts_with(dir));
+        if !accessible {
+            return Err(Error::inaccessible_path(target));
+        }
+        if for_writing
+            && fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+        {
+            return Err(Error::symlink_target(target));
+        }
+        Ok(path)
+    }
+
+    /// The directory against which `target` is resolved, provided it is a path which extensions
+    /// may refer to. This is the output directory if `target` exists there or, when writing, if
+    /// its directory does, and the project root otherwise.
+    fn base(&self, target: &Utf8Path, for_writing: bool) -> Result<&Utf8Path> {
+        if target.is_absolute() {
+            return Err(Error::absolute_path(target));
+        }
+        if target
+            .components()
+            .any(|component| component == Utf8Component::ParentDir)
+        {
+            return Err(Error::parent_path(target));
+        }
+        let Some(project) = &self.project else {
+            return Err(Error::inaccessible_path(target));
+        };
+
+        let in_output = self.output.as_deref().filter(|output| {
+            let path = output.join(target);
+            if for_writing {
+                path.parent().is_some_and(Utf8Path::is_dir)
+            } else {
+                path.exists()
+            }
+        });
+        Ok(in_output.unwrap_or(project))
+    }
+}
+
+/// Create the `em.fs` table. Following the conventions of Lua's `io` library, each function
+/// returns `nil` and a message on failure.
+pub(crate) fn table(lua: &Lua) -> MLuaResult<Table<'_>> {
+    let fs = lua.create_table_with_capacity(0, 4)?;
+    fs.set("read", lua.create_function(read)?)?;
+    fs.set("write", lua.create_function(write)?)?;
+    fs.set("exists", lua.create_function(exists)?)?;
+    fs.set("find", lua.create_function(find)?)?;
+    Ok(fs)
+}
+
+fn roots(lua: &Lua) -> Roots {
+    lua.app_data_ref::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .fs_roots
+        .clone()
+}
+
+/// Read the contents of a file, as requested by a call to `em.fs.read(path)`.
+fn read(lua: &Lua, target: String) -> MLuaResult<(Option<LuaString<'_>>, Option<String>)> {
+    let contents = roots(lua)
+        .resolve(Utf8Path::new(&target), false)
+        .and_then(|path| fs::read(&path).map_err(|e| Error::io(path, e)));
+    match contents {
+        Ok(contents) => Ok((Some(lua.create_string(&contents)?), None)),
+        Err(e) => Ok((None, Some(e.to_string()))),
+    }
+}
+
+/// Write the contents of a file, as requested by a call to `em.fs.write(path, contents)`.
+fn write(
+    lua: &Lua,
+    (target, contents): (String, LuaString),
+) -> MLuaResult<(Option<bool>, Option<String>)> {
+    let roots = roots(lua);
+    if !roots.writable {
+        return Ok((
+            None,
+            Some("writing files is forbidden at this sandbox level".into()),
+        ));
+    }
+
+    let written = roots
+        .resolve(Utf8Path::new(&target), true)
+        .and_then(|path| fs::write(&path, contents.as_bytes()).map_err(|e| Error::io(path, e)));
+    match written {
+        Ok(()) => Ok((Some(true), None)),
+        Err(e) => Ok((None, Some(e.to_string()))),
+    }
+}
+
+/// Check whether an accessible file exists, as requested by a call to `em.fs.exists(path)`.
+fn exists(lua: &Lua, target: String) -> MLuaResult<bool> {
+    Ok(roots(lua)
+        .resolve(Utf8Path::new(&target), false)
+        .is_ok_and(|path| path.is_file()))
+}
+
+/// Find an accessible file, as requested by a call to `em.fs.find(path)`, returning its path
+/// relative to the working directory.
+fn find(lua: &Lua, target: String) -> MLuaResult<(Option<String>, Option<String>)> {
+    match roots(lua).resolve(Utf8Path::new(&target), false) {
+        Ok(path) => Ok((Some(path.into_string()), None)),
+        Err(e) => Ok((None, Some(e.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Context, SandboxLevel, SearchPath};
+
+    fn project() -> (tempfile::TempDir, Utf8PathBuf) {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        for dir in ["doc", "doc/out", "elsewhere"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("doc/table.csv"), "a,b\n1,2\n").unwrap();
+        fs::write(root.join("elsewhere/secret"), "hunter2").unwrap();
+        (tmpdir, root)
+    }
+
+    #[test]
+    fn resolve() -> Result<()> {
+        let (_tmpdir, root) = project();
+        let mut roots = Roots::new(true);
+        assert!(roots.resolve(Utf8Path::new("table.csv"), false).is_err());
+
+        roots.set(root.join("doc"), Some(root.join("doc/out")));
+        assert_eq!(
+            roots.resolve(Utf8Path::new("table.csv"), false)?,
+            root.join("doc/table.csv")
+        );
+        assert_eq!(
+            roots.resolve(Utf8Path::new("out/new.csv"), true)?,
+            root.join("doc/out/new.csv")
+        );
+
+        let err = roots
+            .resolve(&root.join("doc/table.csv"), false)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("absolute paths are forbidden"));
+        for target in [
+            "out/../table.csv",
+            "../elsewhere/secret",
+            "out/../../elsewhere/secret",
+        ] {
+            let err = roots.resolve(Utf8Path::new(target), false).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("parent directory references are forbidden: got {target}")
+            );
+        }
+        assert!(roots
+            .resolve(Utf8Path::new("../elsewhere/new"), true)
+            .is_err());
+        assert!(roots.resolve(Utf8Path::new("out/.."), true).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let (_tmpdir, root) = project();
+        symlink(root.join("elsewhere/secret"), root.join("doc/out/secret")).unwrap();
+        symlink(root.join("doc/table.csv"), root.join("doc/out/table.csv")).unwrap();
+        let mut roots = Roots::new(true);
+        roots.set(root.join("doc"), Some(root.join("doc/out")));
+
+        let err = roots
+            .resolve(Utf8Path::new("out/secret"), false)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "out/secret lies outside of the directories extensions may access"
+        );
+        assert_eq!(
+            roots.resolve(Utf8Path::new("out/table.csv"), false)?,
+            root.join("doc/out/table.csv")
+        );
+        for target in ["out/secret", "out/table.csv"] {
+            let err = roots.resolve(Utf8Path::new(target), true).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("refusing to write through symbolic link {target}")
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_dir() -> Result<()> {
+        let (_tmpdir, root) = project();
+        fs::create_dir(root.join("out")).unwrap();
+        fs::write(root.join("out/table.csv"), "c,d\n3,4\n").unwrap();
+        let mut roots = Roots::new(true);
+        roots.set(root.join("doc"), Some(root.join("out")));
+
+        assert_eq!(
+            roots.resolve(Utf8Path::new("table.csv"), false)?,
+            root.join("out/table.csv")
+        );
+        assert_eq!(
+            roots.resolve(Utf8Path::new("report.txt"), true)?,
+            root.join("out/report.txt")
+        );
+        assert_eq!(
+            roots.resolve(Utf8Path::new("out/report.txt"), true)?,
+            root.join("doc/out/report.txt")
+        );
+        assert!(roots.resolve(Utf8Path::new("report.txt"), false).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_path() -> Result<()> {
+        let (_tmpdir, root) = project();
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.set_search_path(SearchPath::from(vec![root.join("elsewhere")]));
+            ctx
+        };
+        let ext_state = ctx.extension_state()?;
+        ext_state.set_fs_roots(root.join("doc"), None);
+        ext_state.run(
+            r#"
+                assert(em.fs.find('table.csv'):match('/doc/table.csv$'))
+                assert(not em.fs.exists('secret'))
+
+                local contents, err = em.fs.read('secret')
+                assert(contents == nil)
+                assert(err:match('/doc/secret'), err)
+            "#,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn access() -> Result<()> {
+        let (_tmpdir, root) = project();
+        for level in SandboxLevel::input_levels() {
+            let ctx = {
+                let mut ctx = Context::test_new();
+                ctx.lua_params_mut().set_sandbox_level(level);
+                ctx
+            };
+            let ext_state = ctx.extension_state()?;
+            ext_state.set_fs_roots(root.join("doc"), Some(root.join("doc/out")));
+            ext_state.run(
+                r#"
+                    assert(em.fs.read('table.csv') == 'a,b\n1,2\n')
+                    assert(em.fs.exists('table.csv'))
+                    assert(not em.fs.exists('missing.csv'))
+                    assert(not em.fs.exists('../elsewhere/secret'))
+
+                    local contents, err = em.fs.read('../elsewhere/secret')
+                    assert(contents == nil)
+                    assert(err:match('parent directory references are forbidden'), err)
+                "#,
+            )?;
+
+            let writable = level <= SandboxLevel::Standard;
+            ext_state.lua().globals().set("writable", writable)?;
+            ext_state.run(
+                r#"
+                    local ok, err = em.fs.write('out/summary.txt', 'done')
+                    if writable then
+                        assert(ok, err)
+                        assert(em.fs.read('out/summary.txt') == 'done')
+                    else
+                        assert(not ok)
+                        assert(err == 'writing files is forbidden at this sandbox level', err)
+                    end
+                "#,
+            )?;
+            fs::remove_file(root.join("doc/out/summary.txt")).ok();
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+use super::{budgets, callable, EventKind, ExtensionData, EVENT_LISTENERS_RKEY};
+use crate::{Error, Result};
+use mlua::{
+    Error as MLuaError, Integer, Lua, MetaMethod, Result as MLuaResult, Table, UserData,
+    UserDataFields, UserDataMethods, Value,
+};
+
+/// How a listener should be run.
+#[derive(Clone, Debug, Default)]
+pub struct ListenerOptions {
+    /// Listeners with higher priority are called first. Listeners with equal priority are called
+    /// in the order in which they were added.
+    priority: Integer,
+
+    /// Whether to remove the listener after it has been called once.
+    once: bool,
+}
+
+impl ListenerOptions {
+    pub fn with_priority(mut self, priority: Integer) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_once(mut self, once: bool) -> Self {
+        self.once = once;
+        self
+    }
+}
+
+/// A reference to a registered listener, through which it may be removed.
+#[derive(Clone, Debug)]
+pub struct ListenerHandle {
+    kind: EventKind,
+    id: Integer,
+}
+
+impl ListenerHandle {
+    pub(super) fn new(kind: EventKind, id: Integer) -> Self {
+        Self { kind, id }
+    }
+
+    /// Stop the listener from being called. Returns whether it was still registered.
+    pub(crate) fn remove(&self, lua: &Lua) -> Result<bool> {
+        let entries = entries(lua, self.kind)?;
+        for (idx, entry) in entries.clone().sequence_values::<Table>().enumerate() {
+            let entry = entry?;
+            if entry.get::<_, Integer>("id")? == self.id {
+                entry.set("removed", true)?;
+                entries.raw_remove(idx as Integer + 1)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl UserData for ListenerHandle {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("event", |_, this| Ok(this.kind.name()));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("remove", |lua, this, ()| {
+            this.remove(lua)
+                .map_err(|e| MLuaError::RuntimeError(e.to_string()))
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(format!("<listener {} #{}>", this.kind.name(), this.id))
+        });
+    }
+}
+
+/// Register a listener for events of the given kind.
+pub(crate) fn add(
+    lua: &Lua,
+    kind: EventKind,
+    listener: Value,
+    options: &ListenerOptions,
+) -> Result<ListenerHandle> {
+    if !callable(&listener) {
+        return Err(Error::uncallable_listener(listener.type_name()));
+    }
+
+    let id = {
+        let mut data = lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set");
+        data.listeners_added += 1;
+        data.listeners_added
+    };
+
+    let entry = lua.create_table_with_capacity(0, 5)?;
+    entry.set("listener", listener)?;
+    entry.set("priority", options.priority)?;
+    entry.set("once", options.once)?;
+    entry.set("id", id)?;
+    entry.set("owner", budgets::owner(lua))?;
+
+    let entries = entries(lua, kind)?;
+    let mut pos = entries.raw_len() + 1;
+    for (idx, other) in entries.clone().sequence_values::<Table>().enumerate() {
+        if other?.get::<_, Integer>("priority")? < options.priority {
+            pos = idx as Integer + 1;
+            break;
+        }
+    }
+    entries.raw_insert(pos, entry)?;
+
+    Ok(ListenerHandle::new(kind, id))
+}
+
+/// The listeners to call for an event of the given kind, in the order they should be called.
+pub(crate) fn entries(lua: &Lua, kind: EventKind) -> Result<Table<'_>> {
+    let listeners: Table = lua.named_registry_value(EVENT_LISTENERS_RKEY)?;
+    match listeners.get::<_, Option<Table>>(kind.name())? {
+        Some(entries) => Ok(entries),
+        None => panic!("internal error: {kind} event has no listener table"),
+    }
+}
+
+/// Register a listener, as requested by a call to `em.on(event, listener, opts)`.
+pub(crate) fn on<'lua>(
+    lua: &'lua Lua,
+    (event, listener, opts): (String, Value<'lua>, Option<Table<'lua>>),
+) -> MLuaResult<ListenerHandle> {
+    let Some(kind) = EventKind::from_name(&event) else {
+        return Err(MLuaError::RuntimeError(format!(
+            "unknown event {event:?}, expected one of: {}",
+            EventKind::all()
+                .iter()
+                .map(EventKind::name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    };
+
+    let mut options = ListenerOptions::default();
+    if let Some(opts) = opts {
+        if let Some(priority) = opts.get("priority")? {
+            options = options.with_priority(priority);
+        }
+        if let Some(once) = opts.get("once")? {
+            options = options.with_once(once);
+        }
+    }
+
+    add(lua, kind, listener, &options).map_err(|e| MLuaError::RuntimeError(e.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::Iteration, extensions::Event, Context};
+
+    fn fire(ctx: &Context<impl crate::log::Logger>, iter: u32) -> Result<Vec<String>> {
+        let ext_state = ctx.extension_state()?;
+        ext_state.run("calls = {}")?;
+        ext_state.handle(Event::IterEnd {
+            iter: Iteration(iter),
+        })?;
+        Ok(ext_state.lua().globals().get("calls")?)
+    }
+
+    #[test]
+    fn priority() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                local function record(name)
+                    return function() table.insert(calls, name) end
+                end
+                em.on('iter-end', record('default-1'))
+                em.on('iter-end', record('low'), { priority = -10 })
+                em.on('iter-end', record('high'), { priority = 10 })
+                em.on('iter-end', record('default-2'))
+                em.on('iter-end', record('higher'), { priority = 20 })
+            "#,
+        )?;
+
+        assert_eq!(
+            fire(&ctx, 1)?,
+            ["higher", "high", "default-1", "default-2", "low"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn once() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.on('iter-end', function(event) table.insert(calls, 'once ' .. event.iter) end, { once = true })
+                em.on('iter-end', function(event) table.insert(calls, 'always ' .. event.iter) end)
+            "#,
+        )?;
+
+        assert_eq!(fire(&ctx, 1)?, ["once 1", "always 1"]);
+        assert_eq!(fire(&ctx, 2)?, ["always 2"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn removal() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                local later
+                em.on('iter-end', function()
+                    table.insert(calls, 'first')
+                    assert(later:remove())
+                    assert(not later:remove())
+                end, { once = true })
+                later = em.on('iter-end', function() table.insert(calls, 'later') end)
+                assert(later.event == 'iter-end')
+                assert(tostring(later) == '<listener iter-end #2>', tostring(later))
+
+                local removed = em.on('iter-start', function() table.insert(calls, 'removed') end)
+                assert(removed:remove())
+            "#,
+        )?;
+
+        assert_eq!(fire(&ctx, 1)?, ["first"]);
+        assert_eq!(fire(&ctx, 2)?, Vec::<String>::new());
+
+        let ext_state = ctx.extension_state()?;
+        ext_state.handle(Event::IterStart { iter: Iteration(1) })?;
+        assert_eq!(
+            ext_state.lua().globals().get::<_, Vec<String>>("calls")?,
+            Vec::<String>::new()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use super::{doc::DocNode, ExtensionData};
+use crate::{
+    build::typesetter::doc::DocElem,
+    log::{Note, Src},
+    parser::Location,
+    Log,
+};
+use mlua::{Error as MLuaError, Lua, Result as MLuaResult, Table, Value};
+
+/// The severity of a message logged by an extension.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn log(&self, msg: String) -> Log {
+        match self {
+            Self::Error => Log::error(msg),
+            Self::Warning => Log::warning(msg),
+            Self::Info => Log::info(msg),
+            Self::Debug => Log::debug(msg),
+        }
+    }
+
+    fn note(&self, loc: &Location, msg: &str) -> Note {
+        match self {
+            Self::Error => Note::error(loc, msg),
+            Self::Warning => Note::warn(loc, msg),
+            Self::Info | Self::Debug => Note::info(loc, msg),
+        }
+    }
+}
+
+/// Record a message to be printed on behalf of an extension, as requested by a call to
+/// `em.error(msg, opts)` or similar. The `opts` table may contain `at`, a node or location to
+/// point to, `label`, text to show there, and `help` and `note` text.
+pub(crate) fn log<'lua>(
+    lua: &'lua Lua,
+    level: Level,
+    (msg, opts): (String, Option<Table<'lua>>),
+) -> MLuaResult<()> {
+    let mut log = level.log(msg);
+    if let Some(opts) = opts {
+        if let Some(loc) = location(opts.get("at")?)? {
+            let label: Option<String> = opts.get("label")?;
+            let mut src = Src::new(&loc);
+            if let Some(label) = label {
+                src = src.with_annotation(level.note(&loc, &label));
+            }
+            log = log.with_src(src);
+        }
+        if let Some(help) = opts.get::<_, Option<String>>("help")? {
+            log = log.with_help(help);
+        }
+        if let Some(note) = opts.get::<_, Option<String>>("note")? {
+            log = log.with_note(note);
+        }
+    }
+
+    push(lua, log);
+    Ok(())
+}
+
+/// Record a problem found while evaluating a built-in command.
+pub(crate) fn report(lua: &Lua, log: Log) {
+    push(lua, log);
+}
+
+fn push(lua: &Lua, log: Log) {
+    lua.app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .logs
+        .push(log);
+}
+
+/// Find the location referred to by a node or location.
+fn location(at: Value) -> MLuaResult<Option<Location>> {
+    match at {
+        Value::Nil => Ok(None),
+        Value::UserData(ref u) => {
+            if let Ok(loc) = u.borrow::<Location>() {
+                return Ok(Some(loc.clone()));
+            }
+            if let Ok(node) = u.borrow::<DocNode>() {
+                return node.with(|elem| first_loc(elem).cloned());
+            }
+            Err(MLuaError::RuntimeError(
+                "expected a node or location to point to".into(),
+            ))
+        }
+        _ => Err(MLuaError::RuntimeError(format!(
+            "expected a node or location to point to, got {}",
+            at.type_name()
+        ))),
+    }
+}
+
+pub(super) fn first_loc(elem: &DocElem) -> Option<&Location> {
+    elem.loc()
+        .or_else(|| elem.children()?.iter().find_map(first_loc))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        build::typesetter::Typesetter,
+        log::{BatchLogger, MessageType},
+        parser, util, Context, Log, Result, Verbosity,
+    };
+
+    fn logs(verbosity: Verbosity, warnings_as_errors: bool) -> Result<Vec<Log>> {
+        let ctx = Context::new(BatchLogger::new(verbosity)).warnings_as_errors(warnings_as_errors);
+        ctx.extension_state()?.run(
+            r#"
+                em.on('iter-start', function(event)
+                    local cite = event.doc.children[2].args[1].children[2]
+                    em.error('unknown citation key', { at = cite, label = 'this key', help = 'check the bibliography' })
+                    em.warn('citation style is deprecated', { note = 'use numeric instead', at = cite.loc })
+                    em.info('formatting citations')
+                    em.debug('4 citations found')
+                end)
+            "#,
+        )?;
+
+        let err = Typesetter::new(&ctx)
+            .typeset(parser::parse(
+                ctx.alloc_file_name("logs.em"),
+                ctx.alloc_file_content("hello\n\nsee .cite[knuth]\n"),
+            )?)
+            .unwrap_err();
+        let errors = if warnings_as_errors { 2 } else { 1 };
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "extensions reported {errors} {}",
+                util::plural(errors, "error", "errors")
+            )
+        );
+
+        let logs = ctx.logger().logs().to_vec();
+        Ok(logs)
+    }
+
+    #[test]
+    fn levels() -> Result<()> {
+        let logs = logs(Verbosity::Debug, false)?;
+        assert_eq!(
+            logs.iter().map(|log| log.msg()).collect::<Vec<_>>(),
+            [
+                "unknown citation key",
+                "citation style is deprecated",
+                "formatting citations",
+                "4 citations found"
+            ]
+        );
+
+        let error = &logs[0];
+        assert_eq!(error.msg_type(), MessageType::Error);
+        assert_eq!(error.help().as_deref(), Some("check the bibliography"));
+        assert_eq!(error.srcs().len(), 1);
+        assert_eq!(error.srcs()[0].loc().to_string(), "logs.em:3:5-9");
+        assert_eq!(
+            error.srcs()[0].annotation_text(),
+            ["logs.em:3:5-9: this key"]
+        );
+
+        let warning = &logs[1];
+        assert_eq!(warning.msg_type(), MessageType::Warning);
+        assert_eq!(warning.note().as_deref(), Some("use numeric instead"));
+        assert_eq!(warning.srcs()[0].annotations().len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verbosity() -> Result<()> {
+        let count = |verbosity| -> Result<usize> { Ok(logs(verbosity, false)?.len()) };
+        assert_eq!(count(Verbosity::Terse)?, 2);
+        assert_eq!(count(Verbosity::Verbose)?, 4);
+        assert_eq!(count(Verbosity::Debug)?, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn warnings_as_errors() -> Result<()> {
+        let logs = logs(Verbosity::Terse, true)?;
+        assert!(logs.iter().all(|log| log.msg_type() == MessageType::Error));
+
+        Ok(())
+    }
+}
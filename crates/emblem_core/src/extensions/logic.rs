@@ -0,0 +1,135 @@
+use super::{commands, doc::DocNode};
+use mlua::{Lua, Result as MLuaResult, Table};
+
+type Args<'lua> = (Vec<DocNode>, Table<'lua>, DocNode);
+
+/// Whether the condition written as `text` holds. Conditions which are empty, zero or `false`
+/// do not hold, all others do.
+pub(super) fn holds(text: &str) -> bool {
+    let text = text.trim();
+    !(text.is_empty()
+        || text.eq_ignore_ascii_case("false")
+        || text.parse::<f64>().is_ok_and(|n| n == 0.0))
+}
+
+/// Write a boolean as it would be in a document.
+pub(super) fn boolean(b: bool) -> &'static str {
+    if b {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+/// Evaluate the condition `arg`, an arg of a lazy command, returning whether it holds.
+pub(super) fn condition(lua: &Lua, arg: &DocNode) -> MLuaResult<bool> {
+    commands::evaluate_arg(lua, arg)?.with(|elem| holds(&elem.text()))
+}
+
+/// Define the built-in commands which combine conditions. Each evaluates only as many of its
+/// conditions as are needed to find its result.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    commands::define_lazy_builtin(
+        lua,
+        "not",
+        lua.create_function(|lua, (args, _, call): Args| {
+            if !commands::check_num_args(lua, &call, &args, 1..=1)? {
+                return Ok(None);
+            }
+            Ok(Some(boolean(!condition(lua, &args[0])?)))
+        })?,
+    )?;
+    commands::define_lazy_builtin(
+        lua,
+        "xor",
+        lua.create_function(|lua, (args, _, call): Args| {
+            if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+                return Ok(None);
+            }
+            Ok(Some(boolean(
+                condition(lua, &args[0])? != condition(lua, &args[1])?,
+            )))
+        })?,
+    )?;
+    commands::define_lazy_builtin(
+        lua,
+        "any",
+        lua.create_function(|lua, (args, _, _): Args| {
+            for arg in &args {
+                if condition(lua, arg)? {
+                    return Ok(boolean(true));
+                }
+            }
+            Ok(boolean(false))
+        })?,
+    )?;
+    commands::define_lazy_builtin(
+        lua,
+        "all",
+        lua.create_function(|lua, (args, _, _): Args| {
+            for arg in &args {
+                if !condition(lua, arg)? {
+                    return Ok(boolean(false));
+                }
+            }
+            Ok(boolean(true))
+        })?,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{build::typesetter::test::typeset, Context, Result};
+
+    #[test]
+    fn holds() {
+        for text in ["1", "true", "yes", "-0.5", " 2 "] {
+            assert!(super::holds(text), "{text:?} should hold");
+        }
+        for text in ["", " ", "0", "0.0", "-0", "false", "FALSE"] {
+            assert!(!super::holds(text), "{text:?} should not hold");
+        }
+    }
+
+    #[test]
+    fn commands() -> Result<()> {
+        for (src, expected) in [
+            (".not{0}", true),
+            (".not{1}", false),
+            (".xor{0}{0}", false),
+            (".xor{0}{1}", true),
+            (".xor{1}{0}", true),
+            (".xor{1}{1}", false),
+            (".any{true}{true}{false}", true),
+            (".any{false}{false}", false),
+            (".any", false),
+            (".all{true}{true}{true}", true),
+            (".all{true}{true}{false}", false),
+            (".all", true),
+        ] {
+            let ctx = Context::test_new();
+            assert_eq!(
+                typeset(&ctx, "logic.em", src)?,
+                format!("Word({})", boolean(expected)),
+                "unexpected result of {src}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn laziness() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.define('boom', function() error('evaluated needlessly') end)
+            "#,
+        )?;
+        assert_eq!(
+            typeset(&ctx, "logic.em", ".any{1}{.boom}\n\n.all{0}{.boom}\n")?,
+            "[Word(1)|Word(0)]"
+        );
+        Ok(())
+    }
+}
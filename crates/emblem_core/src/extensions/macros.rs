@@ -0,0 +1,245 @@
+use super::{commands, doc::DocNode, logging, vars};
+use crate::{
+    build::typesetter::doc::DocElem,
+    log::{Note, Src},
+    parser::Location,
+    Log,
+};
+use mlua::{Lua, Result as MLuaResult, Table};
+
+type Args<'lua> = (Vec<DocNode>, Table<'lua>, DocNode);
+
+/// Define the built-in commands through which documents define commands of their own.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    commands::define_lazy_builtin(lua, "def", lua.create_function(def_command)?)?;
+    commands::define_builtin(lua, "undef", lua.create_function(undef_command)?)
+}
+
+/// Define a command by its body, as in `.def{name}{body}`. When the new command is called, the
+/// text of its args is held in the variables `1`, `2` and so on, which are visible only within its
+/// body. As with any variable, markup within the args is not kept.
+fn def_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<()> {
+    if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+        return Ok(());
+    }
+    let name = commands::evaluate_arg(lua, &args[0])?.with(DocElem::text)?;
+    let name = name.trim();
+    if !valid_name(name) {
+        if let Some((loc, name_loc)) = name_site(&call, &args[0])? {
+            logging::report(
+                lua,
+                Log::error("invalid command name passed to .def").with_src(
+                    Src::new(&loc).with_annotation(Note::error(
+                        &name_loc,
+                        format!("expected a name without spaces or dots, got ‘{name}’"),
+                    )),
+                ),
+            );
+        }
+        return Ok(());
+    }
+    if commands::is_builtin(lua, name)? {
+        if let Some((loc, name_loc)) = name_site(&call, &args[0])? {
+            logging::report(
+                lua,
+                Log::warning(format!("redefining built-in command .{name}")).with_src(
+                    Src::new(&loc).with_annotation(Note::warn(
+                        &name_loc,
+                        "the built-in definition is no longer available",
+                    )),
+                ),
+            );
+        }
+    }
+
+    let body = args[1].with(Clone::clone)?;
+    commands::define_macro(
+        lua,
+        name,
+        lua.create_function(move |lua, (args, _, call): Args| expand(lua, &body, &args, &call))?,
+    )
+}
+
+/// Remove a command, as in `.undef{name}`. Unknown commands are ignored.
+fn undef_command(lua: &Lua, (args, _, call): Args) -> MLuaResult<()> {
+    if !commands::check_num_args(lua, &call, &args, 1..=1)? {
+        return Ok(());
+    }
+    commands::undefine(lua, args[0].with(DocElem::text)?.trim())?;
+    Ok(())
+}
+
+/// The locations of the `.def` call `call` and of the name passed to it as `name_arg`.
+fn name_site(call: &DocNode, name_arg: &DocNode) -> MLuaResult<Option<(Location, Location)>> {
+    let Some((_, loc)) = commands::call_site(call)? else {
+        return Ok(None);
+    };
+    let name_loc = name_arg
+        .with(|elem| logging::first_loc(elem).cloned())?
+        .unwrap_or_else(|| loc.clone());
+    Ok(Some((loc, name_loc)))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| c == '.' || c.is_whitespace())
+}
+
+/// Evaluate a copy of `body`, the definition of the command `call`. The body is evaluated as
+/// though it were a final arg of the call, so that it lies within the scope which holds the
+/// values of `args` and any variables it sets.
+fn expand(lua: &Lua, body: &DocElem, args: &[DocNode], call: &DocNode) -> MLuaResult<DocNode> {
+    vars::clear_scope(lua, call);
+    let values = args
+        .iter()
+        .map(|arg| commands::evaluate_arg(lua, arg)?.with(DocElem::text))
+        .collect::<MLuaResult<Vec<_>>>()?;
+    for (idx, value) in values.into_iter().enumerate() {
+        vars::set_within(lua, call, &(idx + 1).to_string(), value)?;
+    }
+
+    call.with_mut(|elem| elem.children_mut().map(|args| args.push(body.clone())))?;
+    let expansion = match call.children()?.last() {
+        Some(body) => commands::evaluate_arg(lua, body),
+        None => unreachable!("internal error: body of .def'd command not added"),
+    };
+    call.with_mut(|elem| elem.children_mut().map(Vec::pop))?;
+    expansion
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{build::typesetter::test::typeset, log::MessageType, Context, Result};
+
+    #[test]
+    fn expansion() -> Result<()> {
+        for (src, expected) in [
+            (".def{greet}{Hello}\n\n.greet\n", "[[]|Word(Hello)]"),
+            (
+                ".def{greet}{Hello .get-var{1}}\n\n.greet{world}\n",
+                "[[]|[Word(Hello)|Word(world)]]",
+            ),
+            (
+                ".def{double}{.mul{.get-var{1}}{2}}\n\n.double{.double{3}}\n",
+                "[[]|Word(12)]",
+            ),
+            (
+                ".def{swap}{.get-var{2}.get-var{1}}\n\n.swap{a}{b}\n",
+                "[[]|[Word(b)|Word(a)]]",
+            ),
+            (
+                ".def{bool}{.if{.any{.streq{.get-var{1}}{true}}{.streq{.get-var{1}}{false}}}{.get-var{1}}{?}}\n\n.bool{true}\n\n.bool{maybe}\n",
+                "[[]|Word(true)|Word(?)]",
+            ),
+            (".undef{greet}\n\n.greet\n", "[[]|.greet]"),
+            (
+                ".def{greet}{Hello}\n\n.undef{greet}\n\n.greet\n",
+                "[[]|[]|.greet]",
+            ),
+        ] {
+            let ctx = Context::test_new();
+            assert_eq!(typeset(&ctx, "macros.em", src)?, expected, "unexpected result of {src}");
+            assert_eq!(ctx.logger().logs().to_vec(), vec![], "unexpected logs from {src}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn args_as_text() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "macros.em",
+            ".def{greet}{Hello .get-var{1}}\n\n.greet{_dear_ .it{world}}\n",
+        )?;
+        assert_eq!(repr, "[[]|[Word(Hello)|Word(dear world)]]");
+        Ok(())
+    }
+
+    #[test]
+    fn hygiene() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "macros.em",
+            concat!(
+                ".set-var{x}{outer}\n\n",
+                ".def{shadow}{.set-var{x}{inner}.get-var{x}.get-var{\\!x}}\n\n",
+                ".def{outer}{.shadow{.get-var{1}}}\n\n",
+                ".shadow\n\n",
+                ".outer{arg}\n\n",
+                ".get-var{x}\n\n",
+                ".get-var{1}\n",
+            ),
+        )?;
+        assert_eq!(
+            repr,
+            "[[]|[]|[]|[[]|Word(inner)|Word(outer)]|[[]|Word(inner)|Word(outer)]|Word(outer)|[]]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_args() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "macros.em",
+            ".def{inner}{.get-var{1}~.get-var{\\!1}}\n\n.def{outer}{.inner{y}}\n\n.outer{x}\n",
+        )?;
+        assert_eq!(repr, "[[]|[]|[Word(y)|~|Word(x)]]");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_name() -> Result<()> {
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(&ctx, "macros.em", ".def{two words}{body}")
+                .unwrap_err()
+                .to_string(),
+            "extensions reported 1 error"
+        );
+
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg_type(), MessageType::Error);
+        assert_eq!(logs[0].msg(), "invalid command name passed to .def");
+        assert_eq!(
+            logs[0].srcs()[0].annotations()[0].msg(),
+            "expected a name without spaces or dots, got ‘two words’"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builtin_names() -> Result<()> {
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(&ctx, "macros.em", ".def{streq}{same}\n\n.streq{a}{b}\n")?,
+            "[[]|Word(same)]"
+        );
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg_type(), MessageType::Warning);
+        assert_eq!(logs[0].msg(), "redefining built-in command .streq");
+
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(
+                &ctx,
+                "macros.em",
+                ".def{greet}{Hello}\n\n.def{greet}{Hi}\n\n.greet\n"
+            )?,
+            "[[]|[]|Word(Hi)]"
+        );
+        assert_eq!(ctx.logger().logs().to_vec(), vec![]);
+
+        let ctx = Context::test_new();
+        assert_eq!(
+            typeset(&ctx, "macros.em", ".undef{streq}\n\n.streq\n")?,
+            "[[]|.streq]"
+        );
+        assert_eq!(ctx.logger().logs().to_vec(), vec![]);
+        Ok(())
+    }
+}
@@ -1,18 +1,50 @@
+mod arithmetic;
+mod budgets;
+mod commands;
+mod comparison;
+mod doc;
+mod drivers;
 mod em;
 mod env_extras;
+mod flow;
+mod fs;
 mod global_sandboxing;
+mod listeners;
+mod logging;
+mod logic;
+mod macros;
 mod preload_decls;
 mod preload_sandboxing;
+mod profiler;
+mod specs;
+mod store;
+pub(crate) mod traceback;
+mod transpiler;
+mod vars;
+mod watchdog;
 
 use crate::{
-    context::{Iteration, LuaParameters, Memory, ResourceLimit, SandboxLevel, Step},
+    context::{Iteration, LuaParameters, Memory, Module, ResourceLimit, SandboxLevel, Step},
     log::Logger,
-    Context, Error, Result,
+    parser::Location,
+    Context, Error, Log, Result,
 };
+use budgets::Accounts;
+pub use budgets::Usage;
+use camino::{Utf8Path, Utf8PathBuf};
+use doc::DocNode;
+pub(crate) use doc::DocRef;
 use em::Em;
 use kinded::Kinded;
-use mlua::{Error as MLuaError, HookTriggers, Lua, MetaMethod, Table, TableExt, Value};
+pub use listeners::{ListenerHandle, ListenerOptions};
+use mlua::{
+    Error as MLuaError, Function, HookTriggers, Lua, MetaMethod, MultiValue, Result as MLuaResult,
+    Table, TableExt, ToLuaMulti, Value,
+};
+pub use profiler::Profile;
+pub use specs::SpecOutcome;
 use std::{cell::RefMut, fmt::Display};
+use watchdog::Watchdog;
 use yuescript::include_yuescript;
 
 #[cfg(test)]
@@ -26,6 +58,11 @@ macro_rules! emblem_registry_key {
 
 static STD: &[u8] = include_yuescript!(cfg!(test), concat!(env!("OUT_DIR"), "/yue"), "std");
 const EVENT_LISTENERS_RKEY: &str = emblem_registry_key!("events");
+const COMMANDS_RKEY: &str = emblem_registry_key!("commands");
+const MODULE_COMMANDS_RKEY: &str = emblem_registry_key!("module_commands");
+const DRIVERS_RKEY: &str = emblem_registry_key!("drivers");
+const PROFILER_RKEY: &str = emblem_registry_key!("profiler");
+const YUE_RKEY: &str = emblem_registry_key!("yue");
 
 pub struct ExtensionState {
     lua: Lua,
@@ -42,19 +79,40 @@ impl ExtensionState {
             Lua::new()
         };
 
-        lua.set_app_data(ExtensionData::new());
+        lua.set_app_data(ExtensionData::new(params));
 
+        Self::keep_profiler(&lua)?;
         preload_sandboxing::restrict_preload(&lua, sandbox_level)?;
+        if let ResourceLimit::Limited(_) = params.max_time() {
+            Self::disable_jit(&lua)?;
+        }
         env_extras::import_extras(&lua)?;
         global_sandboxing::restrict_globals(&lua, sandbox_level)?;
+        transpiler::add_searcher(&lua)?;
 
         Self::insert_safety_hook(&lua, params)?;
         Self::setup_event_listeners(&lua)?;
+        Self::setup_commands(&lua)?;
+        Self::setup_drivers(&lua)?;
 
-        lua.globals().set("em", Em::new())?;
+        lua.globals().set(
+            "em",
+            Em::new(ctx.repo_info().cloned(), em::DocInfo::new(ctx)),
+        )?;
         // TODO(kcza): set args
 
-        lua.load(STD).exec()?;
+        lua.load(STD).set_name("std")?.exec()?;
+        let versions = store::resolve(&lua, params.modules())?;
+        {
+            let mut data = lua
+                .app_data_mut::<ExtensionData>()
+                .expect("internal error: lua app data not set");
+            data.store = store::Store::new(versions);
+            if let ResourceLimit::Limited(max_time) = params.max_time() {
+                data.watchdog = Some(Watchdog::new(max_time));
+            }
+        }
+        Self::announce_module_loads(&lua)?;
 
         Ok(ExtensionState { lua })
     }
@@ -62,25 +120,49 @@ impl ExtensionState {
     fn insert_safety_hook(lua: &Lua, params: &LuaParameters) -> Result<()> {
         const INSTRUCTION_INTERVAL: u32 = 1;
 
+        /// The number of steps between checks of module budgets and the time limit, which cost
+        /// too much to check after every step.
+        const ACCOUNTING_INTERVAL: Step = Step(1000);
+
         let max_mem = params.max_mem();
-        let max_steps = params.max_steps();
 
         Ok(lua.set_hook(
             HookTriggers::every_nth_instruction(INSTRUCTION_INTERVAL),
             move |lua, _debug| {
+                let mut data: RefMut<'_, ExtensionData> = lua
+                    .app_data_mut()
+                    .expect("internal error: expected lua app data to be set");
+
                 if let ResourceLimit::Limited(max_mem) = max_mem {
                     if Memory(lua.used_memory()) >= max_mem {
-                        return Err(MLuaError::SafetyError("too much memory used".into()));
+                        return Err(MLuaError::SafetyError(
+                            data.accounts.blame("too much memory used"),
+                        ));
                     }
                 }
 
-                let mut data: RefMut<'_, ExtensionData> = lua
-                    .app_data_mut()
-                    .expect("internal error: expected lua app data to be set");
-                data.curr_step += Step(INSTRUCTION_INTERVAL);
-                if let ResourceLimit::Limited(max_steps) = max_steps {
-                    if data.curr_step > max_steps {
-                        return Err(MLuaError::SafetyError("too many steps".into()));
+                if data.take_steps(Step(INSTRUCTION_INTERVAL)) {
+                    return Err(MLuaError::SafetyError(
+                        data.accounts.blame("too many steps"),
+                    ));
+                }
+
+                data.accounts.count_steps(Step(INSTRUCTION_INTERVAL));
+                if !data.accounting_due(ACCOUNTING_INTERVAL) {
+                    return Ok(());
+                }
+
+                if let Err(msg) = data.accounts.charge(lua.used_memory()) {
+                    return Err(MLuaError::SafetyError(msg));
+                }
+
+                if let Some(watchdog) = &mut data.watchdog {
+                    watchdog.start();
+                    if watchdog.expired() {
+                        return Err(MLuaError::SafetyError(format!(
+                            "too much time spent, limit is {}",
+                            watchdog.max_time()
+                        )));
                     }
                 }
 
@@ -89,6 +171,26 @@ impl ExtensionState {
         )?)
     }
 
+    /// Stop LuaJIT from compiling extension code, as compiled code does not call the safety hook
+    /// and so could otherwise run past the time limit.
+    fn disable_jit(lua: &Lua) -> Result<()> {
+        let Some(jit) = lua.globals().get::<_, Option<Table>>("jit")? else {
+            return Ok(());
+        };
+        Ok(jit.get::<_, Function>("off")?.call(())?)
+    }
+
+    /// Keep the loader of LuaJIT's profiler so that extensions can be profiled even when they may
+    /// not load it themselves.
+    fn keep_profiler(lua: &Lua) -> Result<()> {
+        let loader: Function = lua
+            .globals()
+            .get::<_, Table>("package")?
+            .get::<_, Table>("preload")?
+            .get("jit.profile")?;
+        Ok(lua.set_named_registry_value(PROFILER_RKEY, loader)?)
+    }
+
     fn setup_event_listeners(lua: &Lua) -> Result<()> {
         Ok(lua.set_named_registry_value(EVENT_LISTENERS_RKEY, {
             let event_kinds = EventKind::all();
@@ -105,87 +207,275 @@ impl ExtensionState {
             listeners
         })?)
     }
-    pub fn lua(&self) -> &Lua {
-        &self.lua
+
+    fn setup_commands(lua: &Lua) -> Result<()> {
+        lua.set_named_registry_value(COMMANDS_RKEY, lua.create_table()?)?;
+        lua.set_named_registry_value(MODULE_COMMANDS_RKEY, lua.create_table()?)?;
+        arithmetic::define_commands(lua)?;
+        comparison::define_commands(lua)?;
+        logic::define_commands(lua)?;
+        flow::define_commands(lua)?;
+        vars::define_commands(lua)?;
+        macros::define_commands(lua)?;
+        Ok(())
     }
 
-    pub fn add_listener(&self, event_kind: EventKind, listener: Value) -> Result<()> {
-        if !callable(&listener) {
-            return Err(Error::uncallable_listener(listener.type_name()));
-        }
+    fn setup_drivers(lua: &Lua) -> Result<()> {
+        Ok(lua.set_named_registry_value(DRIVERS_RKEY, lua.create_table()?)?)
+    }
 
-        let listeners: Table = self.lua.named_registry_value(EVENT_LISTENERS_RKEY)?;
-        let Some(event_listeners) = listeners.get::<_, Option<Table>>(event_kind.name())? else {
-            panic!("internal error: {event_kind} event has no listener table")
+    /// Wrap `require` so that listeners hear of each module the first time it is loaded, and so
+    /// that the resources used when loading a module are charged to it.
+    fn announce_module_loads(lua: &Lua) -> Result<()> {
+        let Some(require) = lua.globals().get::<_, Option<Function>>("require")? else {
+            return Ok(());
         };
-        Ok(event_listeners.push(listener)?)
+        let require = lua.create_registry_value(require)?;
+
+        let announcing_require = lua.create_function(move |lua, name: String| {
+            let loaded = match lua.globals().get::<_, Option<Table>>("package")? {
+                Some(package) => package.get::<_, Option<Table>>("loaded")?,
+                None => None,
+            };
+            let already_loaded = match loaded {
+                Some(loaded) => loaded.contains_key(name.as_str())?,
+                None => false,
+            };
+
+            let require: Function = lua.registry_value(&require)?;
+            let owner = (!already_loaded).then(|| name.clone());
+            let ret: MultiValue = budgets::charged_to(lua, owner, || require.call(name.as_str()))?;
+            if !already_loaded {
+                Self::dispatch(lua, &Event::ModuleLoaded { name })?;
+            }
+            Ok(ret)
+        })?;
+        Ok(lua.globals().set("require", announcing_require)?)
     }
 
-    pub fn handle(&self, event: Event) -> Result<()> {
-        let listeners: Table = self.lua.named_registry_value(EVENT_LISTENERS_RKEY)?;
-        let event_listeners = match listeners.get::<_, Option<Table>>(event.kind().name())? {
-            Some(ls) => ls,
-            None => panic!("internal error: {event} event has no listeners table"),
-        };
-        for listener in event_listeners.sequence_values::<Value>() {
-            self.call_listener(listener?, event)?;
-        }
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
 
-        Ok(())
+    pub fn add_listener(&self, event_kind: EventKind, listener: Value) -> Result<ListenerHandle> {
+        self.add_listener_with(event_kind, listener, &ListenerOptions::default())
     }
 
-    fn call_listener(&self, listener: Value, event: Event) -> Result<()> {
-        if let Value::Function(f) = listener {
-            f.call(self.event_data(event)?)?;
-        } else {
-            self.call_listener_method(listener, event)?;
-        }
+    pub fn add_listener_with(
+        &self,
+        event_kind: EventKind,
+        listener: Value,
+        options: &ListenerOptions,
+    ) -> Result<ListenerHandle> {
+        listeners::add(&self.lua, event_kind, listener, options)
+    }
 
-        Ok(())
+    pub fn handle(&self, event: Event) -> Result<()> {
+        Self::dispatch(&self.lua, &event)
     }
 
-    fn call_listener_method(&self, listener: Value, event: Event) -> Result<()> {
-        let listener_type = listener.type_name();
-
-        match listener.clone() {
-            Value::Function(f) => return Ok(f.call(self.event_data(event)?)?),
-            Value::Table(t) => return Ok(t.call(self.event_data(event)?)?),
-            Value::UserData(u) => {
-                if let Ok(mt) = u.get_metatable() {
-                    if let Ok(m) = mt.get::<_, Value>(MetaMethod::Call.name()) {
-                        match m {
-                            Value::Function(f) => {
-                                return Ok(f.call((listener, self.event_data(event)?))?);
-                            }
-                            Value::Table(t) => {
-                                return Ok(t.call((listener, self.event_data(event)?))?);
-                            }
-                            Value::UserData(_) => return self.call_listener_method(m, event),
-                            _ => {}
-                        }
-                    }
-                }
+    fn dispatch(lua: &Lua, event: &Event) -> Result<()> {
+        let entries = listeners::entries(lua, event.kind())?
+            .sequence_values::<Table>()
+            .collect::<mlua::Result<Vec<_>>>()?;
+        for entry in entries {
+            if entry.get("removed")? {
+                continue;
+            }
+            if entry.get("once")? {
+                let id = entry.get("id")?;
+                ListenerHandle::new(event.kind(), id).remove(lua)?;
             }
-            _ => {}
+            let listener = entry.get("listener")?;
+            budgets::charged_to(lua, entry.get("owner")?, || {
+                Self::call_listener(lua, listener, event)
+            })?;
         }
 
-        Err(Error::uncallable_listener(listener_type))
+        Ok(())
     }
 
-    fn event_data(&self, event: Event) -> Result<Value> {
+    fn call_listener(lua: &Lua, listener: Value, event: &Event) -> Result<()> {
+        call(listener, Self::event_data(lua, event)?.to_lua_multi(lua)?)?;
+        Ok(())
+    }
+
+    fn event_data<'lua>(lua: &'lua Lua, event: &Event) -> Result<Value<'lua>> {
         let data = match event {
-            Event::IterStart { iter }
-            | Event::IterEnd { iter }
-            | Event::Done { final_iter: iter } => {
-                let event = self.lua.create_table_with_capacity(0, 1)?;
+            Event::AfterParse | Event::BeforeOutput => {
+                let data = lua.create_table_with_capacity(0, 1)?;
+                data.set("doc", Self::doc(lua).map(DocNode::root))?;
+                data
+            }
+            Event::Include { file, loc } => {
+                let data = lua.create_table_with_capacity(0, 2)?;
+                data.set("file", file.as_str())?;
+                data.set("loc", loc.clone())?;
+                data
+            }
+            Event::IterStart { iter } | Event::IterEnd { iter } => {
+                let data = lua.create_table_with_capacity(0, 2)?;
                 let Iteration(iter) = iter;
-                event.set("iter", iter)?;
-                event
+                data.set("iter", *iter)?;
+                data.set("doc", Self::doc(lua).map(DocNode::root))?;
+                data
+            }
+            Event::Done { final_iter: iter } => {
+                let data = lua.create_table_with_capacity(0, 1)?;
+                let Iteration(iter) = iter;
+                data.set("iter", *iter)?;
+                data
+            }
+            Event::AfterOutput { written } => {
+                let data = lua.create_table_with_capacity(0, 1)?;
+                data.set(
+                    "written",
+                    lua.create_sequence_from(written.iter().map(|path| path.as_str()))?,
+                )?;
+                data
+            }
+            Event::ModuleLoaded { name } => {
+                let data = lua.create_table_with_capacity(0, 1)?;
+                data.set("name", name.as_str())?;
+                data
             }
         };
         Ok(Value::Table(data))
     }
 
+    fn doc(lua: &Lua) -> Option<DocRef> {
+        lua.app_data_ref::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .doc
+            .clone()
+    }
+
+    /// Evaluate the commands in `doc` which have been defined by extensions.
+    pub(crate) fn evaluate(&self, doc: &DocRef) -> Result<()> {
+        commands::evaluate(&self.lua, &DocNode::root(doc.clone()))
+    }
+
+    /// Remove and return the messages logged by extensions.
+    pub(crate) fn take_logs(&self) -> Vec<Log> {
+        std::mem::take(
+            &mut self
+                .lua
+                .app_data_mut::<ExtensionData>()
+                .expect("internal error: lua app data not set")
+                .logs,
+        )
+    }
+
+    /// The resources used by each module which has run so far.
+    pub fn usage(&self) -> Vec<(String, Usage)> {
+        self.lua
+            .app_data_ref::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .accounts
+            .usage()
+            .map(|(owner, usage)| (owner.to_owned(), usage))
+            .collect()
+    }
+
+    /// Start sampling where extensions spend their time.
+    pub fn start_profiling(&self) -> Result<()> {
+        profiler::start(&self.lua)
+    }
+
+    /// Stop sampling where extensions spend their time, returning the samples taken.
+    pub fn stop_profiling(&self) -> Result<Profile> {
+        profiler::stop(&self.lua)
+    }
+
+    /// Set the directories within which extensions may access files through `em.fs`: the root
+    /// of the project and the directory to which output is written.
+    pub(crate) fn set_fs_roots(&self, project: Utf8PathBuf, output: Option<Utf8PathBuf>) {
+        self.lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .fs_roots
+            .set(project, output);
+    }
+
+    /// Tell extensions the format in which output is written and the stem of the files written,
+    /// if not written to stdout.
+    pub(crate) fn set_output(&self, format: Option<String>, stem: Option<Utf8PathBuf>) {
+        self.lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .build = em::BuildInfo::new(format, stem);
+    }
+
+    /// Load the modules the document depends on, so that they may register their commands,
+    /// listeners and drivers. This is left until the build has been set up, so that modules may
+    /// use `em.fs` and `em.build` as they load. Modules which have already been loaded are not
+    /// loaded again.
+    pub(crate) fn load_modules(&self, modules: &[Module]) -> Result<()> {
+        let require: Function = self.lua.globals().get("require")?;
+        for module in modules {
+            require
+                .call::<_, ()>(module.local_name())
+                .map_err(|e| Error::from(e).context(format!("failed to load {}", module.name())))?;
+        }
+        Ok(())
+    }
+
+    /// The names of the output formats for which extensions have registered drivers, in order.
+    pub fn output_formats(&self) -> Result<Vec<String>> {
+        drivers::formats(&self.lua)
+    }
+
+    /// The names of the modules which have registered output drivers, in order.
+    pub fn output_extensions(&self) -> Result<Vec<String>> {
+        drivers::owners(&self.lua)
+    }
+
+    /// Run the driver registered for `format` over `doc`, returning the extension and contents of
+    /// each file to write. If no driver has been registered for `format`, `None` is returned.
+    pub(crate) fn run_driver(
+        &self,
+        format: &str,
+        doc: DocRef,
+    ) -> Result<Option<Vec<(String, String)>>> {
+        drivers::run(&self.lua, format, DocNode::root(doc))
+    }
+
+    /// Open the store which extensions keep between builds whose output is written to `dir`.
+    pub(crate) fn open_store(&self, dir: &Utf8Path) -> Result<()> {
+        self.lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .store
+            .load(dir.join(store::STORE_FILE))
+    }
+
+    /// Write back any changes extensions have made to their store.
+    pub(crate) fn save_store(&self) -> Result<()> {
+        self.lua
+            .app_data_ref::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .store
+            .save()
+    }
+
+    /// Load the module file at `path` and run the tests in its `$spec` blocks, keeping only those
+    /// whose names contain `filter`, if given.
+    pub(crate) fn run_specs(
+        &self,
+        path: &Utf8Path,
+        filter: Option<&str>,
+    ) -> Result<Vec<SpecOutcome>> {
+        specs::run_file(&self.lua, path, filter)
+    }
+
+    /// Set the document which extensions may inspect and modify.
+    pub(crate) fn set_doc(&self, doc: Option<DocRef>) {
+        self.lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set")
+            .doc = doc;
+    }
+
     pub(crate) fn reiter_requested(&self) -> bool {
         self.lua
             .app_data_ref::<ExtensionData>()
@@ -231,15 +521,71 @@ fn callable(value: &Value) -> bool {
     }
 }
 
+/// Call `callable` with `args`, passing callable userdata to its `__call` metamethod as its first
+/// argument.
+fn call<'lua>(callable: Value<'lua>, args: MultiValue<'lua>) -> MLuaResult<Value<'lua>> {
+    match callable {
+        Value::Function(f) => f.call(args),
+        Value::Table(t) => t.call(args),
+        Value::UserData(ref u) => {
+            let handler = u.get_metatable()?.get(MetaMethod::Call.name())?;
+            let mut args = args;
+            args.push_front(callable);
+            call(handler, args)
+        }
+        _ => Err(MLuaError::RuntimeError(format!(
+            "{} is not callable",
+            callable.type_name()
+        ))),
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct ExtensionData {
     curr_step: Step,
+    max_steps: ResourceLimit<Step>,
     reiter_requested: bool,
+    doc: Option<DocRef>,
+    listeners_added: mlua::Integer,
+    logs: Vec<Log>,
+    accounts: Accounts,
+    profile: Option<Profile>,
+    fs_roots: fs::Roots,
+    vars: vars::Vars,
+    store: store::Store,
+    build: em::BuildInfo,
+    watchdog: Option<Watchdog>,
+    last_accounted_step: Step,
 }
 
 impl ExtensionData {
-    fn new() -> Self {
-        Self::default()
+    fn new(params: &LuaParameters) -> Self {
+        Self {
+            max_steps: params.max_steps(),
+            accounts: Accounts::new(params.modules()),
+            fs_roots: fs::Roots::new(params.sandbox_level() <= SandboxLevel::Standard),
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether at least `interval` steps have been taken since the last time accounting
+    /// was due.
+    fn accounting_due(&mut self, interval: Step) -> bool {
+        let Step(curr) = self.curr_step;
+        let Step(last) = self.last_accounted_step;
+        let Step(interval) = interval;
+        if curr - last < interval {
+            return false;
+        }
+        self.last_accounted_step = self.curr_step;
+        true
+    }
+
+    /// Count `steps` more steps taken by extensions, returning whether the limit on steps has now
+    /// been exceeded.
+    pub(crate) fn take_steps(&mut self, steps: Step) -> bool {
+        self.curr_step += steps;
+        matches!(self.max_steps, ResourceLimit::Limited(max_steps) if self.curr_step > max_steps)
     }
 
     #[allow(unused)]
@@ -252,11 +598,16 @@ impl ExtensionData {
     }
 }
 
-#[derive(Copy, Clone, Kinded)]
+#[derive(Clone, Kinded)]
 pub enum Event {
+    AfterParse,
+    Include { file: Utf8PathBuf, loc: Location },
     IterStart { iter: Iteration },
     IterEnd { iter: Iteration },
     Done { final_iter: Iteration },
+    BeforeOutput,
+    AfterOutput { written: Vec<Utf8PathBuf> },
+    ModuleLoaded { name: String },
 }
 
 impl Display for Event {
@@ -265,16 +616,30 @@ impl Display for Event {
             Self::IterStart { iter } | Self::IterEnd { iter } | Self::Done { final_iter: iter } => {
                 write!(f, "{}({iter})", self.kind())
             }
+            Self::Include { file, .. } => write!(f, "{}({file})", self.kind()),
+            Self::ModuleLoaded { name } => write!(f, "{}({name})", self.kind()),
+            Self::AfterParse | Self::BeforeOutput | Self::AfterOutput { .. } => {
+                write!(f, "{}", self.kind())
+            }
         }
     }
 }
 
 impl EventKind {
+    fn from_name(name: &str) -> Option<Self> {
+        Self::all().iter().find(|kind| kind.name() == name).copied()
+    }
+
     fn name(&self) -> &'static str {
         match self {
+            Self::AfterParse => "after-parse",
+            Self::Include => "include",
             Self::IterStart => "iter-start",
             Self::IterEnd => "iter-end",
             Self::Done => "done",
+            Self::BeforeOutput => "before-output",
+            Self::AfterOutput => "after-output",
+            Self::ModuleLoaded => "module-loaded",
         }
     }
 }
@@ -284,6 +649,7 @@ mod test {
     use mlua::chunk;
 
     use super::*;
+    use crate::{context::Time, log::BatchLogger, RepoInfo, Verbosity};
 
     #[test]
     fn std_tests() {
@@ -324,6 +690,118 @@ mod test {
         );
     }
 
+    #[test]
+    fn repo_info() -> Result<()> {
+        {
+            let ctx = Context::test_new();
+            let ext_state = ctx.extension_state()?;
+            ext_state.run(chunk! {
+                assert(em.repo == nil, "unexpected repo info")
+            })?;
+        }
+
+        {
+            let ctx = {
+                let mut ctx = Context::test_new();
+                ctx.set_repo_info(RepoInfo::new(Some("0123456789abcdef".into()), true));
+                ctx
+            };
+            let ext_state = ctx.extension_state()?;
+            ext_state.run(chunk! {
+                assert(em.repo.commit == "0123456789abcdef", "incorrect commit: " .. tostring(em.repo.commit))
+                assert(em.repo.dirty, "repo not dirty")
+            })?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn doc_info() -> Result<()> {
+        {
+            let ctx = Context::test_new();
+            let ext_state = ctx.extension_state()?;
+            ext_state.run(chunk! {
+                assert(em.doc.name == "On the Origin of Burnt Toast", "incorrect name: " .. tostring(em.doc.name))
+                assert(#em.doc.authors == 1 and em.doc.authors[1] == "kcza", "incorrect authors")
+                assert(table.concat(em.doc.keywords, ",") == "toast,burnt,backstory", "incorrect keywords")
+                assert(em.doc.emblem_version == "1.0", "incorrect version: " .. tostring(em.doc.emblem_version))
+            })?;
+        }
+
+        {
+            let ctx = Context::new(BatchLogger::new(Verbosity::Terse));
+            let ext_state = ctx.extension_state()?;
+            ext_state.run(chunk! {
+                assert(em.doc.name == nil, "unexpected name")
+                assert(em.doc.authors == nil, "unexpected authors")
+                assert(em.doc.keywords == nil, "unexpected keywords")
+                assert(em.doc.emblem_version == nil, "unexpected version")
+            })?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_info() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(chunk! {
+            assert(em.build.output_format == nil, "unexpected output format")
+            assert(em.build.output_stem == nil, "unexpected output stem")
+        })?;
+
+        ext_state.set_output(Some("html".into()), Some("out/main".into()));
+        ext_state.run(chunk! {
+            assert(em.build.output_format == "html", "incorrect output format: " .. tostring(em.build.output_format))
+            assert(em.build.output_stem == "out/main", "incorrect output stem: " .. tostring(em.build.output_stem))
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn module_loaded() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.add_listener(
+            EventKind::ModuleLoaded,
+            ext_state
+                .lua()
+                .load("function(event) table.insert(loaded, event.name) end")
+                .eval()?,
+        )?;
+        ext_state.run(chunk! {
+            loaded = {}
+            package.preload["greeting"] = function() return "hello" end
+            assert(require("greeting") == "hello")
+            assert(require("greeting") == "hello")
+            require("string")
+        })?;
+
+        let loaded: Vec<String> = ext_state.lua().globals().get("loaded")?;
+        assert_eq!(loaded, ["greeting"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn event_names() {
+        for kind in EventKind::all() {
+            let name = kind.name();
+            assert!(!name.is_empty());
+            assert_eq!(
+                1,
+                EventKind::all()
+                    .iter()
+                    .filter(|other| other.name() == name)
+                    .count(),
+                "duplicate event name {name}"
+            );
+        }
+    }
+
     #[test]
     fn steps_limited() -> Result<()> {
         let threshold = Step(10000);
@@ -354,6 +832,45 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn time_limited() -> Result<()> {
+        let ctx = Context::test_new();
+        let result = ctx
+            .extension_state()?
+            .lua()
+            .load(chunk! {
+                local total = 0;
+                for i = 1, 1000 do
+                    total = total + i
+                end
+                return total;
+            })
+            .exec();
+        assert!(result.is_ok(), "unexpected error: {result:?}");
+
+        // Long enough for LuaJIT to compile the loop, which must not let it escape the limit.
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.lua_params_mut()
+                .set_max_time(ResourceLimit::Limited(Time(1)));
+            ctx
+        };
+        let err = ctx
+            .extension_state()?
+            .lua()
+            .load(chunk! {
+                while true do end
+            })
+            .exec()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("too much time spent, limit is 1s"),
+            "unexpected error: {err}"
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn memory_limited() -> Result<()> {
         let threshold = Memory(500000);
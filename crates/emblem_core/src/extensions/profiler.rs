@@ -0,0 +1,272 @@
+use super::{budgets, ExtensionData, PROFILER_RKEY};
+use crate::{context::Step, Result};
+use mlua::{Function, Lua, Table, Thread};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+/// The interval between samples, in milliseconds.
+const INTERVAL: u32 = 1;
+
+/// The number of frames to record in each sample.
+const MAX_DEPTH: i32 = 100;
+
+/// The label used for code which does not belong to any module.
+const NO_MODULE: &str = "-";
+
+/// Samples of where time and steps were spent by extensions.
+#[derive(Debug, Default)]
+pub struct Profile {
+    functions: HashMap<(String, String), Entry>,
+    stacks: HashMap<String, u64>,
+    last_step: Step,
+}
+
+/// The resources spent in a single function.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Entry {
+    samples: u64,
+    steps: Step,
+}
+
+impl Entry {
+    /// The approximate time spent in the function, in milliseconds.
+    pub fn millis(&self) -> u64 {
+        self.samples * INTERVAL as u64
+    }
+
+    /// The approximate number of steps executed in the function.
+    pub fn steps(&self) -> Step {
+        self.steps
+    }
+}
+
+impl Profile {
+    /// The time and steps spent in each function, keyed by module and function name, most
+    /// expensive first.
+    pub fn functions(&self) -> Vec<(&str, &str, Entry)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|((module, function), entry)| (module.as_str(), function.as_str(), *entry))
+            .collect();
+        functions.sort_by(|(m1, f1, e1), (m2, f2, e2)| {
+            (e2.samples, e2.steps)
+                .cmp(&(e1.samples, e1.steps))
+                .then_with(|| (m1, f1).cmp(&(m2, f2)))
+        });
+        functions
+    }
+
+    /// The samples in the collapsed-stack format understood by flamegraph tools, where each line
+    /// holds the semicolon-separated frames of a stack followed by the number of samples taken
+    /// there. The first frame of each stack is the module which was running.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks
+            .into_iter()
+            .map(|(stack, samples)| format!("{stack} {samples}\n"))
+            .collect()
+    }
+
+    fn record(&mut self, module: Option<String>, stack: &str, samples: u64, curr_step: Step) {
+        let module = module.unwrap_or_else(|| NO_MODULE.into());
+        let function = stack.rsplit(';').next().unwrap_or(stack).to_owned();
+
+        let Step(curr) = curr_step;
+        let Step(last) = self.last_step;
+        self.last_step = curr_step;
+
+        let entry = self
+            .functions
+            .entry((module.clone(), function))
+            .or_default();
+        entry.samples += samples;
+        entry.steps += Step(curr.saturating_sub(last));
+
+        *self.stacks.entry(format!("{module};{stack}")).or_default() += samples;
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let functions = self.functions();
+        let module_width = functions
+            .iter()
+            .map(|(module, _, _)| module.len())
+            .chain(["module".len()])
+            .max()
+            .unwrap_or_default();
+        let function_width = functions
+            .iter()
+            .map(|(_, function, _)| function.len())
+            .chain(["function".len()])
+            .max()
+            .unwrap_or_default();
+
+        writeln!(
+            f,
+            "{:module_width$}  {:function_width$}  {:>10}  {:>10}",
+            "module", "function", "time (ms)", "steps"
+        )?;
+        for (module, function, entry) in functions {
+            writeln!(
+                f,
+                "{module:module_width$}  {function:function_width$}  {:>10}  {:>10}",
+                entry.millis(),
+                entry.steps().to_string(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Start sampling the execution of extensions.
+pub(crate) fn start(lua: &Lua) -> Result<()> {
+    let profile = jit_profile(lua)?;
+    let dumpstack = lua.create_registry_value(profile.get::<_, Function>("dumpstack")?)?;
+
+    {
+        let mut data = lua
+            .app_data_mut::<ExtensionData>()
+            .expect("internal error: lua app data not set");
+        data.profile = Some(Profile {
+            last_step: data.curr_step,
+            ..Profile::default()
+        });
+    }
+
+    let callback = lua.create_function(
+        move |lua, (thread, samples, _vmstate): (Thread, u64, String)| {
+            let dumpstack: Function = lua.registry_value(&dumpstack)?;
+            let stack: String = dumpstack.call((thread, "F;", -MAX_DEPTH))?;
+            let owner = budgets::owner(lua);
+
+            let mut data = lua
+                .app_data_mut::<ExtensionData>()
+                .expect("internal error: lua app data not set");
+            let curr_step = data.curr_step;
+            if let Some(profile) = &mut data.profile {
+                profile.record(owner, stack.trim_end_matches(';'), samples, curr_step);
+            }
+            Ok(())
+        },
+    )?;
+    let start: Function = profile.get("start")?;
+    Ok(start.call((format!("fi{INTERVAL}"), callback))?)
+}
+
+/// Stop sampling the execution of extensions, returning the samples taken.
+pub(crate) fn stop(lua: &Lua) -> Result<Profile> {
+    let stop: Function = jit_profile(lua)?.get("stop")?;
+    stop.call::<_, ()>(())?;
+
+    Ok(lua
+        .app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .profile
+        .take()
+        .unwrap_or_default())
+}
+
+/// Load LuaJIT's profiler. As extensions may not be allowed to load it themselves, its loader is
+/// kept aside before sandboxing is applied.
+fn jit_profile(lua: &Lua) -> Result<Table<'_>> {
+    let loader: Function = lua.named_registry_value(PROFILER_RKEY)?;
+    Ok(loader.call("jit.profile")?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Context, SandboxLevel};
+
+    #[test]
+    fn record() {
+        let mut profile = Profile::default();
+        profile.record(Some("figures".into()), "figures:0;figures:12", 3, Step(10));
+        profile.record(None, "std:4", 1, Step(12));
+        profile.record(Some("figures".into()), "figures:0;figures:12", 2, Step(30));
+        profile.record(Some("figures".into()), "figures:0;figures:20", 2, Step(31));
+
+        assert_eq!(
+            profile.functions(),
+            [
+                (
+                    "figures",
+                    "figures:12",
+                    Entry {
+                        samples: 5,
+                        steps: Step(28)
+                    }
+                ),
+                (
+                    "figures",
+                    "figures:20",
+                    Entry {
+                        samples: 2,
+                        steps: Step(1)
+                    }
+                ),
+                (
+                    "-",
+                    "std:4",
+                    Entry {
+                        samples: 1,
+                        steps: Step(2)
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            profile.collapsed_stacks(),
+            "-;std:4 1\nfigures;figures:0;figures:12 5\nfigures;figures:0;figures:20 2\n"
+        );
+        assert_eq!(
+            profile.to_string(),
+            [
+                "module   function     time (ms)       steps",
+                "figures  figures:12           5          28",
+                "figures  figures:20           2           1",
+                "-        std:4                1           2",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn sampling() -> Result<()> {
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.lua_params_mut()
+                .set_sandbox_level(SandboxLevel::Standard);
+            ctx
+        };
+        let ext_state = ctx.extension_state()?;
+        ext_state.start_profiling()?;
+        ext_state.run(
+            r#"
+                local function busy()
+                    local start = os.clock()
+                    local total = 0
+                    while os.clock() - start < 0.05 do
+                        total = total + 1
+                    end
+                    return total
+                end
+                busy()
+            "#,
+        )?;
+        let profile = ext_state.stop_profiling()?;
+
+        let functions = profile.functions();
+        assert!(!functions.is_empty());
+        assert!(functions.iter().all(|(module, _, _)| *module == NO_MODULE));
+        assert!(!profile.collapsed_stacks().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+use super::{
+    traceback,
+    transpiler::{self, Specs},
+};
+use crate::{Error, Result};
+use camino::Utf8Path;
+use mlua::{Function, Lua, RegistryKey, Result as MLuaResult, Value};
+use std::{cell::RefCell, fs, rc::Rc};
+
+/// The names of the globals used while running specs.
+const GLOBALS: [&str; 4] = ["__emblem_spec", "describe", "it", "assert"];
+
+/// Assertions in the style of luassert, such as `assert.are.equal(expected, actual)` and
+/// `assert.is_not.nil(value)`. The chunk is passed Lua's own `assert`, which is used when the
+/// table is called.
+const ASSERTIONS: &str = r#"
+    local base_assert = ...
+
+    local function show(v)
+        if type(v) == 'string' then
+            return string.format('%q', v)
+        end
+        return tostring(v)
+    end
+
+    local function same(a, b)
+        if a == b then
+            return true
+        end
+        if type(a) ~= 'table' or type(b) ~= 'table' then
+            return false
+        end
+        for k, v in pairs(a) do
+            if not same(v, b[k]) then
+                return false
+            end
+        end
+        for k in pairs(b) do
+            if a[k] == nil then
+                return false
+            end
+        end
+        return true
+    end
+
+    local checks = {
+        equal = function(expected, actual)
+            return expected == actual, show(actual) .. ' to equal ' .. show(expected)
+        end,
+        same = function(expected, actual)
+            return same(expected, actual), show(actual) .. ' to be the same as ' .. show(expected)
+        end,
+        truthy = function(v)
+            return not not v, show(v) .. ' to be truthy'
+        end,
+        falsy = function(v)
+            return not v, show(v) .. ' to be falsy'
+        end,
+        ['true'] = function(v)
+            return v == true, show(v) .. ' to be true'
+        end,
+        ['false'] = function(v)
+            return v == false, show(v) .. ' to be false'
+        end,
+        ['nil'] = function(v)
+            return v == nil, show(v) .. ' to be nil'
+        end,
+        error = function(f, msg)
+            local ok, err = pcall(f)
+            if msg == nil then
+                return not ok, 'function to raise an error'
+            end
+            return not ok and string.find(tostring(err), msg, 1, true) ~= nil,
+                'function to raise an error containing ' .. show(msg)
+        end,
+    }
+    checks.equals = checks.equal
+    checks.errors = checks.error
+    for _, t in ipairs({ 'boolean', 'function', 'number', 'string', 'table' }) do
+        checks[t] = function(v)
+            return type(v) == t, show(v) .. ' to be a ' .. t
+        end
+    end
+
+    local modifiers = { are = true, is = true, has = true, does = true }
+    local negations = { ['not'] = true, no = true }
+
+    local function chain(negated)
+        return setmetatable({}, {
+            __call = function(_, ...)
+                return base_assert(...)
+            end,
+            __index = function(_, key)
+                local name, neg = key, negated
+                while true do
+                    local word, rest = name:match('^([a-z]+)_(.+)$')
+                    if modifiers[word] then
+                        name = rest
+                    elseif negations[word] then
+                        name, neg = rest, not neg
+                    else
+                        break
+                    end
+                end
+                if modifiers[name] then
+                    return chain(neg)
+                end
+                if negations[name] then
+                    return chain(not neg)
+                end
+
+                local check = checks[name]
+                if check == nil then
+                    error('unknown assertion ' .. key, 2)
+                end
+                return function(...)
+                    local ok, description = check(...)
+                    if ok == neg then
+                        error((neg and 'did not expect ' or 'expected ') .. description, 2)
+                    end
+                    return ...
+                end
+            end,
+        })
+    end
+
+    return chain(false)
+"#;
+
+/// The result of running a single test.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpecOutcome {
+    name: String,
+    loc: Option<String>,
+    failure: Option<String>,
+}
+
+impl SpecOutcome {
+    /// The names of the `describe` blocks containing the test, followed by its own.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Where the test was declared.
+    pub fn loc(&self) -> Option<&str> {
+        self.loc.as_deref()
+    }
+
+    /// Why the test failed, if it did.
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+}
+
+#[derive(Default)]
+struct Runner {
+    specs: Vec<RegistryKey>,
+    names: Vec<String>,
+    filter: Option<String>,
+    outcomes: Vec<SpecOutcome>,
+}
+
+impl Runner {
+    fn full_name(&self, name: &str) -> String {
+        self.names
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Load the module file at `path` and run the tests in its `$spec` blocks, keeping only those
+/// whose names contain `filter`, if given.
+pub(crate) fn run_file(
+    lua: &Lua,
+    path: &Utf8Path,
+    filter: Option<&str>,
+) -> Result<Vec<SpecOutcome>> {
+    let src = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let chunk = transpiler::load(lua, path.as_str(), &src, Specs::Included)?;
+    run(lua, chunk, filter)
+}
+
+/// Run `chunk`, then the tests in the blocks it passes to `__emblem_spec`.
+fn run(lua: &Lua, chunk: Function, filter: Option<&str>) -> Result<Vec<SpecOutcome>> {
+    let globals = lua.globals();
+    let previous = GLOBALS
+        .iter()
+        .map(|name| globals.raw_get(*name))
+        .collect::<MLuaResult<Vec<Value>>>()?;
+
+    let runner = Rc::new(RefCell::new(Runner {
+        filter: filter.map(ToOwned::to_owned),
+        ..Runner::default()
+    }));
+    let ret = with_globals(lua, &runner, chunk);
+
+    for (name, value) in GLOBALS.iter().zip(previous) {
+        globals.raw_set(*name, value)?;
+    }
+    ret?;
+
+    let outcomes = std::mem::take(&mut runner.borrow_mut().outcomes);
+    Ok(outcomes)
+}
+
+fn with_globals(lua: &Lua, runner: &Rc<RefCell<Runner>>, chunk: Function) -> Result<()> {
+    let globals = lua.globals();
+    let base_assert: Value = globals.get("assert")?;
+    globals.raw_set(
+        "assert",
+        lua.load(ASSERTIONS)
+            .set_name("assertions")?
+            .call::<_, Value>(base_assert)?,
+    )?;
+    globals.raw_set("__emblem_spec", {
+        let runner = runner.clone();
+        lua.create_function(move |lua, spec: Function| {
+            runner
+                .borrow_mut()
+                .specs
+                .push(lua.create_registry_value(spec)?);
+            Ok(())
+        })?
+    })?;
+    globals.raw_set("describe", {
+        let runner = runner.clone();
+        lua.create_function(move |lua, (name, body): (String, Function)| {
+            let loc = caller_loc(lua);
+            runner.borrow_mut().names.push(name);
+            let ret = body.call::<_, ()>(());
+            let mut runner = runner.borrow_mut();
+            if let Err(e) = ret {
+                let name = runner.names.join(" ");
+                let (msg, _) = traceback::describe(&e);
+                runner.outcomes.push(SpecOutcome {
+                    name,
+                    loc,
+                    failure: Some(msg),
+                });
+            }
+            runner.names.pop();
+            Ok(())
+        })?
+    })?;
+    globals.raw_set("it", {
+        let runner = runner.clone();
+        lua.create_function(move |lua, (name, body): (String, Function)| {
+            let name = runner.borrow().full_name(&name);
+            if let Some(filter) = &runner.borrow().filter {
+                if !name.contains(filter.as_str()) {
+                    return Ok(());
+                }
+            }
+
+            let loc = caller_loc(lua);
+            let failure = body
+                .call::<_, ()>(())
+                .err()
+                .map(|e| traceback::describe(&e).0);
+            runner
+                .borrow_mut()
+                .outcomes
+                .push(SpecOutcome { name, loc, failure });
+            Ok(())
+        })?
+    })?;
+
+    chunk.call::<_, ()>(())?;
+
+    let specs = std::mem::take(&mut runner.borrow_mut().specs);
+    for spec in specs {
+        let spec: Function = lua.registry_value(&spec)?;
+        spec.call::<_, ()>(())?;
+    }
+    Ok(())
+}
+
+/// The location of the Lua code which called the current function.
+fn caller_loc(lua: &Lua) -> Option<String> {
+    let debug = lua.inspect_stack(1)?;
+    let source = String::from_utf8_lossy(debug.source().source?).into_owned();
+    let loc = format!("{source}:{}", debug.curr_line());
+    Some(transpiler::source_lines_replaced(&loc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Context;
+
+    fn outcomes(src: &str, filter: Option<&str>) -> Result<Vec<SpecOutcome>> {
+        let ctx = Context::test_new();
+        let lua = ctx.extension_state()?.lua();
+        let chunk = lua.load(src).set_name("figures.lua")?.into_function()?;
+        run(lua, chunk, filter)
+    }
+
+    #[test]
+    fn running() -> Result<()> {
+        let src = r#"
+            local figures = { count = 3 }
+            __emblem_spec(function()
+                describe('figures', function()
+                    it('are counted', function()
+                        assert.are.equal(3, figures.count)
+                    end)
+                    describe('captions', function()
+                        it('are numbered', function()
+                            assert.is_not_nil(figures.caption)
+                        end)
+                    end)
+                    error('oh no')
+                end)
+            end)
+            return figures
+        "#;
+
+        assert_eq!(
+            outcomes(src, None)?,
+            [
+                SpecOutcome {
+                    name: "figures are counted".into(),
+                    loc: Some("figures.lua:5".into()),
+                    failure: None,
+                },
+                SpecOutcome {
+                    name: "figures captions are numbered".into(),
+                    loc: Some("figures.lua:9".into()),
+                    failure: Some("figures.lua:10: did not expect nil to be nil".into()),
+                },
+                SpecOutcome {
+                    name: "figures".into(),
+                    loc: Some("figures.lua:4".into()),
+                    failure: Some("figures.lua:13: oh no".into()),
+                },
+            ]
+        );
+
+        let filtered = outcomes(src, Some("captions"))?;
+        assert_eq!(
+            filtered.iter().map(SpecOutcome::name).collect::<Vec<_>>(),
+            ["figures captions are numbered", "figures"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn assertions() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        let lua = ext_state.lua();
+        let chunk = lua
+            .load(
+                r#"
+                    __emblem_spec(function()
+                        it('pass', function()
+                            assert(true)
+                            assert.truthy(1)
+                            assert.is.falsy(false)
+                            assert.is_true(true)
+                            assert.are_not.equal(1, 2)
+                            assert.are.same({ 1, { x = 2 } }, { 1, { x = 2 } })
+                            assert.is_number(1)
+                            assert.is_not_string(1)
+                            assert.has_error(function() error('bad figure') end, 'bad')
+                            assert.has_no.error(function() end)
+                        end)
+                        it('fails equality', function() assert.equal(1, 2) end)
+                        it('fails sameness', function() assert.same('a', 'b') end)
+                        it('fails error', function() assert.error(function() error('x') end, 'y') end)
+                        it('fails base', function() assert(false, 'plain') end)
+                        it('fails unknown', function() assert.is_shiny(1) end)
+                    end)
+                "#,
+            )
+            .set_name("assertions.lua")?
+            .into_function()?;
+
+        let failures: Vec<_> = run(lua, chunk, None)?
+            .into_iter()
+            .map(|outcome| outcome.failure().map(ToOwned::to_owned))
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                None,
+                Some("assertions.lua:15: expected 2 to equal 1".into()),
+                Some(r#"assertions.lua:16: expected "b" to be the same as "a""#.into()),
+                Some(
+                    r#"assertions.lua:17: expected function to raise an error containing "y""#
+                        .into()
+                ),
+                Some("assertions.lua:18: plain".into()),
+                Some("assertions.lua:19: unknown assertion is_shiny".into()),
+            ]
+        );
+
+        for name in GLOBALS {
+            if name != "assert" {
+                assert!(matches!(lua.globals().get::<_, Value>(name)?, Value::Nil));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn yue_specs() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = camino::Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        let path = dir.join("figures.yue");
+        fs::write(
+            &path,
+            [
+                "count = 3",
+                "",
+                "$spec ->",
+                "\tdescribe 'figures', ->",
+                "\t\tit 'are counted', ->",
+                "\t\t\tassert.are.equal 3, count",
+                "\t\tit 'are numbered', ->",
+                "\t\t\tassert.are.equal 4, count",
+                "",
+                "{ :count }",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+
+        let ctx = Context::test_new();
+        let outcomes = ctx.extension_state()?.run_specs(&path, None)?;
+        assert_eq!(
+            outcomes
+                .iter()
+                .map(|outcome| (outcome.name(), outcome.failure().is_some()))
+                .collect::<Vec<_>>(),
+            [
+                ("figures are counted", false),
+                ("figures are numbered", true)
+            ]
+        );
+        assert!(outcomes[1]
+            .failure()
+            .unwrap()
+            .ends_with("expected 3 to equal 4"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,525 @@
+use super::{budgets, transpiler, ExtensionData};
+use crate::{
+    context::{Module, ModuleVersion},
+    Error, Result,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use mlua::{Error as MLuaError, Lua, Result as MLuaResult, Table, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+};
+use toml_edit::{Array, Document, InlineTable, Item, Value as TomlValue};
+
+/// The name of the file in the build directory which holds the store.
+pub(super) const STORE_FILE: &str = ".em-store.toml";
+
+/// The deepest nesting of tables which may be stored.
+const MAX_DEPTH: usize = 32;
+
+/// Values which extensions keep between builds. Each module has its own values, which are
+/// forgotten when the commit from which that module is loaded changes.
+#[derive(Debug, Default)]
+pub(crate) struct Store {
+    path: Option<Utf8PathBuf>,
+    versions: HashMap<String, Option<String>>,
+    modules: BTreeMap<String, BTreeMap<String, Stored>>,
+    changed: bool,
+}
+
+impl Store {
+    /// Create a store for the modules whose resolved commits are given by `versions`.
+    pub(crate) fn new(versions: HashMap<String, Option<String>>) -> Self {
+        Self {
+            versions,
+            ..Self::default()
+        }
+    }
+
+    /// Read the store kept at `path` by previous builds, discarding the values of modules whose
+    /// commit has since changed or cannot be resolved. Values are written back to the same file.
+    pub(crate) fn load(&mut self, path: Utf8PathBuf) -> Result<()> {
+        self.path = Some(path.clone());
+
+        let src = match fs::read_to_string(&path) {
+            Ok(src) => src,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::io(path, e)),
+        };
+        let doc: Document = src
+            .parse()
+            .map_err(|e: toml_edit::TomlError| Error::corrupt_store(&path, e.to_string()))?;
+        for (module, item) in doc.iter() {
+            let Some(table) = item.as_table() else {
+                return Err(Error::corrupt_store(
+                    &path,
+                    format!("expected a table for module ‘{module}’"),
+                ));
+            };
+            let version = table.get("version").and_then(Item::as_str);
+            let current = match self.versions.get(module) {
+                Some(Some(resolved)) => Some(resolved.as_str()),
+                Some(None) => continue,
+                None => None,
+            };
+            if version != current {
+                continue;
+            }
+
+            let mut values = BTreeMap::new();
+            if let Some(stored) = table.get("values").and_then(Item::as_table) {
+                for (key, item) in stored.iter() {
+                    let value = item
+                        .as_value()
+                        .ok_or_else(|| format!("expected a value for ‘{module}.{key}’"))
+                        .and_then(Stored::from_toml)
+                        .map_err(|reason| Error::corrupt_store(&path, reason))?;
+                    values.insert(key.to_owned(), value);
+                }
+            }
+            self.modules.insert(module.to_owned(), values);
+        }
+        Ok(())
+    }
+
+    /// Write the store back to the file it was loaded from, if anything has changed.
+    pub(crate) fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.changed {
+            return Ok(());
+        }
+        fs::write(path, self.to_toml().to_string()).map_err(|e| Error::io(path, e))
+    }
+
+    fn to_toml(&self) -> Document {
+        let mut doc = Document::new();
+        for (module, values) in &self.modules {
+            if values.is_empty() {
+                continue;
+            }
+
+            let mut table = toml_edit::Table::new();
+            match self.versions.get(module).and_then(Option::as_ref) {
+                Some(version) => {
+                    table.insert("version", toml_edit::value(version));
+                }
+                None => table.set_implicit(true),
+            }
+            let mut stored = toml_edit::Table::new();
+            for (key, value) in values {
+                stored.insert(key, Item::Value(value.to_toml()));
+            }
+            table.insert("values", Item::Table(stored));
+            doc.insert(module, Item::Table(table));
+        }
+        doc
+    }
+
+    fn get(&self, module: &str, key: &str) -> Option<&Stored> {
+        self.modules.get(module)?.get(key)
+    }
+
+    fn set(&mut self, module: &str, key: &str, value: Option<Stored>) {
+        let values = self.modules.entry(module.to_owned()).or_default();
+        match value {
+            Some(value) => values.insert(key.to_owned(), value),
+            None => values.remove(key),
+        };
+        self.changed = true;
+    }
+}
+
+/// Find the commit from which each of `modules` is loaded, using the repository which holds the
+/// file `require` would load it from. A module not held in a repository is taken to be at the
+/// commit it was declared at, if any.
+pub(crate) fn resolve(lua: &Lua, modules: &[Module]) -> Result<HashMap<String, Option<String>>> {
+    modules
+        .iter()
+        .map(|module| {
+            let name = module.local_name();
+            let commit = transpiler::locate(lua, name)?
+                .and_then(|path| checkout_commit(Utf8Path::new(&path)))
+                .or_else(|| match module.version() {
+                    ModuleVersion::Hash(hash) => Some(hash.clone()),
+                    ModuleVersion::Tag(_) | ModuleVersion::Branch(_) => None,
+                });
+            Ok((name.to_owned(), commit))
+        })
+        .collect()
+}
+
+/// The commit checked out in the repository which holds the file at `path`, if any.
+#[cfg(feature = "git2")]
+fn checkout_commit(path: &Utf8Path) -> Option<String> {
+    crate::repo::head_commit(crate::util::parent_dir(path).as_std_path())
+        .ok()
+        .flatten()
+}
+
+#[cfg(not(feature = "git2"))]
+fn checkout_commit(_path: &Utf8Path) -> Option<String> {
+    None
+}
+
+/// A value which may be kept in the store.
+#[derive(Clone, Debug, PartialEq)]
+enum Stored {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Stored>),
+    Table(BTreeMap<String, Stored>),
+}
+
+impl Stored {
+    fn from_lua(value: Value, depth: usize) -> MLuaResult<Self> {
+        let unstorable = |reason: String| MLuaError::RuntimeError(format!("cannot store {reason}"));
+        match value {
+            Value::Boolean(b) => Ok(Self::Boolean(b)),
+            Value::Integer(i) => Ok(Self::Integer(i)),
+            Value::Number(n) => Ok(Self::Number(n)),
+            Value::String(s) => Ok(Self::String(s.to_str()?.to_owned())),
+            Value::Table(_) if depth >= MAX_DEPTH => Err(unstorable(format!(
+                "tables nested more than {MAX_DEPTH} deep"
+            ))),
+            Value::Table(table) => Self::from_lua_table(table, depth),
+            value => Err(unstorable(format!("a {}", value.type_name()))),
+        }
+    }
+
+    fn from_lua_table(table: Table, depth: usize) -> MLuaResult<Self> {
+        let len = table.raw_len();
+        let pairs = table
+            .clone()
+            .pairs::<Value, Value>()
+            .collect::<MLuaResult<Vec<_>>>()?;
+        if len > 0 && pairs.len() as i64 == len {
+            return table
+                .sequence_values()
+                .map(|value| Self::from_lua(value?, depth + 1))
+                .collect::<MLuaResult<_>>()
+                .map(Self::List);
+        }
+
+        let mut entries = BTreeMap::new();
+        for (key, value) in pairs {
+            let Value::String(key) = key else {
+                return Err(MLuaError::RuntimeError(format!(
+                    "cannot store a table with a {} key",
+                    key.type_name()
+                )));
+            };
+            entries.insert(key.to_str()?.to_owned(), Self::from_lua(value, depth + 1)?);
+        }
+        Ok(Self::Table(entries))
+    }
+
+    fn to_lua<'lua>(&self, lua: &'lua Lua) -> MLuaResult<Value<'lua>> {
+        Ok(match self {
+            Self::Boolean(b) => Value::Boolean(*b),
+            Self::Integer(i) => Value::Integer(*i),
+            Self::Number(n) => Value::Number(*n),
+            Self::String(s) => Value::String(lua.create_string(s)?),
+            Self::List(values) => Value::Table(
+                lua.create_sequence_from(
+                    values
+                        .iter()
+                        .map(|value| value.to_lua(lua))
+                        .collect::<MLuaResult<Vec<_>>>()?,
+                )?,
+            ),
+            Self::Table(entries) => Value::Table(
+                lua.create_table_from(
+                    entries
+                        .iter()
+                        .map(|(key, value)| Ok((key.as_str(), value.to_lua(lua)?)))
+                        .collect::<MLuaResult<Vec<_>>>()?,
+                )?,
+            ),
+        })
+    }
+
+    fn from_toml(value: &TomlValue) -> std::result::Result<Self, String> {
+        Ok(match value {
+            TomlValue::Boolean(b) => Self::Boolean(*b.value()),
+            TomlValue::Integer(i) => Self::Integer(*i.value()),
+            TomlValue::Float(n) => Self::Number(*n.value()),
+            TomlValue::String(s) => Self::String(s.value().to_owned()),
+            TomlValue::Array(values) => Self::List(
+                values
+                    .iter()
+                    .map(Self::from_toml)
+                    .collect::<std::result::Result<_, _>>()?,
+            ),
+            TomlValue::InlineTable(entries) => Self::Table(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((key.to_owned(), Self::from_toml(value)?)))
+                    .collect::<std::result::Result<_, String>>()?,
+            ),
+            TomlValue::Datetime(_) => return Err("unexpected datetime".into()),
+        })
+    }
+
+    fn to_toml(&self) -> TomlValue {
+        match self {
+            Self::Boolean(b) => (*b).into(),
+            Self::Integer(i) => (*i).into(),
+            Self::Number(n) => (*n).into(),
+            Self::String(s) => s.into(),
+            Self::List(values) => values.iter().map(Self::to_toml).collect::<Array>().into(),
+            Self::Table(entries) => entries
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.to_toml()))
+                .collect::<InlineTable>()
+                .into(),
+        }
+    }
+}
+
+/// Create the `em.store` table, through which each module may keep values between builds.
+/// Code outside of any module shares a single set of values.
+pub(crate) fn table(lua: &Lua) -> MLuaResult<Table<'_>> {
+    let store = lua.create_table_with_capacity(0, 2)?;
+    store.set("get", lua.create_function(get)?)?;
+    store.set("set", lua.create_function(set)?)?;
+    Ok(store)
+}
+
+fn get(lua: &Lua, key: String) -> MLuaResult<Value<'_>> {
+    let module = budgets::module(lua).unwrap_or_default();
+    let stored = lua
+        .app_data_ref::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .store
+        .get(&module, &key)
+        .cloned();
+    match stored {
+        Some(stored) => stored.to_lua(lua),
+        None => Ok(Value::Nil),
+    }
+}
+
+fn set(lua: &Lua, (key, value): (String, Value)) -> MLuaResult<()> {
+    let module = budgets::module(lua).unwrap_or_default();
+    let value = match value {
+        Value::Nil => None,
+        value => Some(Stored::from_lua(value, 0)?),
+    };
+    lua.app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .store
+        .set(&module, &key, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Context;
+    use std::collections::HashMap;
+
+    fn module(name: &str, version: ModuleVersion) -> Module {
+        Module::new(name.into(), None, version, HashMap::new())
+    }
+
+    #[test]
+    fn persistence() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.open_store(&dir)?;
+        ext_state.run(
+            r#"
+                assert(em.store.get('builds') == nil, 'unexpected value in new store')
+                em.store.set('builds', 1)
+                em.store.set('ratio', 0.5)
+                em.store.set('title', 'On the Origin of Burnt Toast')
+                em.store.set('figures', { 'toast.svg', { width = 12, shown = true } })
+                em.store.set('forgotten', 'soon')
+                em.store.set('forgotten', nil)
+            "#,
+        )?;
+        ext_state.save_store()?;
+
+        assert_eq!(
+            fs::read_to_string(dir.join(STORE_FILE)).unwrap(),
+            indoc::indoc!(
+                r#"
+                ["".values]
+                builds = 1
+                figures = ["toast.svg", { shown = true, width = 12 }]
+                ratio = 0.5
+                title = "On the Origin of Burnt Toast"
+                "#
+            )
+        );
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.open_store(&dir)?;
+        ext_state.run(
+            r#"
+                assert(em.store.get('builds') == 1, 'builds not kept')
+                assert(em.store.get('ratio') == 0.5, 'ratio not kept')
+                assert(em.store.get('title') == 'On the Origin of Burnt Toast', 'title not kept')
+                local figures = em.store.get('figures')
+                assert(figures[1] == 'toast.svg', 'list not kept')
+                assert(figures[2].width == 12 and figures[2].shown, 'table not kept')
+                assert(em.store.get('forgotten') == nil, 'removed value kept')
+            "#,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn submodules() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state.run(
+            r#"
+                package.preload['diagrams.render'] = function()
+                    em.store.set('renders', 1)
+                end
+                package.preload.diagrams = function()
+                    require 'diagrams.render'
+                    return em.store.get('renders')
+                end
+                assert(require 'diagrams' == 1, 'submodule values not shared with its module')
+                assert(em.store.get('renders') == nil, 'module values shared with document')
+            "#,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn invalidation() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(tmpdir.path().join(STORE_FILE)).unwrap();
+
+        let mut store = Store::new(HashMap::from([
+            ("diagrams".into(), Some("1f2e3d".into())),
+            ("citations".into(), Some("4c5b6a".into())),
+            ("glossary".into(), None),
+        ]));
+        store.set("diagrams", "cache", Some(Stored::String("old".into())));
+        store.set("citations", "style", Some(Stored::String("numeric".into())));
+        store.set("glossary", "terms", Some(Stored::Integer(12)));
+        store.set("local", "count", Some(Stored::Integer(3)));
+        store.path = Some(path.clone());
+        store.save()?;
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("[citations]\nversion = \"4c5b6a\"\n"),
+            "resolved commit not recorded"
+        );
+
+        let mut store = Store::new(HashMap::from([
+            ("diagrams".into(), Some("7a8b9c".into())),
+            ("citations".into(), Some("4c5b6a".into())),
+            ("glossary".into(), None),
+        ]));
+        store.load(path)?;
+        assert_eq!(store.get("diagrams", "cache"), None);
+        assert_eq!(
+            store.get("citations", "style"),
+            Some(&Stored::String("numeric".into()))
+        );
+        assert_eq!(store.get("glossary", "terms"), None);
+        assert_eq!(store.get("local", "count"), Some(&Stored::Integer(3)));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "git2")]
+    #[test]
+    fn resolution() -> Result<()> {
+        use git2::{Repository, Signature};
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        fs::write(dir.join("diagrams.lua"), "return {}").unwrap();
+        let commit = {
+            let repo = Repository::init(&dir).unwrap();
+            let mut index = repo.index().unwrap();
+            index
+                .add_path(std::path::Path::new("diagrams.lua"))
+                .unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::now("kcza", "kcza@example.com").unwrap();
+            repo.commit(Some("HEAD"), &sig, &sig, "initial commit", &tree, &[])
+                .unwrap()
+        };
+
+        let lua = Lua::new();
+        lua.globals()
+            .get::<_, Table>("package")?
+            .set("path", format!("{dir}/?.lua"))?;
+        let versions = resolve(
+            &lua,
+            &[
+                module("diagrams", ModuleVersion::Branch("main".into())),
+                module("citations", ModuleVersion::Hash("4c5b6a".into())),
+                module("glossary", ModuleVersion::Tag("v1.0".into())),
+            ],
+        )?;
+        assert_eq!(
+            versions,
+            HashMap::from([
+                ("diagrams".into(), Some(commit.to_string())),
+                ("citations".into(), Some("4c5b6a".into())),
+                ("glossary".into(), None),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(tmpdir.path().join(STORE_FILE)).unwrap();
+        fs::write(&path, "[diagrams\n").unwrap();
+
+        let mut store = Store::default();
+        let err = store.load(path.clone()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with(&format!("cannot read store {path}: ")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unstorable() -> Result<()> {
+        let lua = Lua::new();
+        for (src, expected) in [
+            ("return print", "cannot store a function"),
+            (
+                "return { [true] = 1 }",
+                "cannot store a table with a boolean key",
+            ),
+            (
+                "local t = {}; t.t = t; return t",
+                "cannot store tables nested more than 32 deep",
+            ),
+        ] {
+            let value = lua.load(src).eval()?;
+            let err = Stored::from_lua(value, 0).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("runtime error: {expected}"),
+                "unexpected error from {src}"
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use super::transpiler;
+use mlua::Error as MLuaError;
+
+/// Split a Lua error into its message and the frames of its traceback, innermost first. Chunk
+/// names of the form `[string "name"]` are replaced with the name of the chunk, so frames refer
+/// to the module file which raised the error. Lines of compiled YueScript and MoonScript modules
+/// are replaced with the lines of their sources.
+pub(crate) fn describe(error: &MLuaError) -> (String, Vec<String>) {
+    match error {
+        MLuaError::CallbackError { traceback, cause } => {
+            let (message, frames) = describe(cause);
+            if !frames.is_empty() {
+                return (message, frames);
+            }
+            (message, self::frames(traceback))
+        }
+        MLuaError::RuntimeError(msg)
+        | MLuaError::MemoryError(msg)
+        | MLuaError::SafetyError(msg)
+        | MLuaError::SyntaxError { message: msg, .. } => {
+            let (message, traceback) = match msg.split_once("stack traceback:") {
+                Some((message, traceback)) => (message, traceback),
+                None => (&msg[..], ""),
+            };
+            (chunk_names_replaced(message.trim()), frames(traceback))
+        }
+        error => (error.to_string(), vec![]),
+    }
+}
+
+fn frames(traceback: &str) -> Vec<String> {
+    traceback
+        .lines()
+        .map(str::trim)
+        .filter(|frame| {
+            !frame.is_empty() && *frame != "stack traceback:" && !frame.starts_with("[C]:")
+        })
+        .map(chunk_names_replaced)
+        .collect()
+}
+
+fn chunk_names_replaced(text: &str) -> String {
+    const OPEN: &str = "[string \"";
+    const CLOSE: &str = "\"]";
+
+    let mut ret = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        let Some(len) = rest[start + OPEN.len()..].find(CLOSE) else {
+            break;
+        };
+        ret.push_str(&rest[..start]);
+        ret.push_str(&rest[start + OPEN.len()..start + OPEN.len() + len]);
+        rest = &rest[start + OPEN.len() + len + CLOSE.len()..];
+    }
+    ret.push_str(rest);
+    transpiler::source_lines_replaced(&ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Context;
+
+    #[test]
+    fn chunk_names() {
+        assert_eq!(chunk_names_replaced("no chunks here"), "no chunks here");
+        assert_eq!(
+            chunk_names_replaced(r#"[string "figures.lua"]:12: bad figure"#),
+            "figures.lua:12: bad figure"
+        );
+        assert_eq!(
+            chunk_names_replaced(r#"[string "a"]:1: in [string "b"]:2"#),
+            "a:1: in b:2"
+        );
+        assert_eq!(chunk_names_replaced(r#"[string "a"#), r#"[string "a"#);
+    }
+
+    #[test]
+    fn runtime_errors() {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state().unwrap();
+        let err = ext_state
+            .lua()
+            .load(
+                r#"
+                    local function inner()
+                        error('something went wrong')
+                    end
+                    local function outer()
+                        inner()
+                    end
+                    outer()
+                "#,
+            )
+            .set_name("figures.lua")
+            .unwrap()
+            .exec()
+            .unwrap_err();
+
+        let (message, frames) = describe(&err);
+        assert_eq!(message, "figures.lua:3: something went wrong");
+        assert_eq!(
+            frames,
+            [
+                "figures.lua:3: in function 'inner'",
+                "figures.lua:6: in function 'outer'",
+                "figures.lua:8: in main chunk",
+            ]
+        );
+    }
+
+    #[test]
+    fn other_errors() {
+        let err = MLuaError::RuntimeError("plain message".into());
+        assert_eq!(describe(&err), ("plain message".into(), vec![]));
+
+        let err = MLuaError::ToLuaConversionError {
+            from: "thing",
+            to: "other",
+            message: None,
+        };
+        assert_eq!(describe(&err), (err.to_string(), vec![]));
+    }
+}
@@ -0,0 +1,379 @@
+use super::YUE_RKEY;
+use mlua::{Error as MLuaError, Function, Lua, Result as MLuaResult, Table, Value};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+    sync::Arc,
+};
+
+/// The extensions of module files which are compiled to Lua when loaded, in order of preference.
+/// MoonScript is compiled by the YueScript compiler, which accepts it.
+const EXTENSIONS: [&str; 2] = ["yue", "moon"];
+
+/// Whether the `$spec` blocks which hold a module's tests are compiled.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Specs {
+    Omitted,
+    Included,
+}
+
+impl Specs {
+    /// Code placed before each module to define the `$spec` macro. When specs are included, each
+    /// block is passed to `__emblem_spec` to be run later.
+    fn prelude(self) -> &'static str {
+        match self {
+            Self::Omitted => "macro spec = (t) -> \"(->)!\"\n",
+            Self::Included => concat!(
+                "macro spec = (t) ->\n",
+                "\tlines = { '__emblem_spec ->\\n', '\\ttest = ' }\n",
+                "\tfirst = true\n",
+                "\tfor line in t\\gmatch '([^\\r\\n]*)[\\r\\n]?'\n",
+                "\t\tif first\n",
+                "\t\t\tfirst = false\n",
+                "\t\telse\n",
+                "\t\t\tlines[] = '\\t\\t'\n",
+                "\t\tlines[] = line\n",
+                "\t\tlines[] = '\\n'\n",
+                "\tlines[] = '\\ttest!'\n",
+                "\ttable.concat lines\n",
+            ),
+        }
+    }
+}
+
+/// Compiled modules, shared between all Lua states.
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct Cache {
+    by_content: HashMap<(Specs, u64), Arc<Compiled>>,
+    by_chunk: HashMap<String, Arc<Compiled>>,
+}
+
+/// The result of compiling a module.
+#[derive(Debug)]
+struct Compiled {
+    lua: String,
+
+    /// The source line from which each line of the compiled code was generated.
+    source_lines: Vec<u32>,
+}
+
+impl Compiled {
+    /// Record the compiled code `lua`, whose input was preceded by `prelude_lines` lines not
+    /// present in the source.
+    fn new(lua: String, prelude_lines: u32) -> Self {
+        let mut source_lines = Vec::new();
+        let mut curr = 1;
+        for line in lua.lines() {
+            if let Some(source_line) = Self::source_line(line) {
+                curr = source_line.saturating_sub(prelude_lines).max(1);
+            }
+            source_lines.push(curr);
+        }
+        Self { lua, source_lines }
+    }
+
+    /// Extract the source line number the compiler appends to each line of its output, in a
+    /// comment of the form `-- 12`.
+    fn source_line(line: &str) -> Option<u32> {
+        let (_, comment) = line.rsplit_once("-- ")?;
+        comment.trim_end().parse().ok()
+    }
+
+    fn source_line_of(&self, lua_line: u32) -> Option<u32> {
+        let idx = lua_line.checked_sub(1)? as usize;
+        self.source_lines.get(idx).copied()
+    }
+}
+
+/// Make `require` able to load modules written in YueScript and MoonScript, searching for them
+/// alongside Lua modules.
+pub(crate) fn add_searcher(lua: &Lua) -> MLuaResult<()> {
+    let Some(package) = lua.globals().get::<_, Option<Table>>("package")? else {
+        return Ok(());
+    };
+    let Some(loaders) = package.get::<_, Option<Table>>("loaders")? else {
+        return Ok(());
+    };
+    loaders.raw_insert(loaders.raw_len() + 1, lua.create_function(search)?)
+}
+
+/// Find and compile the module `name`, returning a function which runs it. If no such module
+/// exists, a description of the places searched is returned instead.
+fn search<'lua>(lua: &'lua Lua, name: String) -> MLuaResult<Value<'lua>> {
+    let path = package_path(lua)?;
+
+    let mut tried = String::new();
+    for candidate in candidates(&path, &name) {
+        let Ok(src) = fs::read_to_string(&candidate) else {
+            tried.push_str(&format!("\n\tno file '{candidate}'"));
+            continue;
+        };
+
+        return Ok(Value::Function(load(
+            lua,
+            &candidate,
+            &src,
+            Specs::Omitted,
+        )?));
+    }
+
+    Ok(Value::String(lua.create_string(&tried)?))
+}
+
+/// Find the file from which `require` would load the module `name`, if any.
+pub(crate) fn locate(lua: &Lua, name: &str) -> MLuaResult<Option<String>> {
+    let path = package_path(lua)?;
+    let stem = name.replace('.', "/");
+    let lua_candidates = path.split(';').map(|template| template.replace('?', &stem));
+    let found = lua_candidates
+        .chain(candidates(&path, name))
+        .find(|candidate| Path::new(candidate).is_file());
+    Ok(found)
+}
+
+/// The Lua search path, as used by `require`.
+fn package_path(lua: &Lua) -> MLuaResult<String> {
+    Ok(match lua.globals().get::<_, Option<Table>>("package")? {
+        Some(package) => package
+            .get::<_, Option<String>>("path")?
+            .unwrap_or_default(),
+        None => String::new(),
+    })
+}
+
+/// The files which may hold the YueScript or MoonScript module `name`, in order of preference,
+/// given the Lua search path `path`.
+fn candidates<'a>(path: &'a str, name: &str) -> impl Iterator<Item = String> + 'a {
+    let stem = name.replace('.', "/");
+    path.split(';')
+        .filter_map(|template| template.strip_suffix(".lua"))
+        .flat_map(move |template| {
+            let base = template.replace('?', &stem);
+            EXTENSIONS.iter().map(move |ext| format!("{base}.{ext}"))
+        })
+}
+
+/// Compile the YueScript or MoonScript module `src`, read from the file named `chunk`, returning a
+/// function which runs it.
+pub(crate) fn load<'lua>(
+    lua: &'lua Lua,
+    chunk: &str,
+    src: &str,
+    specs: Specs,
+) -> MLuaResult<Function<'lua>> {
+    let compiled = compile(lua, chunk, src, specs)?;
+    lua.load(&compiled.lua).set_name(chunk)?.into_function()
+}
+
+fn compile(lua: &Lua, chunk: &str, src: &str, specs: Specs) -> MLuaResult<Arc<Compiled>> {
+    let hash = {
+        let mut hasher = DefaultHasher::new();
+        src.hash(&mut hasher);
+        (specs, hasher.finish())
+    };
+
+    let cached = CACHE.lock().by_content.get(&hash).cloned();
+    let compiled = match cached {
+        Some(compiled) => compiled,
+        None => {
+            let to_lua: mlua::Function = compiler(lua)?.get("to_lua")?;
+            let options = lua.create_table_with_capacity(0, 3)?;
+            options.set("implicit_return_root", true)?;
+            options.set("reserve_line_number", true)?;
+            options.set("target", "5.1")?;
+            let prelude = specs.prelude();
+            let prelude_lines = prelude.lines().count() as u32;
+            let (code, err): (Option<String>, Option<String>) =
+                to_lua.call((format!("{prelude}{src}"), options))?;
+            let Some(code) = code else {
+                return Err(MLuaError::RuntimeError(format!(
+                    "failed to compile {chunk}: {}",
+                    err.map_or_else(
+                        || "unknown error".into(),
+                        |err| error_in_source(&err, prelude_lines)
+                    )
+                )));
+            };
+            Arc::new(Compiled::new(code, prelude_lines))
+        }
+    };
+
+    let mut cache = CACHE.lock();
+    cache.by_content.insert(hash, compiled.clone());
+    cache.by_chunk.insert(chunk.to_owned(), compiled.clone());
+    Ok(compiled)
+}
+
+/// Correct the line number which starts a compiler error, of the form `12: message`, to refer to
+/// the source rather than the input, which was preceded by `prelude_lines` lines.
+fn error_in_source(err: &str, prelude_lines: u32) -> String {
+    let Some((line, rest)) = err.split_once(": ") else {
+        return err.to_owned();
+    };
+    match line.parse::<u32>() {
+        Ok(line) => format!("{}: {rest}", line.saturating_sub(prelude_lines).max(1)),
+        Err(_) => err.to_owned(),
+    }
+}
+
+/// The YueScript compiler, which is opened the first time it is needed.
+fn compiler(lua: &Lua) -> MLuaResult<Table<'_>> {
+    if let Some(yue) = lua.named_registry_value::<_, Option<Table>>(YUE_RKEY)? {
+        return Ok(yue);
+    }
+    let Value::Table(yue) = yuescript::open(lua)? else {
+        return Err(MLuaError::RuntimeError(
+            "the YueScript compiler is unavailable".into(),
+        ));
+    };
+    lua.set_named_registry_value(YUE_RKEY, yue.clone())?;
+    Ok(yue)
+}
+
+/// Replace references to lines of compiled modules, of the form `chunk:line`, with the
+/// corresponding lines of their sources.
+pub(crate) fn source_lines_replaced(text: &str) -> String {
+    let cache = CACHE.lock();
+    if cache.by_chunk.is_empty() {
+        return text.to_owned();
+    }
+
+    let mut ret = text.to_owned();
+    for (chunk, compiled) in &cache.by_chunk {
+        ret = replace_lines(&ret, chunk, compiled);
+    }
+    ret
+}
+
+fn replace_lines(text: &str, chunk: &str, compiled: &Compiled) -> String {
+    let prefix = format!("{chunk}:");
+    let mut ret = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(&prefix) {
+        let after = start + prefix.len();
+        let digits = rest[after..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len() - after);
+        ret.push_str(&rest[..after]);
+        let line = &rest[after..after + digits];
+        match line.parse().ok().and_then(|l| compiled.source_line_of(l)) {
+            Some(source_line) => ret.push_str(&source_line.to_string()),
+            None => ret.push_str(line),
+        }
+        rest = &rest[after + digits..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{extensions::traceback, Context, Result};
+
+    #[test]
+    fn source_lines() {
+        let compiled = Compiled::new(
+            [
+                "local figures = { } -- 1",
+                "figures.render = function(self)",
+                "  return error('no figures') -- 4",
+                "end -- 3",
+                "return figures",
+            ]
+            .join("\n"),
+            0,
+        );
+        assert_eq!(compiled.source_lines, [1, 1, 4, 3, 3]);
+        assert_eq!(compiled.source_line_of(0), None);
+        assert_eq!(compiled.source_line_of(3), Some(4));
+        assert_eq!(compiled.source_line_of(6), None);
+
+        assert_eq!(
+            replace_lines(
+                "mods/figures.yue:3: no figures (mods/figures.yue:5, mods/figures.yue:99, other.yue:3)",
+                "mods/figures.yue",
+                &compiled
+            ),
+            "mods/figures.yue:4: no figures (mods/figures.yue:3, mods/figures.yue:99, other.yue:3)"
+        );
+        assert_eq!(
+            replace_lines("mods/figures.yue:", "mods/figures.yue", &compiled),
+            "mods/figures.yue:"
+        );
+    }
+
+    #[test]
+    fn error_lines() {
+        assert_eq!(
+            error_in_source("13: syntax error\nz = (\n    ^", 10),
+            "3: syntax error\nz = (\n    ^"
+        );
+        assert_eq!(error_in_source("1: bad prelude", 10), "1: bad prelude");
+        assert_eq!(error_in_source("no line: here", 10), "no line: here");
+    }
+
+    #[test]
+    fn yue_compile_errors() -> Result<()> {
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        for specs in [Specs::Omitted, Specs::Included] {
+            let err = compile(
+                ext_state.lua(),
+                "mods/broken.yue",
+                "x = 1\ny = 2\nz = (\n",
+                specs,
+            )
+            .unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("failed to compile mods/broken.yue: 3: "),
+                "unexpected error with {specs:?}: {err}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn yue_modules() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = camino::Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        fs::write(
+            dir.join("greeting.yue"),
+            "greet = (name) ->\n  \"hello, #{name}\"\n\n{ :greet }\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("farewell.moon"),
+            "farewell = (name) ->\n  error \"goodbye, #{name}\"\n\n{ :farewell }\n",
+        )
+        .unwrap();
+
+        let ctx = Context::test_new();
+        let ext_state = ctx.extension_state()?;
+        ext_state
+            .lua()
+            .globals()
+            .get::<_, Table>("package")?
+            .set("path", format!("{dir}/?.lua"))?;
+        ext_state.run("assert(require('greeting').greet('world') == 'hello, world')")?;
+
+        let Err(err) = ext_state
+            .lua()
+            .load("require('farewell').farewell('world')")
+            .exec()
+        else {
+            panic!("expected an error");
+        };
+        let (message, _) = traceback::describe(&err);
+        assert_eq!(message, format!("{dir}/farewell.moon:2: goodbye, world"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,459 @@
+use super::{commands, doc::DocNode, ExtensionData};
+use crate::build::typesetter::doc::DocElem;
+use mlua::{Lua, Result as MLuaResult, Table};
+use std::collections::BTreeMap;
+
+/// The route from the root of the document to the command which opens a scope.
+type ScopeId = Vec<usize>;
+
+/// The variables set within a document. Variables are held in scopes which follow the nesting of
+/// commands: each command opens a scope for its args and the root of the document holds the
+/// outermost scope. The paragraphs inserted by the typesetter open no scope of their own.
+/// Variables persist across typesetting iterations.
+#[derive(Debug, Default)]
+pub(crate) struct Vars {
+    scopes: BTreeMap<ScopeId, Scope>,
+}
+
+#[derive(Debug)]
+struct Scope {
+    /// A description of the command which opened this scope.
+    label: String,
+
+    vars: BTreeMap<String, String>,
+}
+
+impl Vars {
+    /// The value of the variable `name`, searching outward from the innermost of `chain`. Each
+    /// match found is skipped while `widening` remains.
+    fn get(&mut self, chain: &[(ScopeId, String)], name: &str, widening: usize) -> Option<&str> {
+        self.forget_stale(chain);
+        chain
+            .iter()
+            .filter_map(|(id, _)| self.scopes.get(id)?.vars.get(name))
+            .nth(widening)
+            .map(String::as_str)
+    }
+
+    /// Set the variable `name` in the scope `widening` levels outside the innermost of `chain`,
+    /// or unset it if `value` is `None`.
+    fn set(
+        &mut self,
+        chain: &[(ScopeId, String)],
+        name: &str,
+        value: Option<String>,
+        widening: usize,
+    ) {
+        self.forget_stale(chain);
+        let (id, label) = &chain[widening.min(chain.len() - 1)];
+        self.assign(id, label, name, value);
+    }
+
+    /// Set the variable `name` where it is found searching outward from the innermost of `chain`,
+    /// skipping each match found while `widening` remains. If there is no such variable, it is set
+    /// in the outermost scope.
+    fn find_set(
+        &mut self,
+        chain: &[(ScopeId, String)],
+        name: &str,
+        value: String,
+        widening: usize,
+    ) {
+        self.forget_stale(chain);
+        let (id, label) = chain
+            .iter()
+            .filter(|(id, _)| {
+                self.scopes
+                    .get(id)
+                    .is_some_and(|scope| scope.vars.contains_key(name))
+            })
+            .nth(widening)
+            .unwrap_or(&chain[chain.len() - 1]);
+        self.assign(id, label, name, Some(value));
+    }
+
+    fn assign(&mut self, id: &ScopeId, label: &str, name: &str, value: Option<String>) {
+        if self
+            .scopes
+            .get(id)
+            .is_some_and(|scope| scope.label != label)
+        {
+            self.clear_within(id);
+        }
+        match value {
+            Some(value) => {
+                self.scopes
+                    .entry(id.clone())
+                    .or_insert_with(|| Scope {
+                        label: label.to_owned(),
+                        vars: BTreeMap::new(),
+                    })
+                    .vars
+                    .insert(name.to_owned(), value);
+            }
+            None => {
+                if let Some(scope) = self.scopes.get_mut(id) {
+                    scope.vars.remove(name);
+                }
+            }
+        }
+    }
+
+    /// Remove the scopes of `chain` which were opened by a different command at the same position
+    /// in an earlier version of the document, along with those within them.
+    fn forget_stale(&mut self, chain: &[(ScopeId, String)]) {
+        for (id, label) in chain {
+            if self
+                .scopes
+                .get(id)
+                .is_some_and(|scope| &scope.label != label)
+            {
+                self.clear_within(id);
+            }
+        }
+    }
+
+    /// Remove the variables in the scope opened at `id` and in those within it.
+    fn clear_within(&mut self, id: &[usize]) {
+        self.scopes.retain(|scope_id, _| !scope_id.starts_with(id));
+    }
+
+    /// A description of each scope and the variables it contains, outermost first.
+    fn describe(&self) -> String {
+        self.scopes
+            .values()
+            .filter(|scope| !scope.vars.is_empty())
+            .map(|scope| {
+                let vars = scope
+                    .vars
+                    .iter()
+                    .map(|(name, value)| format!("{name} = {value}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}: {vars}", scope.label)
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Split the leading exclamation marks which widen the scope searched from a variable name. As
+/// in the command `.set-var{\!name}{value}`, the first may be escaped.
+fn widening(name: &str) -> (&str, usize) {
+    let name = match name.strip_prefix('\\') {
+        Some(rest) if rest.starts_with('!') => rest,
+        _ => name,
+    };
+    let stripped = name.trim_start_matches('!');
+    (stripped, name.len() - stripped.len())
+}
+
+/// The scope opened by `node`, if it is a command written in the source.
+fn scope_of(node: &DocNode) -> MLuaResult<Option<(ScopeId, String)>> {
+    let label = node.with(|elem| match elem {
+        _ if elem.is_implicit_par() => None,
+        DocElem::Command { name, loc, .. } => Some(format!(".{} at {loc}", name.as_str())),
+        _ => None,
+    })?;
+    Ok(label.map(|label| (node.path().to_vec(), label)))
+}
+
+/// The scopes within which `node` lies, innermost first. If `node` is not given, only the
+/// outermost scope is returned.
+fn scope_chain(node: Option<&DocNode>) -> MLuaResult<Vec<(ScopeId, String)>> {
+    let mut chain = Vec::new();
+    if let Some(node) = node {
+        for ancestor in node.ancestors() {
+            chain.extend(scope_of(&ancestor)?);
+        }
+    }
+    if !chain.last().is_some_and(|(id, _)| id.is_empty()) {
+        chain.push((ScopeId::new(), "document".into()));
+    }
+    Ok(chain)
+}
+
+/// Forget the variables set within the args of the command `call`, so that it may start afresh.
+pub(super) fn clear_scope(lua: &Lua, call: &DocNode) {
+    with_vars(lua, |vars| vars.clear_within(call.path()));
+}
+
+/// Set the variable `name` in the scope opened by the command `call`, which holds its args.
+pub(super) fn set_within(lua: &Lua, call: &DocNode, name: &str, value: String) -> MLuaResult<()> {
+    if let Some((id, label)) = scope_of(call)? {
+        with_vars(lua, |vars| vars.assign(&id, &label, name, Some(value)));
+    }
+    Ok(())
+}
+
+fn with_vars<R>(lua: &Lua, f: impl FnOnce(&mut Vars) -> R) -> R {
+    f(&mut lua
+        .app_data_mut::<ExtensionData>()
+        .expect("internal error: lua app data not set")
+        .vars)
+}
+
+/// Define the built-in commands which read and write variables.
+pub(crate) fn define_commands(lua: &Lua) -> MLuaResult<()> {
+    commands::define_builtin(lua, "set-var", lua.create_function(set_var_command)?)?;
+    commands::define_builtin(
+        lua,
+        "find-set-var",
+        lua.create_function(find_set_var_command)?,
+    )?;
+    commands::define_builtin(lua, "get-var", lua.create_function(get_var_command)?)?;
+    commands::define_builtin(lua, "vars", lua.create_function(vars_command)?)
+}
+
+/// Set a variable in the scope of the call, as in `.set-var{name}{value}`.
+fn set_var_command(lua: &Lua, (args, _, call): (Vec<DocNode>, Table, DocNode)) -> MLuaResult<()> {
+    if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+        return Ok(());
+    }
+    let raw_name = args[0].with(DocElem::text)?;
+    let (name, widening) = widening(&raw_name);
+    let value = args[1].with(DocElem::text)?;
+    let chain = scope_chain(Some(&call))?;
+    with_vars(lua, |vars| vars.set(&chain, name, Some(value), widening));
+    Ok(())
+}
+
+/// Set a variable where it was last set, as in `.find-set-var{name}{value}`.
+fn find_set_var_command(
+    lua: &Lua,
+    (args, _, call): (Vec<DocNode>, Table, DocNode),
+) -> MLuaResult<()> {
+    if !commands::check_num_args(lua, &call, &args, 2..=2)? {
+        return Ok(());
+    }
+    let raw_name = args[0].with(DocElem::text)?;
+    let (name, widening) = widening(&raw_name);
+    let value = args[1].with(DocElem::text)?;
+    let chain = scope_chain(Some(&call))?;
+    with_vars(lua, |vars| vars.find_set(&chain, name, value, widening));
+    Ok(())
+}
+
+/// Get the value of a variable visible from the call, as in `.get-var{name}`.
+fn get_var_command(
+    lua: &Lua,
+    (args, _, call): (Vec<DocNode>, Table, DocNode),
+) -> MLuaResult<Option<String>> {
+    if !commands::check_num_args(lua, &call, &args, 1..=1)? {
+        return Ok(None);
+    }
+    let raw_name = args[0].with(DocElem::text)?;
+    let (name, widening) = widening(&raw_name);
+    let chain = scope_chain(Some(&call))?;
+    Ok(with_vars(lua, |vars| {
+        vars.get(&chain, name, widening).map(ToOwned::to_owned)
+    }))
+}
+
+/// Describe the scopes which hold variables, as in `.vars`.
+fn vars_command(lua: &Lua, (args, _, call): (Vec<DocNode>, Table, DocNode)) -> MLuaResult<String> {
+    commands::check_num_args(lua, &call, &args, 0..=0)?;
+    Ok(with_vars(lua, |vars| vars.describe()))
+}
+
+/// Create the `em.vars` table, through which extensions may read and write the same variables
+/// as the document.
+pub(crate) fn table(lua: &Lua) -> MLuaResult<Table<'_>> {
+    let vars = lua.create_table_with_capacity(0, 2)?;
+    vars.set("get", lua.create_function(get)?)?;
+    vars.set("set", lua.create_function(set)?)?;
+    Ok(vars)
+}
+
+/// Get the value of a variable, as requested by a call to `em.vars.get(name, at)`. If the node
+/// `at` is given, the search starts in the scope in which it lies, otherwise only the outermost
+/// scope is searched.
+fn get(lua: &Lua, (raw_name, at): (String, Option<DocNode>)) -> MLuaResult<Option<String>> {
+    let (name, widening) = widening(&raw_name);
+    let chain = scope_chain(at.as_ref())?;
+    Ok(with_vars(lua, |vars| {
+        vars.get(&chain, name, widening).map(ToOwned::to_owned)
+    }))
+}
+
+/// Set the value of a variable, as requested by a call to `em.vars.set(name, value, at)`. If the
+/// node `at` is given, the variable is set in the scope in which it lies, otherwise in the
+/// outermost scope. A `nil` value unsets the variable.
+fn set(
+    lua: &Lua,
+    (raw_name, value, at): (String, Option<String>, Option<DocNode>),
+) -> MLuaResult<()> {
+    let (name, widening) = widening(&raw_name);
+    let chain = scope_chain(at.as_ref())?;
+    with_vars(lua, |vars| vars.set(&chain, name, value, widening));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ScopeId, Vars};
+    use crate::{build::typesetter::test::typeset, log::MessageType, Context, Reiteration, Result};
+
+    #[test]
+    fn widening() {
+        assert_eq!(super::widening("name"), ("name", 0));
+        assert_eq!(super::widening("!!name"), ("name", 2));
+        assert_eq!(super::widening("\\!name"), ("name", 1));
+        assert_eq!(super::widening("\\name"), ("\\name", 0));
+    }
+
+    #[test]
+    fn scopes() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            concat!(
+                ".set-var{product}{Emblem}\n\n",
+                ".set-var{version}{1.0}\n\n",
+                ".sec{.set-var{product}{Inner} .set-var{\\!version}{2.0} .get-var{product} .get-var{\\!product}}\n\n",
+                ".get-var{product} .get-var{version} .get-var{missing}\n",
+            ),
+        )?;
+        assert_eq!(
+            repr,
+            "[[]|[]|.sec{[[]|[]|Word(Inner)|Word(Emblem)]}|.p{[Word(Emblem)|Word(2.0)|[]]}]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn paragraphs() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            concat!(
+                "Product: .set-var{product}{Emblem} .set-var{version}{1.0}\n\n",
+                ".get-var{product} .get-var{version}\n",
+            ),
+        )?;
+        assert_eq!(
+            repr,
+            "[.p{[Word(Product:)|[]|[]]}|.p{[Word(Emblem)|Word(1.0)]}]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_set_var() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            concat!(
+                ".set-var{count}{1}\n\n",
+                ".sec{.find-set-var{count}{2} .find-set-var{fresh}{yes}}\n\n",
+                ".get-var{count} .get-var{fresh}\n",
+            ),
+        )?;
+        assert_eq!(repr, "[[]|.sec{[[]|[]]}|.p{[Word(2)|Word(yes)]}]");
+        Ok(())
+    }
+
+    #[test]
+    fn stale_scopes() {
+        let mut vars = Vars::default();
+        let document = (ScopeId::new(), "document".to_owned());
+        let sec = (vec![0], ".sec at vars.em:1:1".to_owned());
+        let note = (vec![0], ".note at vars.em:1:1".to_owned());
+        let inner = (vec![0, 0, 1], ".sec at vars.em:1:6".to_owned());
+
+        vars.set(
+            &[inner.clone(), sec.clone(), document.clone()],
+            "x",
+            Some("1".into()),
+            0,
+        );
+        vars.set(&[sec.clone(), document.clone()], "x", Some("2".into()), 0);
+        assert_eq!(
+            vars.get(&[sec.clone(), document.clone()], "x", 0),
+            Some("2")
+        );
+
+        assert_eq!(vars.get(&[note.clone(), document.clone()], "x", 0), None);
+        assert_eq!(vars.get(&[sec.clone(), document.clone()], "x", 0), None);
+        assert_eq!(vars.describe(), "");
+
+        vars.set(&[sec.clone(), document.clone()], "x", Some("3".into()), 0);
+        vars.assign(&note.0, &note.1, "y", Some("4".into()));
+        assert_eq!(vars.describe(), ".note at vars.em:1:1: y = 4");
+    }
+
+    #[test]
+    fn persistence() -> Result<()> {
+        let ctx = {
+            let mut ctx = Context::test_new();
+            ctx.typesetter_params_mut()
+                .set_reiteration(Reiteration::UntilConverged);
+            ctx
+        };
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            ".get-var{later}\n\n.set-var{later}{found}\n",
+        )?;
+        assert_eq!(repr, "[Word(found)|[]]");
+        Ok(())
+    }
+
+    #[test]
+    fn lua_api() -> Result<()> {
+        let ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.vars.set('product', 'Emblem')
+                em.define('shout', function(args, attrs, call)
+                    em.vars.set('product', 'EMBLEM', call)
+                    return em.vars.get('product', call) .. '!'
+                end)
+                em.on('done', function()
+                    assert(em.vars.get('product') == 'Emblem', 'outer value changed')
+                    em.vars.set('product', nil)
+                    assert(em.vars.get('product') == nil, 'value not unset')
+                end)
+            "#,
+        )?;
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            ".set-var{edition}{.get-var{product}}\n\n.get-var{edition}\n\n.sec{.shout}\n",
+        )?;
+        assert_eq!(repr, "[[]|Word(Emblem)|.sec{Word(EMBLEM!)}]");
+        Ok(())
+    }
+
+    #[test]
+    fn describe() -> Result<()> {
+        let ctx = Context::test_new();
+        let repr = typeset(
+            &ctx,
+            "vars.em",
+            ".set-var{a}{1}\n\n.sec{.set-var{b}{2} .vars}\n",
+        )?;
+        assert_eq!(
+            repr,
+            "[[]|.sec{[[]|Word(document: a = 1; .sec at vars.em:3:1-4: b = 2)]}]"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_num_args() -> Result<()> {
+        let ctx = Context::test_new();
+        let err = typeset(&ctx, "vars.em", ".set-var{lonely}\n").unwrap_err();
+        assert_eq!(err.to_string(), "extensions reported 1 error");
+
+        let logs = ctx.logger().logs().to_vec();
+        assert_eq!(logs.len(), 1, "{logs:?}");
+        assert_eq!(logs[0].msg_type(), MessageType::Error);
+        assert_eq!(logs[0].msg(), "too few arguments passed to .set-var");
+        assert_eq!(logs[0].srcs()[0].loc().to_string(), "vars.em:1:1-8");
+        Ok(())
+    }
+}
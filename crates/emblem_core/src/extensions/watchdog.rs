@@ -0,0 +1,87 @@
+use crate::context::Time;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+};
+
+/// Enforces the limit on the wall-clock time extensions may spend running. The clock starts when
+/// extension code first runs, after which a timer thread raises a flag for the safety hook to
+/// check. The timer is cancelled when the watchdog is dropped.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    max_time: Time,
+    expired: Arc<AtomicBool>,
+    cancel: Option<Sender<()>>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(max_time: Time) -> Self {
+        Self {
+            max_time,
+            expired: Arc::new(AtomicBool::new(false)),
+            cancel: None,
+        }
+    }
+
+    /// Start the clock, if it is not already running.
+    pub(crate) fn start(&mut self) {
+        if self.cancel.is_some() {
+            return;
+        }
+
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        self.cancel = Some(cancel);
+
+        let max_time = self.max_time.as_std();
+        let expired = self.expired.clone();
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(max_time) {
+                expired.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// The limit on the time extensions may spend running.
+    pub(crate) fn max_time(&self) -> Time {
+        self.max_time
+    }
+
+    /// Whether the time limit has been reached.
+    pub(crate) fn expired(&self) -> bool {
+        self.expired.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn expires() {
+        let mut watchdog = Watchdog::new(Time(0));
+        assert!(!watchdog.expired(), "expired before starting");
+
+        watchdog.start();
+        let start = Instant::now();
+        while !watchdog.expired() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "watchdog did not expire"
+            );
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn waits_for_limit() {
+        let mut watchdog = Watchdog::new(Time(60));
+        watchdog.start();
+        thread::sleep(Duration::from_millis(10));
+        assert!(!watchdog.expired());
+    }
+}
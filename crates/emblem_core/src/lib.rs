@@ -13,10 +13,12 @@ mod error;
 pub mod explain;
 mod extensions;
 pub mod lint;
+pub mod list;
 pub mod parser;
 mod path;
 mod repo;
 mod result;
+pub mod spec;
 mod util;
 mod version;
 
@@ -29,19 +31,23 @@ pub use crate::{
             doc::{Doc, DocElem},
             Typesetter,
         },
-        Builder,
+        BuildOutcome, Builder, ProfileOutput,
     },
     context::{
         file_content::{FileContent, FileContentSlice},
         file_name::FileName,
-        Context, ResourceLimit, SandboxLevel,
+        Context, Reiteration, ResourceLimit, SandboxLevel,
     },
     error::Error,
     explain::Explainer,
-    extensions::ExtensionState,
+    extensions::{ExtensionState, Profile, SpecOutcome},
     lint::Linter,
+    list::{Lister, Listing},
     log::{Log, Verbosity},
+    path::SearchPath,
+    repo::RepoInfo,
     result::{ErrorContext, Result},
+    spec::Tester,
     version::Version,
 };
 
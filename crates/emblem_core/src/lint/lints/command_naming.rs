@@ -27,27 +27,55 @@ impl Lint for CommandNaming {
         match content {
             Content::Command {
                 name,
+                inline_args,
                 loc,
                 invocation_loc,
                 ..
             } => {
+                let mut problems = vec![];
                 if !CONFORMANT_NAME.is_match(name.to_str()) {
-                    return vec![Log::warning(format!(
-                        "commands should be lowercase with dashes: got ‘.{name}’"
-                    ))
-                    .with_src(Src::new(loc).with_annotation(Note::help(
-                        invocation_loc,
-                        format!(
-                            "try changing this to ‘.{}’",
-                            name.to_str().to_lowercase().replace('_', "-")
+                    problems.push(
+                        Log::warning(format!(
+                            "commands should be lowercase with dashes: got ‘.{name}’"
+                        ))
+                        .with_src(Src::new(loc).with_annotation(Note::help(
+                            invocation_loc,
+                            format!("try changing this to ‘.{}’", conformant(name.to_str())),
+                        )))
+                        .with_note(
+                            "command-names are case-insensitive but lowercase reads more fluidly",
                         ),
-                    )))
-                    .with_note(
-                        "command-names are case-insensitive but lowercase reads more fluidly",
-                    )];
+                    );
                 }
 
-                vec![]
+                if name.to_str() == "def" {
+                    if let Some(
+                        [Content::Word {
+                            word,
+                            loc: word_loc,
+                        }],
+                    ) = inline_args.first().map(Vec::as_slice)
+                    {
+                        if !CONFORMANT_NAME.is_match(word.to_str()) {
+                            problems.push(
+                                Log::warning(format!(
+                                    "commands should be lowercase with dashes: defined ‘.{word}’"
+                                ))
+                                .with_src(
+                                    Src::new(loc).with_annotation(Note::help(
+                                        word_loc,
+                                        format!(
+                                            "try changing this to ‘{}’",
+                                            conformant(word.to_str())
+                                        ),
+                                    )),
+                                ),
+                            );
+                        }
+                    }
+                }
+
+                problems
             }
             Content::Shebang { .. }
             | Content::Word { .. }
@@ -63,6 +91,11 @@ impl Lint for CommandNaming {
     }
 }
 
+/// A conformant spelling of the command name `name`.
+fn conformant(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -106,5 +139,20 @@ mod test {
                     r":1:1-4: try changing this to ‘.φoo’",
                 ],
             );
+        LintTest::new("defined", CommandNaming::new())
+            .input(".def{foo-bar}{baz}")
+            .passes();
+        LintTest::new("defined-computed", CommandNaming::new())
+            .input(".def{.get-var{name}}{baz}")
+            .passes();
+        LintTest::new("defined-uppercase", CommandNaming::new())
+            .input(".def{FooBar}{baz}")
+            .causes(
+                1,
+                &[
+                    r"commands should be lowercase with dashes: defined ‘.FooBar’",
+                    r":1:6-11: try changing this to ‘foobar’",
+                ],
+            );
     }
 }
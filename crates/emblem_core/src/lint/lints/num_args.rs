@@ -32,6 +32,36 @@ lazy_static! {
             ("h5", (1, 1)),
             ("h6", (1, 1)),
             ("if", (2, 3)),
+            ("case", (2, usize::MAX)),
+            ("add", (2, 2)),
+            ("sub", (2, 2)),
+            ("mul", (2, 2)),
+            ("div", (2, 2)),
+            ("idiv", (2, 2)),
+            ("mod", (2, 2)),
+            ("pow", (2, 2)),
+            ("abs", (1, 1)),
+            ("sign", (1, 1)),
+            ("eq", (2, 2)),
+            ("streq", (2, 2)),
+            ("numeq", (2, 2)),
+            ("lt", (2, usize::MAX)),
+            ("le", (2, usize::MAX)),
+            ("gt", (2, usize::MAX)),
+            ("ge", (2, usize::MAX)),
+            ("not", (1, 1)),
+            ("xor", (2, 2)),
+            ("any", (0, usize::MAX)),
+            ("all", (0, usize::MAX)),
+            ("for", (4, 4)),
+            ("foreach", (3, 3)),
+            ("while", (2, 2)),
+            ("set-var", (2, 2)),
+            ("find-set-var", (2, 2)),
+            ("get-var", (1, 1)),
+            ("vars", (0, 0)),
+            ("def", (2, 2)),
+            ("undef", (1, 1)),
         ]
         .into_iter()
         .collect()
@@ -167,7 +197,7 @@ mod test {
         for (command, (min, max)) in AFFECTED_COMMANDS.iter() {
             let valid = *min..=*max;
             let min_args_to_test = if *min > 0 { min - 1 } else { *min };
-            let max_args_to_test = max + 1;
+            let max_args_to_test = if *max == usize::MAX { min + 3 } else { max + 1 };
 
             for arg_type in [
                 ArgsType::Inline {
@@ -9,8 +9,9 @@ use crate::context::Context;
 use crate::log::Logger;
 use crate::path::SearchResult;
 use crate::Log;
-use crate::{parser, Result};
+use crate::{parser, util, Result};
 use crate::{Action, Version};
+use camino::Utf8Path;
 use derive_more::From;
 use derive_new::new;
 
@@ -38,9 +39,17 @@ impl Linter {
     fn lint_root<L: Logger>(&self, ctx: &mut Context<L>, file: SearchResult) -> Result<Vec<Log>> {
         let mut problems = Vec::new();
         let mut lints = lints::lints_for(ctx.version().unwrap_or(Version::latest()));
-        parser::parse_file(ctx, file)?.lint(&mut lints, &mut problems);
+        parser::parse_document(ctx, self.input_dir(), file)?.lint(&mut lints, &mut problems);
         Ok(problems)
     }
+
+    /// The directory containing the input document.
+    fn input_dir(&self) -> &Utf8Path {
+        match &self.input {
+            ArgPath::Path(p) => util::parent_dir(p),
+            ArgPath::Stdio => Utf8Path::new("."),
+        }
+    }
 }
 
 pub type Lints = Vec<Box<dyn Lint>>;
@@ -0,0 +1,89 @@
+use crate::{context::Context, log::Logger, Action, Result};
+use derive_new::new;
+
+#[derive(new)]
+pub struct Lister {
+    what: Listing,
+}
+
+/// Something which may be listed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Listing {
+    /// The formats for which extensions have registered output drivers.
+    OutputFormats,
+
+    /// The extensions which have registered output drivers.
+    OutputExtensions,
+}
+
+impl Action for Lister {
+    type Response = Vec<String>;
+
+    fn run<L: Logger>(&self, ctx: &mut Context<L>) -> Result<Self::Response> {
+        let ret = self.list(ctx);
+        let logged = ctx.print_extension_logs();
+        let items = ret?;
+        logged?;
+        Ok(items)
+    }
+}
+
+impl Lister {
+    fn list<L: Logger>(&self, ctx: &mut Context<L>) -> Result<Vec<String>> {
+        let ext_state = ctx.extension_state()?;
+        ext_state.load_modules(ctx.lua_params().modules())?;
+        match self.what {
+            Listing::OutputFormats => ext_state.output_formats(),
+            Listing::OutputExtensions => ext_state.output_extensions(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::context::{Module, ModuleVersion};
+    use camino::Utf8PathBuf;
+    use std::{collections::HashMap, fs};
+
+    #[test]
+    fn output_formats() -> Result<()> {
+        let mut ctx = Context::test_new();
+        ctx.extension_state()?.run(
+            r#"
+                em.driver('xml', function() end)
+                em.driver('md', function() end)
+            "#,
+        )?;
+        assert_eq!(
+            Lister::new(Listing::OutputFormats).run(&mut ctx)?,
+            ["md", "xml"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_extensions() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        fs::write(dir.join("slides.lua"), "em.driver('odp', function() end)").unwrap();
+
+        let mut ctx = Context::test_new();
+        ctx.lua_params_mut().set_modules(vec![Module::new(
+            "slides".into(),
+            None,
+            ModuleVersion::Tag("v1.0".into()),
+            HashMap::new(),
+        )]);
+        ctx.extension_state()?
+            .lua()
+            .globals()
+            .get::<_, mlua::Table>("package")?
+            .set("path", format!("{dir}/?.lua"))?;
+        assert_eq!(
+            Lister::new(Listing::OutputExtensions).run(&mut ctx)?,
+            ["slides"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    ast::{
+        parsed::{Content, ParsedFile, Sugar},
+        Par, ParPart, ReprLoc,
+    },
+    context::{file_content::FileSlice, Context},
+    extensions::{Event, EventKind},
+    log::Logger,
+    parser::{self, Location},
+    path::SearchResult,
+    util, Error, ErrorContext, Result,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+
+static INCLUDE: &str = "include";
+
+/// Parses a document, splicing in the contents of files referenced by `.include` directives.
+pub(crate) struct Includer<'ctx, L: Logger> {
+    ctx: &'ctx Context<L>,
+    root: Utf8PathBuf,
+
+    /// The files currently being parsed, as (canonical path, path as found).
+    chain: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+}
+
+impl<'ctx, L: Logger> Includer<'ctx, L> {
+    pub(crate) fn new(ctx: &'ctx Context<L>, root: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            ctx,
+            root: root.into(),
+            chain: Vec::new(),
+        }
+    }
+
+    /// Parse the given file and everything it includes.
+    pub(crate) fn parse(&mut self, to_parse: SearchResult) -> Result<ParsedFile> {
+        let path = to_parse.path.clone();
+        let canonical = match path.as_str() {
+            "-" => path.clone(),
+            _ => path.canonicalize_utf8().map_err(|e| Error::io(&path, e))?,
+        };
+        if let Some(idx) = self.chain.iter().position(|(seen, _)| *seen == canonical) {
+            let mut cycle: Vec<_> = self.chain[idx..]
+                .iter()
+                .map(|(_, path)| path.clone())
+                .collect();
+            cycle.push(path);
+            return Err(Error::include_cycle(cycle));
+        }
+
+        let file = parser::parse_file(self.ctx, to_parse)?;
+
+        self.chain.push((canonical, path));
+        let pars = self.expand_pars(file.pars);
+        self.chain.pop();
+
+        Ok(pars?.into())
+    }
+
+    /// The directory relative to which the current file's includes are resolved.
+    fn src_dir(&self) -> &Utf8Path {
+        match self.chain.last() {
+            Some((_, path)) if path != "-" => util::parent_dir(path),
+            _ => &self.root,
+        }
+    }
+
+    fn expand_pars(
+        &mut self,
+        pars: Vec<Par<ParPart<Content>>>,
+    ) -> Result<Vec<Par<ParPart<Content>>>> {
+        let mut ret = Vec::with_capacity(pars.len());
+        for par in pars {
+            let sole_part = par.parts.iter().filter(|part| !part.is_empty()).count() == 1;
+
+            let mut parts = Vec::with_capacity(par.parts.len());
+            for part in par.parts {
+                match part {
+                    ParPart::Line(line) => match sole_include(&line) {
+                        Some(include) if sole_part => {
+                            ret.append(&mut self.include(include)?.pars);
+                        }
+                        Some(include) => {
+                            let included = self.include(include)?;
+                            parts.extend(included.pars.into_iter().flat_map(|par| par.parts));
+                        }
+                        None => {
+                            check_inline(&line)?;
+                            parts.push(ParPart::Line(line));
+                        }
+                    },
+                    ParPart::Command(command) => {
+                        parts.push(ParPart::Command(self.expand_trailers(command)?))
+                    }
+                }
+            }
+
+            if !parts.is_empty() {
+                ret.push(parts.into());
+            }
+        }
+        Ok(ret)
+    }
+
+    fn expand_trailers(&mut self, command: Content) -> Result<Content> {
+        match command {
+            Content::Command {
+                qualifier,
+                name,
+                pluses,
+                attrs,
+                inline_args,
+                remainder_arg,
+                trailer_args,
+                loc,
+                invocation_loc,
+            } => {
+                if qualifier.is_none() && name.to_str() == INCLUDE {
+                    return Err(Error::invalid_include(
+                        invocation_loc,
+                        "file name must be given inline",
+                    ));
+                }
+                for arg in inline_args.iter().chain(remainder_arg.iter()) {
+                    check_inline(arg)?;
+                }
+
+                Ok(Content::Command {
+                    qualifier,
+                    name,
+                    pluses,
+                    attrs,
+                    inline_args,
+                    remainder_arg,
+                    trailer_args: trailer_args
+                        .into_iter()
+                        .map(|arg| self.expand_pars(arg))
+                        .collect::<Result<_>>()?,
+                    loc,
+                    invocation_loc,
+                })
+            }
+            content => Ok(content),
+        }
+    }
+
+    fn include(&mut self, include: &Content) -> Result<ParsedFile> {
+        let Content::Command {
+            inline_args,
+            remainder_arg,
+            invocation_loc,
+            ..
+        } = include
+        else {
+            panic!("internal error: include is not a command");
+        };
+
+        let mut args = inline_args.iter().chain(remainder_arg.iter());
+        let (Some(arg), None) = (args.next(), args.next()) else {
+            return Err(Error::invalid_include(
+                invocation_loc.clone(),
+                "expected exactly one file name",
+            ));
+        };
+        let target = match raw_text(arg) {
+            Some(target) if !target.trim().is_empty() => target.trim().to_owned(),
+            _ => {
+                return Err(Error::invalid_include(
+                    invocation_loc.clone(),
+                    "file name is empty",
+                ))
+            }
+        };
+
+        let found = self
+            .ctx
+            .search_path()
+            .open(&self.root, self.src_dir(), &target)
+            .with_context(|| format!("{invocation_loc}: cannot include {target}"))?;
+        self.ctx
+            .extension_state()?
+            .handle(Event::Include {
+                file: found.path.clone(),
+                loc: invocation_loc.clone(),
+            })
+            .with_context(|| format!("failed to handle {} event", EventKind::Include))?;
+        self.parse(found)
+    }
+}
+
+/// Find the include directive which is the only meaningful content of a line.
+fn sole_include(line: &[Content]) -> Option<&Content> {
+    let mut meaningful = line.iter().filter(|content| {
+        !matches!(
+            content,
+            Content::Whitespace { .. } | Content::Comment { .. } | Content::MultiLineComment { .. }
+        )
+    });
+    match (meaningful.next(), meaningful.next()) {
+        (Some(content), None) if is_include(content) => Some(content),
+        _ => None,
+    }
+}
+
+/// Ensure that no include directive is nested within a line.
+fn check_inline(line: &[Content]) -> Result<()> {
+    for content in line {
+        match content {
+            Content::Command {
+                inline_args,
+                remainder_arg,
+                invocation_loc,
+                ..
+            } => {
+                if is_include(content) {
+                    return Err(Error::invalid_include(
+                        invocation_loc.clone(),
+                        "include must be the only content on its line",
+                    ));
+                }
+                for arg in inline_args.iter().chain(remainder_arg.iter()) {
+                    check_inline(arg)?;
+                }
+            }
+            Content::Sugar(
+                Sugar::Italic { arg, .. }
+                | Sugar::Bold { arg, .. }
+                | Sugar::Monospace { arg, .. }
+                | Sugar::Smallcaps { arg, .. }
+                | Sugar::AlternateFace { arg, .. }
+                | Sugar::Heading { arg, .. },
+            ) => check_inline(arg)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_include(content: &Content) -> bool {
+    matches!(content, Content::Command { qualifier: None, name, .. } if name.to_str() == INCLUDE)
+}
+
+/// The source text spanned by the given content.
+fn raw_text(content: &[Content]) -> Option<String> {
+    let loc: Location = match content {
+        [] => return None,
+        [sole] => sole.repr_loc(),
+        [first, .., last] => first.repr_loc().span_to(&last.repr_loc()),
+    };
+    let (start, end) = loc.indices_in(loc.src());
+    loc.src().to_str().get(start..end).map(ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ast::AstDebug, path::SearchPath};
+    use std::fs;
+
+    fn make_file(dir: &Utf8Path, path: &str, content: &str) -> Result<()> {
+        let path = dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        fs::write(&path, content).map_err(|e| Error::io(&path, e))
+    }
+
+    fn parse_in(dir: &Utf8Path, main: &str) -> Result<ParsedFile> {
+        let ctx = Context::test_new();
+        Includer::new(&ctx, dir).parse(dir.join(main).try_into()?)
+    }
+
+    fn tmpdir() -> (tempfile::TempDir, Utf8PathBuf) {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(tmpdir.path().canonicalize().unwrap()).unwrap();
+        (tmpdir, path)
+    }
+
+    #[test]
+    fn splices_pars() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "main.em", "hello\n\n.include{chapter2.em}\n\nworld\n")?;
+        make_file(&dir, "chapter2.em", "foo\n\nbar\n")?;
+
+        assert_eq!(
+            parse_in(&dir, "main.em")?.repr(),
+            "File[Par[[Word(hello)]]|Par[[Word(foo)]]|Par[[Word(bar)]]|Par[[Word(world)]]]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merges_into_surrounding_par() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "main.em", "hello\n.include: part\nworld\n")?;
+        make_file(&dir, "part.em", "foo\nbar\n")?;
+
+        assert_eq!(
+            parse_in(&dir, "main.em")?.repr(),
+            "File[Par[[Word(hello)]|[Word(foo)]|[Word(bar)]|[Word(world)]]]"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "main.em", ".include{chapters/one}\n")?;
+        make_file(&dir, "chapters/one/one.em", ".include{two}\n")?;
+        make_file(&dir, "chapters/one/two.em", "deep\n")?;
+        make_file(&dir, "chapters/two.em", "shallow\n")?;
+
+        assert_eq!(parse_in(&dir, "main.em")?.repr(), "File[Par[[Word(deep)]]]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_path() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "doc/main.em", ".include{shared}\n")?;
+        make_file(&dir, "lib/shared.em", "shared\n")?;
+
+        let root = dir.join("doc");
+        {
+            let ctx = Context::test_new();
+            let err = Includer::new(&ctx, &root)
+                .parse(root.join("main.em").try_into()?)
+                .unwrap_err();
+            assert!(
+                err.to_string().contains("could not find shared"),
+                "unexpected error: {err}"
+            );
+        }
+
+        {
+            let mut ctx = Context::test_new();
+            ctx.set_search_path(SearchPath::from(vec![dir.join("lib")]));
+            let file = Includer::new(&ctx, &root).parse(root.join("main.em").try_into()?)?;
+            assert_eq!(file.repr(), "File[Par[[Word(shared)]]]");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn root_escape() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "doc/main.em", ".include{chapters/../../secret}\n")?;
+        make_file(&dir, "doc/chapters/one.em", "one\n")?;
+        make_file(&dir, "secret.em", "secret\n")?;
+
+        let err = parse_in(&dir.join("doc"), "main.em").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("chapters/../../secret escapes the project root"),
+            "unexpected error: {err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cycle() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        make_file(&dir, "main.em", ".include{a}\n")?;
+        make_file(&dir, "a.em", ".include{b}\n")?;
+        make_file(&dir, "b.em", ".include{a}\n")?;
+
+        let err = parse_in(&dir, "main.em").unwrap_err();
+        let a = dir.join("a.em");
+        let b = dir.join("b.em");
+        assert!(
+            err.to_string()
+                .contains(&format!("include cycle detected: {a} -> {b} -> {a}")),
+            "unexpected error: {err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let (_tmpdir, dir) = tmpdir();
+        for (input, reason) in [
+            (
+                "foo .include{a}\n",
+                "include must be the only content on its line",
+            ),
+            (
+                ".bf{.include{a}}\n",
+                "include must be the only content on its line",
+            ),
+            (
+                "_.include{a}_\n",
+                "include must be the only content on its line",
+            ),
+            (".include\n", "expected exactly one file name"),
+            (".include{a}{b}\n", "expected exactly one file name"),
+            (".include{ }\n", "file name is empty"),
+            (".include:\n\ta\n", "file name must be given inline"),
+        ] {
+            make_file(&dir, "main.em", input)?;
+            make_file(&dir, "a.em", "a\n")?;
+            let err = parse_in(&dir, "main.em").unwrap_err();
+            assert!(
+                err.to_string().contains(reason),
+                "unexpected error for {input:?}: {err}"
+            );
+        }
+
+        Ok(())
+    }
+}
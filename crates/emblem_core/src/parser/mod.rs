@@ -1,4 +1,5 @@
 pub mod error;
+mod include;
 pub mod lexer;
 pub mod location;
 mod point;
@@ -12,6 +13,8 @@ use crate::log::Logger;
 use crate::path::SearchResult;
 use crate::{ast, Error, FileContent, FileName, Result};
 use ast::parsed::ParsedFile;
+use camino::Utf8Path;
+use include::Includer;
 use lalrpop_util::lalrpop_mod;
 use lexer::Lexer;
 use std::io::{BufReader, Read};
@@ -22,6 +25,17 @@ lalrpop_mod!(
     "/parser/parser.rs"
 );
 
+/// Parse an emblem document starting at the given root file, splicing in the contents of any
+/// included files. Included files are searched for within the `root` directory and then along the
+/// context's search path.
+pub fn parse_document<L: Logger>(
+    ctx: &Context<L>,
+    root: &Utf8Path,
+    to_parse: SearchResult,
+) -> Result<ParsedFile> {
+    Includer::new(ctx, root).parse(to_parse)
+}
+
 /// Parse an emblem source file at the given location.
 pub fn parse_file<L: Logger>(ctx: &Context<L>, to_parse: SearchResult) -> Result<ParsedFile> {
     let path = to_parse.path;
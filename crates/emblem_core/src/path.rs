@@ -1,9 +1,11 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 
 use crate::{args::ArgPath, Error, Result};
 use std::{
+    fmt::{self, Display, Formatter},
     fs,
     io::{self, Read},
+    iter,
 };
 
 #[cfg(test)]
@@ -12,90 +14,135 @@ use std::{
     io::{BufReader, Stdin},
 };
 
-// #[derive(Clone, Debug, Default, PartialEq, Eq)]
-// pub struct SearchPath {
-//     path: Vec<path::PathBuf>,
-// }
-
-// impl SearchPath {
-//     #[allow(dead_code)]
-//     pub fn open<S, T>(&self, src: S, target: T) -> Result<SearchResult, io::Error>
-//     where
-//         S: Into<path::PathBuf>,
-//         T: AsRef<path::Path>,
-//     {
-//         let target = target.as_ref();
-
-//         if target.is_absolute() {
-//             return Err(io::Error::new(
-//                 io::ErrorKind::InvalidInput,
-//                 format!("Absolute paths are forbidden: got {:?}", target,),
-//             ));
-//         }
-
-//         let src = src.into().canonicalize()?;
-
-//         let path = path::PathBuf::from(&src).join(target);
-//         if path.starts_with(&src) {
-//             if let Ok(file) = fs::File::open(&path) {
-//                 if let Ok(metadata) = file.metadata() {
-//                     if metadata.is_file() {
-//                         let file = InputFile::from(file);
-//                         return Ok(SearchResult { path, file });
-//                     }
-//                 }
-//             }
-//         }
-
-//         for dir in self.normalised().path {
-//             let path = {
-//                 let p = path::PathBuf::from(&dir).join(target);
-//                 match p.canonicalize() {
-//                     Ok(p) => p,
-//                     _ => continue,
-//                 }
-//             };
-
-//             if !path.starts_with(&dir) {
-//                 continue;
-//             }
-
-//             if let Ok(file) = fs::File::open(&path) {
-//                 if let Ok(metadata) = file.metadata() {
-//                     if metadata.is_file() {
-//                         let file = InputFile::from(file);
-//                         return Ok(SearchResult { path, file });
-//                     }
-//                 }
-//             }
-//         }
-
-//         Err(io::Error::new(
-//             io::ErrorKind::NotFound,
-//             format!(
-//                 "Could not find file {:?} along path \"{}\"",
-//                 target.as_os_str(),
-//                 self.to_string()
-//             ),
-//         ))
-//     }
-
-//     fn normalised(&self) -> Self {
-//         Self {
-//             path: self.path.iter().flat_map(|d| d.canonicalize()).collect(),
-//         }
-//     }
-// }
-
-// impl ToString for SearchPath {
-//     fn to_string(&self) -> String {
-//         self.path
-//             .iter()
-//             .map(|dir| dir.to_str().unwrap_or("?"))
-//             .collect::<Vec<_>>()
-//             .join(":")
-//     }
-// }
+/// An ordered list of directories to search for included files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchPath {
+    path: Vec<Utf8PathBuf>,
+}
+
+impl SearchPath {
+    pub fn dirs(&self) -> &[Utf8PathBuf] {
+        &self.path
+    }
+
+    /// Find and open `target`, as described by [`SearchPath::find`].
+    pub fn open(
+        &self,
+        root: &Utf8Path,
+        src: &Utf8Path,
+        target: impl AsRef<Utf8Path>,
+    ) -> Result<SearchResult> {
+        let path = self.find(root, src, target)?;
+        let file = fs::File::open(&path).map_err(|e| Error::io(&path, e))?;
+        Ok(SearchResult {
+            path,
+            file: InputFile::from(file),
+        })
+    }
+
+    /// Find `target`. The file is first searched for relative to `src`, the directory of the
+    /// file which references it, and must lie within the project `root`. After this, each
+    /// directory along this search path is tried in turn, and any file found must lie within
+    /// that directory. A target without an extension is taken to refer to an emblem file. If
+    /// `target` is found relative to `src` but outside of the project root, it is reported as
+    /// escaping the root rather than as missing.
+    pub fn find(
+        &self,
+        root: &Utf8Path,
+        src: &Utf8Path,
+        target: impl AsRef<Utf8Path>,
+    ) -> Result<Utf8PathBuf> {
+        let target = target.as_ref();
+        if target.is_absolute() {
+            return Err(Error::absolute_path(target));
+        }
+
+        let root = root.canonicalize_utf8().map_err(|e| Error::io(root, e))?;
+        let candidates = Self::candidates(target);
+        let search_dirs = iter::once((src, Some(root.clone()))).chain(
+            self.path
+                .iter()
+                .map(|dir| (dir.as_path(), dir.canonicalize_utf8().ok())),
+        );
+
+        let mut tried = Vec::new();
+        let mut escaped = false;
+        for (dir, limit) in search_dirs {
+            tried.push(dir.to_owned());
+            let Some(limit) = limit else {
+                continue;
+            };
+
+            for candidate in &candidates {
+                let path = dir.join(candidate);
+                let Ok(canonical) = path.canonicalize_utf8() else {
+                    continue;
+                };
+                if !canonical.is_file() {
+                    continue;
+                }
+                if canonical.starts_with(&limit) {
+                    return Ok(path);
+                }
+                escaped |= limit == root;
+            }
+        }
+
+        if escaped {
+            return Err(Error::escaping_path(target));
+        }
+        Err(Error::not_found_along_path(target, tried))
+    }
+
+    /// The paths which `target` may refer to, in order of preference. A reference to `file` or
+    /// `file.em` may refer to either `file.em` or `file/file.em`.
+    fn candidates(target: &Utf8Path) -> Vec<Utf8PathBuf> {
+        match target.extension() {
+            None | Some("em") => {
+                let stem = target.with_extension("");
+                let Some(name) = stem.file_name() else {
+                    return vec![target.to_owned()];
+                };
+                vec![
+                    stem.with_extension("em"),
+                    stem.join(name).with_extension("em"),
+                ]
+            }
+            Some(_) => vec![target.to_owned()],
+        }
+    }
+}
+
+impl From<&str> for SearchPath {
+    fn from(path: &str) -> Self {
+        Self {
+            path: path
+                .split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(Utf8PathBuf::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<String> for SearchPath {
+    fn from(path: String) -> Self {
+        Self::from(&path[..])
+    }
+}
+
+impl From<Vec<Utf8PathBuf>> for SearchPath {
+    fn from(path: Vec<Utf8PathBuf>) -> Self {
+        Self { path }
+    }
+}
+
+impl Display for SearchPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let dirs: Vec<_> = self.path.iter().map(|path| path.as_str()).collect();
+        write!(f, "{}", dirs.join(":"))
+    }
+}
 
 #[derive(Debug)]
 pub struct SearchResult {
@@ -197,198 +244,149 @@ impl InputFile {
 mod test {
     use super::*;
 
-    // mod search_path {
-    //     use super::*;
-    //     use std::io;
-
-    //     #[test]
-    //     fn search_path_from() {
-    //         assert_eq!(
-    //             SearchPath::from("foo:bar::baz"),
-    //             SearchPath {
-    //                 path: vec!["foo", "bar", "baz"].iter().map(|d| d.into()).collect()
-    //             }
-    //         );
-
-    //         assert_eq!(
-    //             SearchPath::from("foo:bar::baz".to_owned()),
-    //             SearchPath {
-    //                 path: vec!["foo", "bar", "baz"].iter().map(|d| d.into()).collect()
-    //             }
-    //         );
-
-    //         assert_eq!(
-    //             SearchPath::from(
-    //                 vec!["foo", "bar", "baz"]
-    //                     .iter()
-    //                     .map(path::PathBuf::from)
-    //                     .collect::<Vec<_>>()
-    //             ),
-    //             SearchPath {
-    //                 path: vec!["foo", "bar", "baz"].iter().map(|d| d.into()).collect()
-    //             }
-    //         );
-    //     }
-
-    //     #[test]
-    //     fn to_string() {
-    //         let path = SearchPath::from("asdf:fdsa: ::q");
-    //         assert_eq!(path.to_string(), "asdf:fdsa: :q");
-    //     }
-
-    //     fn make_file(tmppath: &path::Path, filepath: &str, content: &str) -> Result<(), io::Error> {
-    //         let path = path::PathBuf::from(tmppath).join(filepath);
-
-    //         if let Some(parent) = path.parent() {
-    //             fs::create_dir_all(parent)?;
-    //         }
-
-    //         fs::write(path, content)
-    //     }
-
-    //     #[test]
-    //     fn open() -> Result<(), io::Error> {
-    //         let tmpdir = tempfile::tempdir()?;
-    //         let tmppath = tmpdir.path().canonicalize()?;
-
-    //         make_file(&tmppath, "a.txt", "a")?;
-    //         make_file(&tmppath, "B/b.txt", "b")?;
-    //         make_file(&tmppath, "C1/C2/c.txt", "c")?;
-    //         make_file(&tmppath, "D/d.txt", "c")?;
-    //         make_file(&tmppath, "x.txt", "x")?;
-
-    //         let raw_path: Vec<path::PathBuf> = vec!["B", "C1", "D"]
-    //             .iter()
-    //             .map(|s| path::PathBuf::from(&tmppath).join(s))
-    //             .collect();
-    //         let path = SearchPath::from(raw_path).normalised();
-
-    //         {
-    //             let a = path.open(&tmppath, "a.txt");
-    //             assert!(a.is_ok(), "{:?}", a);
-    //             let mut content = String::new();
-    //             let mut found = a.unwrap();
-    //             assert_eq!(found.path, tmppath.join("a.txt"));
-    //             found.file().read_to_string(&mut content)?;
-    //             assert_eq!(content, "a");
-    //         }
-
-    //         {
-    //             let b = path.open(&tmppath, "b.txt");
-    //             assert!(b.is_ok(), "{:?}", b);
-    //             let mut found = b.unwrap();
-    //             assert_eq!(found.path, tmppath.join("B/b.txt"));
-    //             let mut content = String::new();
-    //             found.file().read_to_string(&mut content)?;
-    //             assert_eq!(content, "b");
-    //         }
-
-    //         {
-    //             let c = path.open(&tmppath, "C2/c.txt");
-    //             assert!(c.is_ok());
-    //             let mut found = c.unwrap();
-    //             assert_eq!(found.path, tmppath.join("C1/C2/c.txt"));
-    //             let mut content = String::new();
-    //             found.file().read_to_string(&mut content)?;
-    //             assert_eq!(content, "c");
-    //         }
-
-    //         {
-    //             let c = path.open(&tmppath, "D/d.txt");
-    //             assert!(c.is_ok());
-    //             let mut found = c.unwrap();
-    //             assert_eq!(found.path, tmppath.join("D/d.txt"));
-    //             let mut content = String::new();
-    //             found.file().read_to_string(&mut content)?;
-    //             assert_eq!(content, "c");
-    //         }
-
-    //         {
-    //             let abs_path = tmppath.join("a.txt");
-    //             let abs_result =
-    //                 path.open(&tmppath, path::PathBuf::from(&abs_path).canonicalize()?);
-    //             assert!(abs_result.is_err());
-    //             let err = abs_result.unwrap_err();
-    //             assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
-    //             assert_eq!(
-    //                 err.to_string(),
-    //                 format!("Absolute paths are forbidden: got {:?}", abs_path,)
-    //             );
-    //         }
-
-    //         {
-    //             let dir_result = path.open(&tmppath, "D");
-    //             assert!(dir_result.is_err());
-    //             let err = dir_result.unwrap_err();
-    //             assert_eq!(err.kind(), io::ErrorKind::NotFound);
-    //             assert_eq!(
-    //                 err.to_string(),
-    //                 format!(
-    //                     "Could not find file \"D\" along path \"{}\"",
-    //                     path.to_string()
-    //                 )
-    //             );
-    //         }
-
-    //         {
-    //             let dir_result = path.open(&tmppath, "C2");
-    //             assert!(dir_result.is_err());
-    //             let err = dir_result.unwrap_err();
-    //             assert_eq!(err.kind(), io::ErrorKind::NotFound);
-    //             assert_eq!(
-    //                 err.to_string(),
-    //                 format!(
-    //                     "Could not find file \"C2\" along path \"{}\"",
-    //                     path.to_string()
-    //                 )
-    //             );
-    //         }
-
-    //         {
-    //             let inaccessible = path.open(&tmppath, "c.txt");
-    //             assert!(inaccessible.is_err());
-    //             let err = inaccessible.unwrap_err();
-    //             assert_eq!(err.kind(), io::ErrorKind::NotFound);
-    //             assert_eq!(
-    //                 err.to_string(),
-    //                 format!(
-    //                     "Could not find file \"c.txt\" along path \"{}\"",
-    //                     path.to_string()
-    //                 )
-    //             );
-    //         }
-
-    //         {
-    //             let inaccessible = path.open(&tmppath, "../a.txt");
-    //             assert!(inaccessible.is_err());
-    //             let abs_file = inaccessible.unwrap_err();
-    //             assert_eq!(abs_file.kind(), io::ErrorKind::NotFound);
-    //             assert_eq!(
-    //                 abs_file.to_string(),
-    //                 format!(
-    //                     "Could not find file \"../a.txt\" along path \"{}\"",
-    //                     path.to_string()
-    //                 )
-    //             );
-    //         }
-
-    //         {
-    //             let non_existent = path.open(&tmppath, "non-existent.txt");
-    //             assert!(non_existent.is_err());
-    //             let non_existent = non_existent.unwrap_err();
-    //             assert_eq!(non_existent.kind(), io::ErrorKind::NotFound);
-    //             assert_eq!(
-    //                 non_existent.to_string(),
-    //                 format!(
-    //                     "Could not find file \"non-existent.txt\" along path \"{}\"",
-    //                     path.to_string()
-    //                 )
-    //             );
-    //         }
-
-    //         Ok(())
-    //     }
-    // }
+    mod search_path {
+        use super::*;
+
+        #[test]
+        fn search_path_from() {
+            let expected = SearchPath {
+                path: ["foo", "bar", "baz"].iter().map(|d| d.into()).collect(),
+            };
+            assert_eq!(SearchPath::from("foo:bar::baz"), expected);
+            assert_eq!(SearchPath::from("foo:bar::baz".to_owned()), expected);
+            assert_eq!(
+                SearchPath::from(
+                    ["foo", "bar", "baz"]
+                        .iter()
+                        .map(Utf8PathBuf::from)
+                        .collect::<Vec<_>>()
+                ),
+                expected
+            );
+        }
+
+        #[test]
+        fn to_string() {
+            let path = SearchPath::from("asdf:fdsa: ::q");
+            assert_eq!(path.to_string(), "asdf:fdsa: :q");
+        }
+
+        fn make_file(tmppath: &Utf8Path, filepath: &str, content: &str) -> Result<()> {
+            let path = tmppath.join(filepath);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+            }
+            fs::write(&path, content).map_err(|e| Error::io(&path, e))
+        }
+
+        fn read(found: SearchResult) -> Result<String> {
+            let mut content = String::new();
+            found
+                .file
+                .file()
+                .unwrap()
+                .read_to_string(&mut content)
+                .map_err(|e| Error::io(&found.path, e))?;
+            Ok(content)
+        }
+
+        #[test]
+        fn open() -> Result<()> {
+            let tmpdir = tempfile::tempdir().unwrap();
+            let tmppath = Utf8PathBuf::try_from(tmpdir.path().canonicalize().unwrap()).unwrap();
+            let root = tmppath.join("root");
+
+            make_file(&root, "a.em", "a")?;
+            make_file(&root, "sub/s.em", "s")?;
+            make_file(&root, "chapter/chapter.em", "chapter")?;
+            make_file(&root, "image.png", "png")?;
+            make_file(&tmppath, "B/b.em", "b")?;
+            make_file(&tmppath, "C1/C2/c.em", "c")?;
+            make_file(&tmppath, "x.em", "x")?;
+
+            let path = SearchPath::from(vec![tmppath.join("B"), tmppath.join("C1")]);
+            let sub = root.join("sub");
+
+            assert_eq!(read(path.open(&root, &root, "a.em")?)?, "a");
+            assert_eq!(read(path.open(&root, &root, "a")?)?, "a");
+            assert_eq!(read(path.open(&root, &root, "chapter")?)?, "chapter");
+            assert_eq!(read(path.open(&root, &root, "image.png")?)?, "png");
+            assert_eq!(read(path.open(&root, &sub, "s")?)?, "s");
+            assert_eq!(read(path.open(&root, &sub, "../a")?)?, "a");
+            assert_eq!(read(path.open(&root, &root, "b")?)?, "b");
+            assert_eq!(read(path.open(&root, &root, "C2/c.em")?)?, "c");
+
+            {
+                let abs = root.join("a.em");
+                let err = path.open(&root, &root, &abs).unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    format!("absolute paths are forbidden: got {abs}")
+                );
+            }
+
+            let err = path.open(&root, &root, "../x.em").unwrap_err();
+            assert_eq!(err.to_string(), "../x.em escapes the project root");
+
+            for inaccessible in ["c.em", "C1", "sub", "non-existent"] {
+                let err = path.open(&root, &root, inaccessible).unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    format!(
+                        "could not find {inaccessible}, looked in {root}, {}, {}",
+                        tmppath.join("B"),
+                        tmppath.join("C1")
+                    ),
+                );
+            }
+
+            Ok(())
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn symlinks() -> Result<()> {
+            use std::os::unix::fs::symlink;
+
+            let tmpdir = tempfile::tempdir().unwrap();
+            let tmppath = Utf8PathBuf::try_from(tmpdir.path().canonicalize().unwrap()).unwrap();
+            let root = tmppath.join("root");
+            make_file(&root, "a.em", "a")?;
+            make_file(&tmppath, "secret.em", "secret")?;
+            symlink(root.join("a.em"), root.join("b.em")).unwrap();
+            symlink(tmppath.join("secret.em"), root.join("c.em")).unwrap();
+
+            let path = SearchPath::default();
+            assert_eq!(read(path.open(&root, &root, "b")?)?, "a");
+
+            let err = path.open(&root, &root, "c").unwrap_err();
+            assert_eq!(err.to_string(), "c escapes the project root");
+
+            Ok(())
+        }
+
+        #[test]
+        fn missing_dirs() -> Result<()> {
+            let tmpdir = tempfile::tempdir().unwrap();
+            let tmppath = Utf8PathBuf::try_from(tmpdir.path().canonicalize().unwrap()).unwrap();
+            make_file(&tmppath, "shared/a.em", "a")?;
+
+            let path = SearchPath::from(vec![tmppath.join("missing"), tmppath.join("shared")]);
+            assert_eq!(read(path.open(&tmppath, &tmppath, "a")?)?, "a");
+
+            let err = path.open(&tmppath, &tmppath, "b").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "could not find b, looked in {tmppath}, {}, {}",
+                    tmppath.join("missing"),
+                    tmppath.join("shared")
+                )
+            );
+
+            Ok(())
+        }
+    }
 
     mod search_result {
         use super::*;
@@ -1,8 +1,28 @@
+use derive_new::new;
 #[cfg(feature = "git2")]
 use git2::{ErrorCode, Repository, Status, StatusOptions};
 use std::error::Error;
 use std::path::Path;
 
+/// Reproducibility information about the repository containing a document.
+#[derive(Clone, Debug, Default, Eq, PartialEq, new)]
+pub struct RepoInfo {
+    commit: Option<String>,
+    dirty: bool,
+}
+
+impl RepoInfo {
+    /// The hash of the commit at `HEAD`, if one exists.
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
+
+    /// Whether the working tree contains uncommitted changes.
+    pub fn dirty(&self) -> bool {
+        self.dirty
+    }
+}
+
 #[cfg(not(feature = "git2"))]
 #[allow(dead_code)]
 pub fn is_dirty(_dir: &Path) -> Result<bool, Box<dyn Error>> {
@@ -11,14 +31,35 @@ pub fn is_dirty(_dir: &Path) -> Result<bool, Box<dyn Error>> {
     );
 }
 
+#[cfg(not(feature = "git2"))]
+#[allow(dead_code)]
+pub fn info(_dir: &Path) -> Result<Option<RepoInfo>, Box<dyn Error>> {
+    panic!("internal error: emblem_core::repo::info cannot be called without the 'git2' feature");
+}
+
+#[cfg(not(feature = "git2"))]
+#[allow(dead_code)]
+pub fn head_commit(_dir: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    panic!(
+        "internal error: emblem_core::repo::head_commit cannot be called without the 'git2' feature"
+    );
+}
+
+/// Gather reproducibility information about the repository containing the given path, if any.
+#[cfg(feature = "git2")]
+pub fn info(dir: &Path) -> Result<Option<RepoInfo>, Box<dyn Error>> {
+    if discover(dir)?.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(RepoInfo::new(head_commit(dir)?, is_dirty(dir)?)))
+}
+
 /// Check whether there is a dirty repository at the given path.
 #[cfg(feature = "git2")]
-#[allow(dead_code)]
 pub fn is_dirty(dir: &Path) -> Result<bool, Box<dyn Error>> {
-    let repo = match Repository::open(dir) {
-        Ok(r) => r,
-        Err(e) if e.code() == ErrorCode::NotFound => return Ok(false),
-        Err(e) => return Err(Box::new(e)),
+    let Some(repo) = discover(dir)? else {
+        return Ok(false);
     };
 
     let mut opts = StatusOptions::new();
@@ -45,13 +86,41 @@ pub fn is_dirty(dir: &Path) -> Result<bool, Box<dyn Error>> {
     Ok(false)
 }
 
+/// Find the hash of the commit currently checked out in the repository at the given path.
+#[cfg(feature = "git2")]
+pub fn head_commit(dir: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(repo) = discover(dir)? else {
+        return Ok(None);
+    };
+
+    let head = match repo.head() {
+        Ok(h) => h,
+        Err(e) if [ErrorCode::UnbornBranch, ErrorCode::NotFound].contains(&e.code()) => {
+            return Ok(None)
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let commit = head.peel_to_commit()?;
+    Ok(Some(commit.id().to_string()))
+}
+
+#[cfg(feature = "git2")]
+fn discover(dir: &Path) -> Result<Option<Repository>, Box<dyn Error>> {
+    match Repository::discover(dir) {
+        Ok(r) => Ok(Some(r)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
 #[cfg(feature = "git2")]
 #[cfg(test)]
 mod test {
     use super::*;
     use std::{fs::File, io::Write};
 
-    use git2::{Repository, RepositoryInitOptions};
+    use git2::{Repository, RepositoryInitOptions, Signature};
 
     #[test]
     fn dirt_detection() -> Result<(), Box<dyn Error>> {
@@ -72,4 +141,45 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn head_commit_tracking() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+
+        assert_eq!(None, head_commit(dir.path())?);
+        assert_eq!(None, info(dir.path())?);
+
+        let repo = Repository::init_opts(dir.path(), RepositoryInitOptions::new().mkdir(true))?;
+
+        assert_eq!(None, head_commit(dir.path())?);
+        assert_eq!(Some(RepoInfo::new(None, false)), info(dir.path())?);
+
+        {
+            let mut file = File::create(dir.path().join("main.em"))?;
+            file.write_all(b"hello, world")?;
+        }
+        assert!(is_dirty(dir.path())?);
+
+        let commit = {
+            let mut index = repo.index()?;
+            index.add_path(Path::new("main.em"))?;
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let sig = Signature::now("kcza", "kcza@example.com")?;
+            repo.commit(Some("HEAD"), &sig, &sig, "initial commit", &tree, &[])?
+        };
+
+        assert_eq!(Some(commit.to_string()), head_commit(dir.path())?);
+        assert!(!is_dirty(dir.path())?);
+        assert_eq!(
+            Some(RepoInfo::new(Some(commit.to_string()), false)),
+            info(dir.path())?
+        );
+
+        let subdir = dir.path().join("chapters");
+        std::fs::create_dir(&subdir)?;
+        assert_eq!(Some(commit.to_string()), head_commit(&subdir)?);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,170 @@
+use crate::{
+    args::ArgPath, context::Context, extensions::SpecOutcome, log::Logger, util, Action, Error,
+    Log, Result,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use derive_new::new;
+use std::fs;
+
+/// The extensions of module files which may contain `$spec` blocks.
+const SPEC_EXTENSIONS: [&str; 2] = ["yue", "moon"];
+
+#[derive(new)]
+pub struct Tester {
+    input: ArgPath,
+
+    filter: Option<String>,
+}
+
+impl Action for Tester {
+    type Response = Vec<SpecOutcome>;
+
+    fn run<L: Logger>(&self, ctx: &mut Context<L>) -> Result<Self::Response> {
+        let ret = self.test(ctx);
+        let logged = ctx.print_extension_logs();
+        let outcomes = ret?;
+        logged?;
+
+        let failed = outcomes
+            .iter()
+            .filter(|outcome| outcome.failure().is_some())
+            .count();
+        if failed > 0 {
+            return Err(Error::specs_failed(failed, outcomes.len()));
+        }
+        Ok(outcomes)
+    }
+}
+
+impl Tester {
+    fn test<L: Logger>(&self, ctx: &mut Context<L>) -> Result<Vec<SpecOutcome>> {
+        let root = self.input_dir();
+        let files = self.spec_files(ctx, &root)?;
+
+        let ext_state = ctx.extension_state()?;
+        ext_state.set_fs_roots(root, None);
+
+        let mut outcomes = Vec::new();
+        for file in files {
+            for outcome in ext_state.run_specs(&file, self.filter.as_deref())? {
+                ctx.print(Self::log(&outcome))?;
+                outcomes.push(outcome);
+            }
+        }
+        Ok(outcomes)
+    }
+
+    fn log(outcome: &SpecOutcome) -> Log {
+        let log = match outcome.failure() {
+            Some(failure) => Log::error(format!("{} failed: {failure}", outcome.name())),
+            None => Log::info(format!("{} passed", outcome.name())),
+        };
+        match outcome.loc() {
+            Some(loc) => log.add_info(format!("declared at {loc}")),
+            None => log,
+        }
+    }
+
+    /// The directory containing the input document.
+    fn input_dir(&self) -> Utf8PathBuf {
+        match &self.input {
+            ArgPath::Path(p) => util::parent_dir(p).to_owned(),
+            ArgPath::Stdio => ".".into(),
+        }
+    }
+
+    /// Find the document's local modules which contain specs, searching the directory of the
+    /// input document and those on the search path.
+    fn spec_files<L: Logger>(&self, ctx: &Context<L>, root: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+        let mut files = Vec::new();
+        for dir in [root]
+            .into_iter()
+            .chain(ctx.search_path().dirs().iter().map(AsRef::as_ref))
+        {
+            Self::find_spec_files(dir, &mut files)?;
+        }
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    fn find_spec_files(dir: &Utf8Path, files: &mut Vec<Utf8PathBuf>) -> Result<()> {
+        let Ok(entries) = dir.read_dir_utf8() else {
+            return Ok(());
+        };
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::io(dir, e))?;
+            let path = entry.path();
+            if path.file_name().is_some_and(|name| name.starts_with('.')) {
+                continue;
+            }
+
+            let file_type = entry.file_type().map_err(|e| Error::io(path, e))?;
+            if file_type.is_dir() {
+                Self::find_spec_files(path, files)?;
+            } else if path
+                .extension()
+                .is_some_and(|ext| SPEC_EXTENSIONS.contains(&ext))
+            {
+                let src = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+                if src.contains("$spec") {
+                    files.push(path.to_owned());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SearchPath;
+
+    #[test]
+    fn spec_files() -> Result<()> {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let root = Utf8PathBuf::try_from(tmpdir.path().to_owned()).unwrap();
+        for dir in ["doc/mods", "doc/.git", "lib"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for (file, content) in [
+            ("doc/main.em", "$spec"),
+            ("doc/figures.yue", "$spec ->\n\tdescribe 'figures', ->\n"),
+            ("doc/tables.yue", "{}\n"),
+            ("doc/mods/cites.moon", "$spec ->\n"),
+            ("doc/mods/notes.lua", "-- $spec\n"),
+            ("doc/.git/hook.yue", "$spec ->\n"),
+            ("lib/shared.yue", "$spec ->\n"),
+        ] {
+            fs::write(root.join(file), content).unwrap();
+        }
+
+        let mut ctx = Context::test_new();
+        ctx.set_search_path(SearchPath::from(vec![
+            root.join("lib"),
+            root.join("missing"),
+        ]));
+        let tester = Tester::new(ArgPath::Path(root.join("doc/main.em")), None);
+        assert_eq!(tester.input_dir(), root.join("doc"));
+        assert_eq!(
+            tester.spec_files(&ctx, &root.join("doc"))?,
+            [
+                root.join("doc/figures.yue"),
+                root.join("doc/mods/cites.moon"),
+                root.join("lib/shared.yue"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn input_dir() {
+        assert_eq!(
+            Tester::new(ArgPath::Path("main.em".into()), None).input_dir(),
+            "."
+        );
+        assert_eq!(Tester::new(ArgPath::Stdio, None).input_dir(), ".");
+    }
+}
@@ -1,3 +1,13 @@
+use camino::Utf8Path;
+
+/// The directory containing `path`, which is `.` for bare file names.
+pub(crate) fn parent_dir(path: &Utf8Path) -> &Utf8Path {
+    match path.parent() {
+        Some(parent) if !parent.as_str().is_empty() => parent,
+        _ => Utf8Path::new("."),
+    }
+}
+
 pub fn plural<T>(n: usize, singular: T, plural: T) -> T {
     match n {
         1 => singular,
@@ -7,6 +17,18 @@ pub fn plural<T>(n: usize, singular: T, plural: T) -> T {
 
 #[cfg(test)]
 mod test {
+    use camino::Utf8Path;
+
+    #[test]
+    fn parent_dir() {
+        assert_eq!(super::parent_dir(Utf8Path::new("main.em")), ".");
+        assert_eq!(super::parent_dir(Utf8Path::new("./main.em")), ".");
+        assert_eq!(
+            super::parent_dir(Utf8Path::new("docs/manual/main.em")),
+            "docs/manual"
+        );
+    }
+
     #[test]
     fn plural() {
         assert_eq!("a", super::plural(1, "a", "b"));
@@ -1,4 +1,4 @@
-use mlua::{lua_State, Lua, Value};
+use mlua::{lua_State, Lua, Table, Value};
 use std::{
     env,
     error::Error,
@@ -27,6 +27,26 @@ extern "C" {
     fn luaopen_system_core(state: *mut lua_State) -> std::os::raw::c_int;
 }
 
+/// Open the YueScript compiler within `lua`, returning its module table. The compiler is not left
+/// registered as a loaded module, so code running in `lua` cannot require it for itself.
+pub fn open(lua: &Lua) -> mlua::Result<Value<'_>> {
+    let loaded: Option<Table> = match lua.globals().get::<_, Option<Table>>("package")? {
+        Some(package) => package.get("loaded")?,
+        None => None,
+    };
+    let previous: Option<Value> = match &loaded {
+        Some(loaded) => Some(loaded.raw_get("yue")?),
+        None => None,
+    };
+
+    let yue: Value = unsafe { lua.create_c_function(luaopen_yue)? }.call(())?;
+
+    if let (Some(loaded), Some(previous)) = (loaded, previous) {
+        loaded.raw_set("yue", previous)?;
+    }
+    Ok(yue)
+}
+
 pub struct Compiler {
     in_dir_path: PathBuf,
     out_path: PathBuf,